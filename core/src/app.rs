@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use axum::body::Body;
 use http::request::Parts;
 use mockall::automock;
 
@@ -7,10 +8,17 @@ use crate::{
     config::Config,
     guards::Guards,
     plumbing::ContentService,
-    s3::{FileObject, S3, S3Error},
+    range::ByteRange,
+    s3::{
+        FileObject, ListOptions, ObjectList, ObjectMetadata, ObjectStream, S3, S3Error,
+        UploadedPart,
+    },
     signed_url::{
         extractor::Claims,
-        service::{AvailableActions, HMACUrlService, SignedUrlError, SignedUrlService},
+        service::{
+            AvailableActions, BoundParams, HMACUrlService, IssuedUrl, SignedUrlError,
+            SignedUrlService,
+        },
     },
 };
 
@@ -23,9 +31,64 @@ pub trait AppStateOperations {
         &self,
         prefix: String,
         action: AvailableActions,
-        expires_in_ms: u64,
+        expires_in_secs: u64,
+        params: BoundParams,
+    ) -> Result<IssuedUrl, SignedUrlError>;
+    fn sign_url_until(
+        &self,
+        prefix: String,
+        action: AvailableActions,
+        expires: u64,
+        params: BoundParams,
     ) -> Result<String, SignedUrlError>;
-    async fn get_object(&self, bucket: &str, key: &str) -> Result<(Vec<u8>, String), S3Error>;
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, S3Error>;
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, S3Error>;
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), S3Error>;
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        key: &str,
+    ) -> Result<String, S3Error>;
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        options: ListOptions,
+    ) -> Result<ObjectList, S3Error>;
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, S3Error>;
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u16,
+        data: Body,
+        content_length: u64,
+    ) -> Result<String, S3Error>;
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<String, S3Error>;
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), S3Error>;
     fn verify_parts(&self, parts: Parts) -> Result<Claims, SignedUrlError>;
     fn guards(&self) -> Arc<Guards>;
 }
@@ -71,17 +134,112 @@ impl AppStateOperations for AppState {
         &self,
         prefix: String,
         action: AvailableActions,
-        expires_in_ms: u64,
+        expires_in_secs: u64,
+        params: BoundParams,
+    ) -> Result<IssuedUrl, SignedUrlError> {
+        self.signer
+            .sign_url(prefix, action, expires_in_secs, params)
+    }
+
+    fn sign_url_until(
+        &self,
+        prefix: String,
+        action: AvailableActions,
+        expires: u64,
+        params: BoundParams,
     ) -> Result<String, SignedUrlError> {
-        self.signer.sign_url(prefix, action, expires_in_ms)
+        self.signer.sign_url_until(prefix, action, expires, params)
     }
 
     fn verify_parts(&self, parts: Parts) -> Result<Claims, SignedUrlError> {
         self.signer.verify_parts(parts)
     }
 
-    async fn get_object(&self, bucket: &str, key: &str) -> Result<(Vec<u8>, String), S3Error> {
-        self.service.s3.get_object(bucket, key).await
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, S3Error> {
+        self.service.s3.get_object(bucket, key, range).await
+    }
+
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, S3Error> {
+        self.service.s3.head_object(bucket, key).await
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), S3Error> {
+        self.service.s3.delete_object(bucket, key).await
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        key: &str,
+    ) -> Result<String, S3Error> {
+        self.service.s3.copy_object(bucket, source_key, key).await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        options: ListOptions,
+    ) -> Result<ObjectList, S3Error> {
+        self.service.s3.list_objects(bucket, prefix, options).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, S3Error> {
+        self.service
+            .s3
+            .create_multipart_upload(bucket, key, content_type)
+            .await
+    }
+
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u16,
+        data: Body,
+        content_length: u64,
+    ) -> Result<String, S3Error> {
+        self.service
+            .s3
+            .upload_part(bucket, key, upload_id, part_number, data, content_length)
+            .await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<String, S3Error> {
+        self.service
+            .s3
+            .complete_multipart_upload(bucket, key, upload_id, parts)
+            .await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), S3Error> {
+        self.service
+            .s3
+            .abort_multipart_upload(bucket, key, upload_id)
+            .await
     }
 
     fn guards(&self) -> Arc<Guards> {
@@ -128,17 +286,105 @@ pub mod tests {
             &self,
             prefix: String,
             action: AvailableActions,
-            expires_in_ms: u64,
+            expires_in_secs: u64,
+            params: BoundParams,
+        ) -> Result<IssuedUrl, SignedUrlError> {
+            self.0.sign_url(prefix, action, expires_in_secs, params)
+        }
+
+        fn sign_url_until(
+            &self,
+            prefix: String,
+            action: AvailableActions,
+            expires: u64,
+            params: BoundParams,
         ) -> Result<String, SignedUrlError> {
-            self.0.sign_url(prefix, action, expires_in_ms)
+            self.0.sign_url_until(prefix, action, expires, params)
         }
 
         fn verify_parts(&self, parts: Parts) -> Result<Claims, SignedUrlError> {
             self.0.verify_parts(parts)
         }
 
-        async fn get_object(&self, bucket: &str, key: &str) -> Result<(Vec<u8>, String), S3Error> {
-            self.0.get_object(bucket, key).await
+        async fn get_object(
+            &self,
+            bucket: &str,
+            key: &str,
+            range: Option<ByteRange>,
+        ) -> Result<ObjectStream, S3Error> {
+            self.0.get_object(bucket, key, range).await
+        }
+
+        async fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, S3Error> {
+            self.0.head_object(bucket, key).await
+        }
+
+        async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), S3Error> {
+            self.0.delete_object(bucket, key).await
+        }
+
+        async fn copy_object(
+            &self,
+            bucket: &str,
+            source_key: &str,
+            key: &str,
+        ) -> Result<String, S3Error> {
+            self.0.copy_object(bucket, source_key, key).await
+        }
+
+        async fn list_objects(
+            &self,
+            bucket: &str,
+            prefix: &str,
+            options: ListOptions,
+        ) -> Result<ObjectList, S3Error> {
+            self.0.list_objects(bucket, prefix, options).await
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            content_type: &str,
+        ) -> Result<String, S3Error> {
+            self.0
+                .create_multipart_upload(bucket, key, content_type)
+                .await
+        }
+
+        async fn upload_part(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: u16,
+            data: Body,
+            content_length: u64,
+        ) -> Result<String, S3Error> {
+            self.0
+                .upload_part(bucket, key, upload_id, part_number, data, content_length)
+                .await
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<UploadedPart>,
+        ) -> Result<String, S3Error> {
+            self.0
+                .complete_multipart_upload(bucket, key, upload_id, parts)
+                .await
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+        ) -> Result<(), S3Error> {
+            self.0.abort_multipart_upload(bucket, key, upload_id).await
         }
 
         fn guards(&self) -> Arc<Guards> {
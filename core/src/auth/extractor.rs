@@ -0,0 +1,88 @@
+use axum::extract::FromRequestParts;
+use http::header::AUTHORIZATION;
+
+use crate::{
+    app::AppStateOperations,
+    auth::{AuthError, Caller},
+};
+
+/// The microservice which sent the request, authenticated by its bearer token
+#[derive(Debug, Clone)]
+pub struct AuthenticatedCaller(pub Caller);
+
+impl<S> FromRequestParts<S> for AuthenticatedCaller
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or(AuthError::MissingToken)?;
+
+        let config = state.config();
+        let caller = config
+            .callers
+            .authenticate(token.trim())
+            .ok_or(AuthError::InvalidToken)?;
+        Ok(Self(caller.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, routing::get};
+    use axum_test::TestServer;
+    use http::StatusCode;
+
+    use super::*;
+    use crate::auth::tests::{TEST_TOKEN, config_with_caller};
+
+    async fn fake_handler(AuthenticatedCaller(caller): AuthenticatedCaller) -> String {
+        caller.name
+    }
+
+    fn fake_server() -> TestServer {
+        let mut operations = crate::app::MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| config_with_caller("message_attachment"));
+        let app_state = crate::app::tests::TestAppState::new(operations);
+        let router: Router = Router::new()
+            .route("/", get(fake_handler))
+            .with_state(app_state);
+
+        TestServer::new(router).expect("Test server creation failed")
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_caller() {
+        let response = fake_server()
+            .get("/")
+            .authorization_bearer(TEST_TOKEN)
+            .await;
+        response.assert_status_ok();
+        response.assert_text("messaging");
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_caller() {
+        let server = fake_server();
+
+        let response = server.get("/").await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        let response = server
+            .get("/")
+            .authorization_bearer("test_token_for_the_user_service")
+            .await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+    }
+}
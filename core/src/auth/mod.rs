@@ -0,0 +1,192 @@
+use std::fmt::{Debug, Formatter};
+
+use axum::response::IntoResponse;
+use http::{StatusCode, header::WWW_AUTHENTICATE};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::signed_url::service::AvailableActions;
+
+pub(crate) mod extractor;
+
+/// Minimum length of the bearer tokens of the callers
+pub const MIN_TOKEN_LENGTH: usize = 16;
+
+/// A backend microservice allowed to request signed urls
+#[derive(Clone, Deserialize, PartialEq)]
+pub struct Caller {
+    pub name: String,
+    token: String,
+    /// Prefixes the caller may sign urls for
+    pub prefixes: Vec<String>,
+    /// Actions the caller may sign urls for
+    pub actions: Vec<AvailableActions>,
+}
+
+impl Caller {
+    pub fn new(
+        name: String,
+        token: String,
+        prefixes: Vec<String>,
+        actions: Vec<AvailableActions>,
+    ) -> Self {
+        Self {
+            name,
+            token,
+            prefixes,
+            actions,
+        }
+    }
+
+    pub fn allows(&self, prefix: &str, action: AvailableActions) -> bool {
+        self.prefixes.iter().any(|p| p == prefix) && self.actions.contains(&action)
+    }
+
+    /// Compares digests rather than the tokens so the comparison time
+    /// doesn't depend on how much of the token was guessed right.
+    fn has_token(&self, token: &str) -> bool {
+        Sha256::digest(self.token.as_bytes()) == Sha256::digest(token.as_bytes())
+    }
+}
+
+impl Debug for Caller {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Caller")
+            .field("name", &self.name)
+            .field("token", &"***")
+            .field("prefixes", &self.prefixes)
+            .field("actions", &self.actions)
+            .finish()
+    }
+}
+
+/// The callers allowed to request signed urls, configured as a JSON array
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Callers(Vec<Caller>);
+
+impl Callers {
+    pub fn new(callers: Vec<Caller>) -> Result<Self, String> {
+        for (i, caller) in callers.iter().enumerate() {
+            if caller.name.is_empty() {
+                return Err("Caller name cannot be empty".to_string());
+            }
+            if caller.token.len() < MIN_TOKEN_LENGTH {
+                return Err(format!(
+                    "Token of caller {} is too short, expected at least {} characters",
+                    caller.name, MIN_TOKEN_LENGTH
+                ));
+            }
+            if callers[..i].iter().any(|c| c.token == caller.token) {
+                return Err(format!(
+                    "Token of caller {} is already used by another caller",
+                    caller.name
+                ));
+            }
+        }
+        Ok(Self(callers))
+    }
+
+    /// Parses a JSON array of `{"name", "token", "prefixes", "actions"}` objects
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let callers: Vec<Caller> = serde_json::from_str(value)
+            .map_err(|e| format!("Callers are not a valid JSON array: {}", e))?;
+        Self::new(callers)
+    }
+
+    pub fn authenticate(&self, token: &str) -> Option<&Caller> {
+        self.0.iter().find(|caller| caller.has_token(token))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Missing bearer token")]
+    MissingToken,
+    #[error("Invalid bearer token")]
+    InvalidToken,
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> axum::response::Response {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, "Bearer")],
+            self.to_string(),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::Config;
+
+    pub const TEST_TOKEN: &str = "test_token_for_the_messaging_service";
+
+    /// A config letting the `TEST_TOKEN` bearer sign any action on `prefix`
+    pub fn config_with_caller(prefix: &str) -> Arc<Config> {
+        Arc::new(Config {
+            callers: Callers::new(vec![Caller::new(
+                "messaging".to_string(),
+                TEST_TOKEN.to_string(),
+                vec![prefix.to_string()],
+                vec![
+                    AvailableActions::Put,
+                    AvailableActions::Get,
+                    AvailableActions::Delete,
+                    AvailableActions::CreateMultipartUpload,
+                ],
+            )])
+            .expect("Invalid callers"),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_parse_callers() {
+        let callers = Callers::parse(
+            r#"[{"name": "messaging", "token": "test_token_for_the_messaging_service", "prefixes": ["message_attachment"], "actions": ["Put", "Get"]}]"#,
+        )
+        .expect("Invalid callers");
+
+        let caller = callers.authenticate(TEST_TOKEN).expect("Unknown caller");
+        assert_eq!(caller.name, "messaging");
+        assert!(caller.allows("message_attachment", AvailableActions::Put));
+        assert!(!caller.allows("message_attachment", AvailableActions::Delete));
+        assert!(!caller.allows("profile_picture", AvailableActions::Put));
+        assert!(
+            callers
+                .authenticate("test_token_for_the_user_service")
+                .is_none()
+        );
+        assert!(!format!("{:?}", callers).contains(TEST_TOKEN));
+    }
+
+    #[test]
+    fn test_invalid_callers() {
+        assert!(Callers::parse("{}").is_err());
+        assert!(
+            Callers::parse(
+                r#"[{"name": "short", "token": "short", "prefixes": [], "actions": []}]"#
+            )
+            .is_err()
+        );
+        assert!(
+            Callers::parse(
+                r#"[
+                    {"name": "a", "token": "test_token_for_the_messaging_service", "prefixes": [], "actions": []},
+                    {"name": "b", "token": "test_token_for_the_messaging_service", "prefixes": [], "actions": []}
+                ]"#
+            )
+            .is_err()
+        );
+    }
+}
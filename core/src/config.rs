@@ -1,4 +1,112 @@
-use clap::Parser;
+use std::{
+    fmt::{Debug, Formatter},
+    path::PathBuf,
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use clap::{Parser, ValueEnum};
+
+pub use crate::signed_url::service::MaxTtls;
+use crate::{auth::Callers, signer::is_valid_key_id};
+
+/// Minimum length in bytes of the key signing the urls, `setup` generates 20 bytes keys
+pub const MIN_SIGNING_KEY_LENGTH: usize = 16;
+
+/// Secret key signing the urls, kept apart from the S3 credentials
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SigningKey(Vec<u8>);
+
+impl SigningKey {
+    /// Decodes a base64 key, as generated by `setup`
+    pub fn parse(encoded: &str) -> Result<Self, String> {
+        let key = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("Signing key is not valid base64: {}", e))?;
+        if key.len() < MIN_SIGNING_KEY_LENGTH {
+            return Err(format!(
+                "Signing key is too short, expected at least {} bytes, got {}",
+                MIN_SIGNING_KEY_LENGTH,
+                key.len()
+            ));
+        }
+        Ok(Self(key))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Debug for SigningKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SigningKey(***)")
+    }
+}
+
+/// A previous signing key, still accepted to verify the urls it signed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerificationKey {
+    pub id: String,
+    pub key: SigningKey,
+}
+
+impl VerificationKey {
+    /// Parses a `kid:base64` pair
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let Some((id, key)) = value.trim().split_once(':') else {
+            return Err("Verification key must be formatted as `kid:base64`".to_string());
+        };
+        let id = parse_key_id(id)?;
+        Ok(Self {
+            id,
+            key: SigningKey::parse(key)?,
+        })
+    }
+}
+
+/// Key ids are embedded in the signed urls
+pub fn parse_key_id(id: &str) -> Result<String, String> {
+    if !is_valid_key_id(id) {
+        return Err(format!(
+            "Invalid key id {:?}, only alphanumerics, `-`, `_` and `.` are allowed",
+            id
+        ));
+    }
+    Ok(id.to_string())
+}
+
+/// Where the objects are stored
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// An S3 compatible object store, Garage in development
+    #[default]
+    S3,
+    /// A directory of the local filesystem, see `STORAGE_PATH`
+    Filesystem,
+    /// Kept in memory and lost on exit, for tests and demos
+    Memory,
+}
+
+/// How buckets are addressed in the requests to the S3 provider
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum S3Addressing {
+    /// `https://endpoint/bucket/key`, as Garage, MinIO and Ceph RGW expect
+    #[default]
+    Path,
+    /// `https://bucket.endpoint/key`, as AWS S3 expects
+    VirtualHosted,
+}
+
+/// Where the credentials of the S3 provider come from
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum S3Credentials {
+    /// `KEY_ID` and `SECRET_KEY`
+    #[default]
+    Static,
+    /// The standard AWS provider chain: environment, profiles, web identity,
+    /// then container and instance metadata
+    Chain,
+}
 
 #[derive(Parser, Default, Clone, Debug)]
 #[clap(name = "beep-content", version, about = "Content server for Beep")]
@@ -17,6 +125,87 @@ pub struct Config {
     )]
     pub s3_endpoint: String,
 
+    #[clap(
+        env,
+        long,
+        default_value = "garage",
+        help = "Region of the S3 provider"
+    )]
+    pub s3_region: String,
+
+    #[clap(
+        env,
+        long,
+        value_enum,
+        default_value = "path",
+        help = "Whether buckets are addressed in the path or in the host name of the S3 requests"
+    )]
+    pub s3_addressing: S3Addressing,
+
+    #[clap(
+        env,
+        long,
+        help = "PEM bundle of the certificate authorities trusted besides the platform ones"
+    )]
+    pub s3_ca_bundle: Option<PathBuf>,
+
+    #[clap(
+        env,
+        long,
+        help = "Timeout of the connections to the S3 provider, in milliseconds"
+    )]
+    pub s3_connect_timeout_ms: Option<u64>,
+
+    #[clap(
+        env,
+        long,
+        help = "Longest wait for the S3 provider to send data, in milliseconds"
+    )]
+    pub s3_read_timeout_ms: Option<u64>,
+
+    #[clap(
+        env,
+        long,
+        default_value = "3",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Attempts of each S3 request, including the first one"
+    )]
+    pub s3_max_attempts: u32,
+
+    #[clap(
+        env,
+        long,
+        default_value = "1000",
+        help = "Delay before the first retry of an S3 request, in milliseconds"
+    )]
+    pub s3_initial_backoff_ms: u64,
+
+    #[clap(
+        env,
+        long,
+        value_enum,
+        default_value = "static",
+        help = "Whether the S3 credentials are KEY_ID and SECRET_KEY or come from the AWS provider chain"
+    )]
+    pub s3_credentials: S3Credentials,
+
+    #[clap(
+        env,
+        long,
+        value_enum,
+        default_value = "s3",
+        help = "Where the objects are stored, `filesystem` needs no object store"
+    )]
+    pub storage_backend: StorageBackend,
+
+    #[clap(
+        env,
+        long,
+        default_value = "data",
+        help = "Directory of the objects when STORAGE_BACKEND is `filesystem`"
+    )]
+    pub storage_path: PathBuf,
+
     #[clap(env, long, default_value = "beep", help = "S3 bucket")]
     pub s3_bucket: String,
 
@@ -28,11 +217,61 @@ pub struct Config {
 
     #[clap(env, long, default_value = "https://beep.com", help = "Base URL")]
     pub base_url: String,
+
+    #[clap(
+        env,
+        long,
+        hide_env_values = true,
+        value_parser = SigningKey::parse,
+        help = "Base64 encoded key signing the urls"
+    )]
+    pub signing_key: SigningKey,
+
+    #[clap(
+        env,
+        long,
+        default_value = "v1",
+        value_parser = parse_key_id,
+        help = "Id of the signing key, embedded in the signed urls"
+    )]
+    pub signing_key_id: String,
+
+    #[clap(
+        env,
+        long,
+        hide_env_values = true,
+        value_delimiter = ',',
+        value_parser = VerificationKey::parse,
+        help = "Previous signing keys still accepted, as comma separated `kid:base64` pairs"
+    )]
+    pub verification_keys: Vec<VerificationKey>,
+
+    #[clap(
+        env,
+        long,
+        hide_env_values = true,
+        default_value = "[]",
+        value_parser = Callers::parse,
+        help = "Microservices allowed to sign urls, as a JSON array of {name, token, prefixes, actions}"
+    )]
+    pub callers: Callers,
+
+    #[clap(flatten)]
+    pub max_ttls: MaxTtls,
+
+    #[clap(
+        env,
+        long,
+        help = "TOML upload policy of the prefixes, the built-in policy is used when unset"
+    )]
+    pub policy_file: Option<PathBuf>,
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::config::Config;
+    use clap::Parser;
+
+    use crate::config::{Config, MaxTtls, SigningKey, VerificationKey};
     use dotenv::dotenv;
 
     pub fn bootstrap_integration_tests() -> Config {
@@ -49,7 +288,52 @@ pub mod tests {
             key_id: std::env::var("TEST_KEY_ID").unwrap_or("beep_test_admin".to_string()),
             secret_key: std::env::var("TEST_SECRET_KEY").unwrap_or("beep_test_admin".to_string()),
             s3_bucket: std::env::var("TEST_S3_BUCKET").unwrap_or("test".to_string()),
+            signing_key: SigningKey::parse(
+                &std::env::var("SIGNING_KEY").unwrap_or("dGVzdF9zaWduaW5nX2tleV9iZWVw".to_string()),
+            )
+            .expect("Invalid signing key"),
+            signing_key_id: "v1".to_string(),
+            s3_region: "garage".to_string(),
+            s3_max_attempts: 3,
             ..Default::default()
         }
     }
+
+    #[test]
+    fn test_default_max_ttls() {
+        let parsed = Config::try_parse_from([
+            "beep-content",
+            "--signing-key",
+            "dGVzdF9zaWduaW5nX2tleV9iZWVw",
+        ])
+        .expect("Invalid arguments");
+        assert_eq!(parsed.max_ttls, MaxTtls::default());
+        assert_eq!(Config::default().max_ttls, MaxTtls::default());
+        assert_eq!(MaxTtls::default().put, 900);
+    }
+
+    #[test]
+    fn test_signing_key() {
+        let key = SigningKey::parse("dGVzdF9zaWduaW5nX2tleV9iZWVw").expect("Invalid signing key");
+        assert_eq!(key.as_bytes(), b"test_signing_key_beep");
+        assert_eq!(format!("{:?}", key), "SigningKey(***)");
+    }
+
+    #[test]
+    fn test_invalid_signing_key() {
+        assert!(SigningKey::parse("").is_err());
+        assert!(SigningKey::parse("not base64!").is_err());
+        assert!(SigningKey::parse("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn test_verification_key() {
+        let key = VerificationKey::parse("v0:dGVzdF9zaWduaW5nX2tleV9iZWVw").expect("Invalid key");
+        assert_eq!(key.id, "v0");
+        assert_eq!(key.key.as_bytes(), b"test_signing_key_beep");
+
+        assert!(VerificationKey::parse("dGVzdF9zaWduaW5nX2tleV9iZWVw").is_err());
+        assert!(VerificationKey::parse("v&0:dGVzdF9zaWduaW5nX2tleV9iZWVw").is_err());
+        assert!(VerificationKey::parse("v0:c2hvcnQ=").is_err());
+    }
 }
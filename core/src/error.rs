@@ -1,4 +1,7 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use axum::{
+    http::{StatusCode, header::CONTENT_RANGE},
+    response::IntoResponse,
+};
 use utoipa::ToSchema;
 
 #[derive(Debug, thiserror::Error)]
@@ -11,6 +14,8 @@ pub enum CoreError {
     SigningKeyError(String),
     #[error("StorageError: {0}")]
     StorageError(String),
+    #[error("PolicyError: {0}")]
+    PolicyError(String),
     #[error("TelemetryError: {0}")]
     TelemetryError(TelemetryError),
 }
@@ -43,6 +48,10 @@ pub enum ApiError {
     BadRequest(String),
     #[allow(dead_code)]
     ServiceUnavailable(String),
+    LengthRequired(String),
+    /// Unsatisfiable range of an object of the given length
+    RangeNotSatisfiable(String, u64),
+    PayloadTooLarge(String),
 }
 
 impl IntoResponse for ApiError {
@@ -61,6 +70,18 @@ impl IntoResponse for ApiError {
             ApiError::ServiceUnavailable(message) => {
                 (StatusCode::SERVICE_UNAVAILABLE, message).into_response()
             }
+            ApiError::LengthRequired(message) => {
+                (StatusCode::LENGTH_REQUIRED, message).into_response()
+            }
+            ApiError::RangeNotSatisfiable(message, length) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{}", length))],
+                message,
+            )
+                .into_response(),
+            ApiError::PayloadTooLarge(message) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, message).into_response()
+            }
         }
     }
 }
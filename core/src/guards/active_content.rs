@@ -0,0 +1,132 @@
+use crate::guards::{FileType, media_type};
+
+/// Content type active content is stored with, which browsers never render
+pub const NEUTRAL_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Leading bytes browsers recognise markup from, after the WHATWG MIME
+/// sniffing standard, along with the root element of SVG images
+const MARKUP_PATTERNS: &[&[u8]] = &[
+    b"<!doctype html",
+    b"<html",
+    b"<head",
+    b"<script",
+    b"<iframe",
+    b"<h1",
+    b"<div",
+    b"<font",
+    b"<table",
+    b"<a",
+    b"<style",
+    b"<title",
+    b"<b",
+    b"<body",
+    b"<br",
+    b"<p",
+    b"<!--",
+    b"<svg",
+];
+
+/// Whether browsers execute content of this media type: markup, which can
+/// carry scripts, and scripts themselves
+pub fn is_active_media_type(media_type: &str) -> bool {
+    matches!(
+        media_type,
+        "text/html"
+            | "application/xhtml+xml"
+            | "image/svg+xml"
+            | "text/xml"
+            | "application/xml"
+            | "text/xsl"
+            | "text/javascript"
+            | "application/javascript"
+            | "application/x-javascript"
+            | "application/ecmascript"
+            | "text/ecmascript"
+    ) || media_type.ends_with("+xml")
+}
+
+/// Whether the first bytes of a file would be sniffed as HTML, SVG or XML
+pub fn sniffs_as_markup(head: &[u8]) -> bool {
+    // Browsers skip the UTF-8 byte order mark and leading whitespace
+    let head = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    let start = head
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(head.len());
+    let head = &head[start..];
+
+    if head.len() >= 5 && head[..5].eq_ignore_ascii_case(b"<?xml") {
+        return true;
+    }
+    MARKUP_PATTERNS.iter().any(|pattern| {
+        head.len() > pattern.len()
+            && head[..pattern.len()].eq_ignore_ascii_case(pattern)
+            && (head[pattern.len()] == b'>' || head[pattern.len()].is_ascii_whitespace())
+    })
+}
+
+/// Content type an upload is stored with: active content, whether declared
+/// or sniffed, is downgraded to `NEUTRAL_CONTENT_TYPE`.
+pub fn neutralize(head: &[u8], content_type: String) -> String {
+    if is_active_media_type(&media_type(&content_type)) || sniffs_as_markup(head) {
+        NEUTRAL_CONTENT_TYPE.to_string()
+    } else {
+        content_type
+    }
+}
+
+/// Whether objects of this content type can be displayed by browsers rather
+/// than downloaded: the file types guards recognise, and plain text
+pub fn is_inline_safe(content_type: &str) -> bool {
+    let media_type = media_type(content_type);
+    media_type == "text/plain"
+        || FileType::parse(&media_type).is_some_and(|file_type| file_type != FileType::Any)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniffs_as_markup() {
+        for head in [
+            "<!DOCTYPE html><html></html>",
+            "\u{FEFF}  <HTML>",
+            "\n<script>alert(1)</script>",
+            "<svg xmlns='http://www.w3.org/2000/svg'/>",
+            "<?xml version=\"1.0\"?>",
+            "<p>hello</p>",
+        ] {
+            assert!(sniffs_as_markup(head.as_bytes()), "{}", head);
+        }
+        for head in ["hello <script>", "<paragraph", "", "%PDF-1.7"] {
+            assert!(!sniffs_as_markup(head.as_bytes()), "{}", head);
+        }
+    }
+
+    #[test]
+    fn test_neutralize() {
+        assert_eq!(
+            neutralize(b"hello", "Text/HTML; charset=utf-8".to_string()),
+            NEUTRAL_CONTENT_TYPE
+        );
+        assert_eq!(
+            neutralize(b"{}", "application/rss+xml".to_string()),
+            NEUTRAL_CONTENT_TYPE
+        );
+        assert_eq!(
+            neutralize(b"<html>", "text/plain".to_string()),
+            NEUTRAL_CONTENT_TYPE
+        );
+        assert_eq!(neutralize(b"hello", "text/plain".to_string()), "text/plain");
+    }
+
+    #[test]
+    fn test_is_inline_safe() {
+        assert!(is_inline_safe("image/png"));
+        assert!(is_inline_safe("text/plain; charset=utf-8"));
+        assert!(!is_inline_safe("text/html"));
+        assert!(!is_inline_safe("image/svg+xml"));
+        assert!(!is_inline_safe(NEUTRAL_CONTENT_TYPE));
+    }
+}
@@ -1,6 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::{error::ApiError, prefixes::Prefix, s3::FileObject};
+use crate::{
+    error::ApiError,
+    images::{
+        ImageError,
+        limits::{ImageLimits, limited_format},
+        variants::VariantParams,
+    },
+};
+
+pub mod active_content;
+pub mod policy;
+
+/// Number of leading bytes of an upload handed to the guards. The `infer`
+/// matchers never need more than that to recognise a format.
+pub const SNIFF_LENGTH: usize = 8192;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum FileType {
@@ -37,6 +51,20 @@ impl From<&str> for FileType {
     }
 }
 
+impl FileType {
+    /// Strict counterpart of `From<&str>`, unknown content types are refused
+    /// rather than treated as `Any`, which is spelled `*`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "*" => Some(FileType::Any),
+            s => match FileType::from(s) {
+                FileType::Any => None,
+                file_type => Some(file_type),
+            },
+        }
+    }
+}
+
 impl From<FileType> for &str {
     fn from(file_type: FileType) -> Self {
         match file_type {
@@ -56,6 +84,16 @@ impl From<FileType> for &str {
     }
 }
 
+/// Content type without its parameters, in lowercase
+pub fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
 #[allow(clippy::from_over_into)]
 impl Into<ApiError> for GuardError {
     fn into(self) -> ApiError {
@@ -65,8 +103,25 @@ impl Into<ApiError> for GuardError {
             }
             GuardError::WrongContentType => ApiError::BadRequest("Wrong content type".to_string()),
             GuardError::UnknownFileType => ApiError::BadRequest("Unknown file type".to_string()),
-            GuardError::NoGuardFound => ApiError::InternalServerError("No guard found".to_string()),
             GuardError::UnknownPrefix => ApiError::NotFound("Unknown prefix".to_string()),
+            GuardError::FileTooLarge(max_size) => ApiError::PayloadTooLarge(format!(
+                "File too large, the maximum size is {} bytes",
+                max_size
+            )),
+            GuardError::FileTooSmall(min_size) => ApiError::BadRequest(format!(
+                "File too small, the minimum size is {} bytes",
+                min_size
+            )),
+            GuardError::ActiveContent => ApiError::BadRequest(format!(
+                "Active content is only accepted as {}",
+                active_content::NEUTRAL_CONTENT_TYPE
+            )),
+            GuardError::ImageTooLarge(reason) => {
+                ApiError::UnProcessableEntity(format!("Image too large: {}", reason))
+            }
+            GuardError::InvalidImage(reason) => {
+                ApiError::UnProcessableEntity(format!("Invalid image: {}", reason))
+            }
         }
     }
 }
@@ -77,36 +132,207 @@ pub enum GuardError {
     WrongContentType,
     UnknownFileType,
     UnknownPrefix,
-    NoGuardFound,
+    FileTooLarge(u64),
+    FileTooSmall(u64),
+    ActiveContent,
+    ImageTooLarge(String),
+    InvalidImage(String),
 }
 
+/// Policy of a prefix : what its uploads must look like, who can read its
+/// objects and how long they are kept.
 #[derive(Clone, Debug)]
 pub struct Guard {
     allowed_file_types: Vec<FileType>,
+    max_size: Option<u64>,
+    min_size: Option<u64>,
+    public: bool,
+    max_ttl: Option<u64>,
+    retention_days: Option<u32>,
+    cache_control: Option<String>,
+    derivatives: Vec<VariantParams>,
+    strip_metadata: bool,
+    image_limits: ImageLimits,
+    bucket: Option<String>,
+    key_prefix: Option<String>,
 }
 
+/// Bucket and key an object is stored under
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub bucket: String,
+    pub key: String,
+}
+
+/// Registry of the prefixes declared in the policy, along with their guard.
+/// Prefixes missing from it are unknown and refused.
 pub struct Guards {
-    map: HashMap<Prefix, Guard>,
+    map: HashMap<String, Guard>,
 }
 
 impl Guard {
     pub fn new(allowed_file_types: Vec<FileType>) -> Self {
-        Self { allowed_file_types }
+        Self {
+            allowed_file_types,
+            max_size: None,
+            min_size: None,
+            public: false,
+            max_ttl: None,
+            retention_days: None,
+            cache_control: None,
+            derivatives: Vec::new(),
+            strip_metadata: false,
+            image_limits: ImageLimits::default(),
+            bucket: None,
+            key_prefix: None,
+        }
+    }
+
+    /// Refuses files bigger than `max_size` bytes
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Refuses files smaller than `min_size` bytes
+    pub fn with_min_size(mut self, min_size: u64) -> Self {
+        self.min_size = Some(min_size);
+        self
+    }
+
+    /// Lets the objects of the prefix be read without a signed url
+    pub fn with_public(mut self, public: bool) -> Self {
+        self.public = public;
+        self
+    }
+
+    /// Caps the lifetime, in seconds, of the urls signed for the prefix
+    pub fn with_max_ttl(mut self, max_ttl: u64) -> Self {
+        self.max_ttl = Some(max_ttl);
+        self
+    }
+
+    /// Deletes the objects of the prefix `retention_days` days after their upload
+    pub fn with_retention_days(mut self, retention_days: u32) -> Self {
+        self.retention_days = Some(retention_days);
+        self
+    }
+
+    /// Sets the `Cache-Control` header of the downloads of the prefix
+    pub fn with_cache_control(mut self, cache_control: &str) -> Self {
+        self.cache_control = Some(cache_control.to_string());
+        self
+    }
+
+    /// Renders these variants of the images uploaded to the prefix along with them
+    pub fn with_derivatives(mut self, derivatives: Vec<VariantParams>) -> Self {
+        self.derivatives = derivatives;
+        self
+    }
+
+    /// Removes the EXIF, XMP and text metadata of the JPEG, PNG and WebP uploads
+    pub fn with_strip_metadata(mut self, strip_metadata: bool) -> Self {
+        self.strip_metadata = strip_metadata;
+        self
+    }
+
+    /// Refuses the PNG, JPEG, GIF and WebP uploads whose headers exceed these limits
+    pub fn with_image_limits(mut self, image_limits: ImageLimits) -> Self {
+        self.image_limits = image_limits;
+        self
+    }
+
+    /// Stores the objects of the prefix in `bucket` rather than in the configured bucket
+    pub fn with_bucket(mut self, bucket: &str) -> Self {
+        self.bucket = Some(bucket.to_string());
+        self
+    }
+
+    /// Stores the objects of the prefix under `key_prefix/`
+    pub fn with_key_prefix(mut self, key_prefix: &str) -> Self {
+        self.key_prefix = Some(key_prefix.to_string());
+        self
+    }
+
+    /// Where the object at `key` is stored, in `default_bucket` unless the
+    /// prefix has a bucket of its own
+    pub fn location(&self, default_bucket: &str, key: &str) -> Location {
+        Location {
+            bucket: self.bucket.as_deref().unwrap_or(default_bucket).to_string(),
+            key: match &self.key_prefix {
+                Some(key_prefix) => format!("{}/{}", key_prefix, key),
+                None => key.to_string(),
+            },
+        }
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.public
+    }
+
+    pub fn max_ttl(&self) -> Option<u64> {
+        self.max_ttl
+    }
+
+    pub fn cache_control(&self) -> Option<&str> {
+        self.cache_control.as_deref()
+    }
+
+    pub fn derivatives(&self) -> &[VariantParams] {
+        &self.derivatives
+    }
+
+    pub fn strips_metadata(&self) -> bool {
+        self.strip_metadata
+    }
+
+    /// Whether uploads of this media type are checked against the image limits,
+    /// which needs the whole upload rather than its first bytes
+    pub fn limits_images(&self, media_type: &str) -> bool {
+        !self.image_limits.is_empty() && limited_format(media_type).is_some()
+    }
+
+    /// Checks the dimensions, and the frames of GIFs, of a whole image upload
+    /// against the image limits. Other media types are left alone.
+    pub fn check_image(&self, data: &[u8], media_type: &str) -> Result<(), GuardError> {
+        let Some(format) = limited_format(media_type) else {
+            return Ok(());
+        };
+        self.image_limits.check(data, format).map_err(|e| match e {
+            ImageError::LimitExceeded(reason) => GuardError::ImageTooLarge(reason),
+            e => GuardError::InvalidImage(e.to_string()),
+        })
     }
 
+    /// Checks the size of an upload and its first bytes against the allowed file types.
+    /// On success, returns the content type the object should be stored with, which
+    /// differs from the declared one when active content is uploaded to an `Any` guard.
     pub fn check(
         &self,
-        data: Vec<u8>,
+        head: &[u8],
+        content_length: u64,
         content_type: &str,
         _file_name: &str,
-    ) -> Result<FileObject, GuardError> {
+    ) -> Result<String, GuardError> {
+        if let Some(max_size) = self.max_size
+            && content_length > max_size
+        {
+            return Err(GuardError::FileTooLarge(max_size));
+        }
+        if let Some(min_size) = self.min_size
+            && content_length < min_size
+        {
+            return Err(GuardError::FileTooSmall(min_size));
+        }
+
         let content_type = content_type.to_string();
 
+        // Any file is accepted, but never served as something browsers execute
         if self.allowed_file_types.contains(&FileType::Any) {
-            return Ok(FileObject { data, content_type });
+            return Ok(active_content::neutralize(head, content_type));
         }
 
-        let kind = infer::get(&data);
+        let kind = infer::get(head);
 
         match kind {
             Some(kind) => {
@@ -135,12 +361,12 @@ impl Guard {
             }
         }
 
-        Ok(FileObject { content_type, data })
+        Ok(content_type)
     }
 }
 
 pub struct GuardsBuilder {
-    map: HashMap<Prefix, Guard>,
+    map: HashMap<String, Guard>,
 }
 
 impl GuardsBuilder {
@@ -149,8 +375,8 @@ impl GuardsBuilder {
             map: HashMap::new(),
         }
     }
-    pub fn add(&mut self, destination: Prefix, guard: Guard) -> &mut Self {
-        self.map.insert(destination, guard);
+    pub fn add(&mut self, destination: &str, guard: Guard) -> &mut Self {
+        self.map.insert(destination.to_string(), guard);
         self
     }
 
@@ -166,25 +392,58 @@ impl Guards {
         &self,
         destination: &str,
         file_name: &str,
-        data: Vec<u8>,
+        head: &[u8],
+        content_length: u64,
         content_type: &str,
-    ) -> Result<FileObject, GuardError> {
-        let prefix = Prefix::from(destination);
-        if prefix == Prefix::Unknown {
-            return Err(GuardError::UnknownPrefix);
-        }
-        let guard = self.map.get(&prefix);
-        let file = match guard {
-            Some(guard) => guard.check(data, content_type, file_name),
-            None => Err(GuardError::NoGuardFound),
-        }?;
-        Ok(file)
+    ) -> Result<String, GuardError> {
+        self.guard(destination)?
+            .check(head, content_length, content_type, file_name)
+    }
+
+    /// Returns the guard protecting a destination prefix
+    pub fn guard(&self, destination: &str) -> Result<&Guard, GuardError> {
+        self.map.get(destination).ok_or(GuardError::UnknownPrefix)
+    }
+
+    /// Where the object `file_name` of `prefix` is stored. Unknown prefixes
+    /// are stored as is in `default_bucket`, their guard refuses them anyway.
+    pub fn location(&self, default_bucket: &str, prefix: &str, file_name: &str) -> Location {
+        let key = format!("{}/{}", prefix, file_name);
+        match self.map.get(prefix) {
+            Some(guard) => guard.location(default_bucket, &key),
+            None => Location {
+                bucket: default_bucket.to_string(),
+                key,
+            },
+        }
+    }
+
+    /// Returns the key prefixes whose objects expire, with their retention in
+    /// days, grouped by the bucket they are stored in. `default_bucket` and
+    /// every bucket of the prefixes are listed, without any retention if none
+    /// of their prefixes expire, so that the rules of the former retentions
+    /// can be removed.
+    pub fn retentions(&self, default_bucket: &str) -> BTreeMap<String, Vec<(String, u32)>> {
+        let mut retentions: BTreeMap<String, Vec<(String, u32)>> =
+            BTreeMap::from([(default_bucket.to_string(), Vec::new())]);
+        for (prefix, guard) in &self.map {
+            let location = guard.location(default_bucket, prefix);
+            let rules = retentions.entry(location.bucket).or_default();
+            if let Some(retention_days) = guard.retention_days {
+                rules.push((location.key, retention_days));
+            }
+        }
+        for rules in retentions.values_mut() {
+            rules.sort();
+        }
+        retentions
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::images::variants::tests::fake_png;
 
     #[test]
     fn test_guard_happy_path() {
@@ -193,15 +452,16 @@ mod tests {
         const CONTENT_TYPE: &str = "image/jpeg";
 
         let guards = GuardsBuilder::new()
-            .add(
-                Prefix::ServerBanner,
-                Guard {
-                    allowed_file_types: vec![FileType::ImageJPEG],
-                },
-            )
+            .add("server_banner", Guard::new(vec![FileType::ImageJPEG]))
             .build();
 
-        let file = guards.check(Prefix::ServerBanner.as_str(), FILE_NAME, buf, CONTENT_TYPE);
+        let file = guards.check(
+            "server_banner",
+            FILE_NAME,
+            &buf,
+            buf.len() as u64,
+            CONTENT_TYPE,
+        );
         insta::assert_debug_snapshot!(file);
     }
 
@@ -212,15 +472,16 @@ mod tests {
         const CONTENT_TYPE: &str = "text/html";
 
         let guards = GuardsBuilder::new()
-            .add(
-                Prefix::ServerBanner,
-                Guard {
-                    allowed_file_types: vec![FileType::Any],
-                },
-            )
+            .add("server_banner", Guard::new(vec![FileType::Any]))
             .build();
 
-        let file = guards.check(Prefix::ServerBanner.as_str(), FILE_NAME, buf, CONTENT_TYPE);
+        let file = guards.check(
+            "server_banner",
+            FILE_NAME,
+            &buf,
+            buf.len() as u64,
+            CONTENT_TYPE,
+        );
         insta::assert_debug_snapshot!(file);
     }
 
@@ -230,16 +491,109 @@ mod tests {
         const FILE_NAME: &str = "index.svg";
         const CONTENT_TYPE: &str = "application/octet-stream";
 
+        let guards = GuardsBuilder::new()
+            .add("server_banner", Guard::new(vec![FileType::ImagePNG]))
+            .build();
+
+        let file = guards.check("test", FILE_NAME, &buf, buf.len() as u64, CONTENT_TYPE);
+        insta::assert_debug_snapshot!(file);
+    }
+
+    #[test]
+    fn test_guard_any_files_active_content() {
+        let svg = "<svg xmlns='http://www.w3.org/2000/svg'><script>alert(1)</script></svg>";
+        let guards = GuardsBuilder::new()
+            .add("message_attachment", Guard::new(vec![FileType::Any]))
+            .build();
+
+        for content_type in ["image/svg+xml", "text/plain", "image/png"] {
+            let file = guards.check(
+                "message_attachment",
+                "index.svg",
+                svg.as_bytes(),
+                svg.len() as u64,
+                content_type,
+            );
+            assert_eq!(
+                file.expect("Upload refused"),
+                active_content::NEUTRAL_CONTENT_TYPE
+            );
+        }
+    }
+
+    #[test]
+    fn test_guard_file_too_large() {
+        let buf: Vec<u8> = vec![0xFF, 0xD8, 0xFF, 0xAA];
+
         let guards = GuardsBuilder::new()
             .add(
-                Prefix::ServerBanner,
-                Guard {
-                    allowed_file_types: vec![FileType::ImagePNG],
-                },
+                "profile_picture",
+                Guard::new(vec![FileType::ImageJPEG]).with_max_size(1024),
             )
             .build();
 
-        let file = guards.check("test", FILE_NAME, buf, CONTENT_TYPE);
-        insta::assert_debug_snapshot!(file);
+        let file = guards.check("profile_picture", "index.jpg", &buf, 1025, "image/jpeg");
+        assert!(matches!(file, Err(GuardError::FileTooLarge(1024))));
+
+        let file = guards.check("profile_picture", "index.jpg", &buf, 1024, "image/jpeg");
+        assert!(file.is_ok());
+    }
+
+    #[test]
+    fn test_guard_file_too_small() {
+        let guards = GuardsBuilder::new()
+            .add(
+                "message_attachment",
+                Guard::new(vec![FileType::Any]).with_min_size(1),
+            )
+            .build();
+
+        let file = guards.check("message_attachment", "empty.txt", &[], 0, "text/plain");
+        assert!(matches!(file, Err(GuardError::FileTooSmall(1))));
+    }
+
+    #[test]
+    fn test_guard_image_limits() {
+        let guard = Guard::new(vec![FileType::ImagePNG]).with_image_limits(ImageLimits {
+            max_width: Some(64),
+            ..ImageLimits::default()
+        });
+        assert!(guard.limits_images("image/png"));
+        assert!(!guard.limits_images("application/pdf"));
+        assert!(!Guard::new(vec![FileType::ImagePNG]).limits_images("image/png"));
+
+        assert!(guard.check_image(&fake_png(64, 1), "image/png").is_ok());
+        assert!(matches!(
+            guard.check_image(&fake_png(65, 1), "image/png"),
+            Err(GuardError::ImageTooLarge(_))
+        ));
+        assert!(matches!(
+            guard.check_image(b"\x89PNG", "image/png"),
+            Err(GuardError::InvalidImage(_))
+        ));
+    }
+
+    #[test]
+    fn test_retentions_of_default_bucket() {
+        let guards = GuardsBuilder::new()
+            .add(
+                "message_attachment",
+                Guard::new(vec![FileType::Any])
+                    .with_retention_days(30)
+                    .with_bucket("beep-attachments"),
+            )
+            .build();
+
+        // Still listed once no prefix is stored in it anymore
+        assert_eq!(
+            guards.retentions("beep"),
+            BTreeMap::from([
+                ("beep".to_string(), vec![]),
+                (
+                    "beep-attachments".to_string(),
+                    vec![("message_attachment".to_string(), 30)]
+                ),
+            ])
+        );
     }
 }
@@ -0,0 +1,472 @@
+use std::{collections::BTreeMap, path::Path};
+
+use http::HeaderValue;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    guards::{FileType, Guard, Guards, GuardsBuilder},
+    images::{
+        limits::ImageLimits,
+        variants::{Fit, VariantFormat, VariantParams},
+    },
+};
+
+/// Policy used when no policy file is configured
+pub const DEFAULT_POLICY: &str = include_str!("policy.toml");
+
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("Cannot read policy file {0}: {1}")]
+    Read(String, String),
+    #[error("Invalid policy: {0}")]
+    Parse(String),
+    #[error("Invalid prefix name {0:?}, only lowercase alphanumerics, `_` and `-` are allowed")]
+    InvalidPrefix(String),
+    #[error("The policy declares no prefix")]
+    NoPrefix,
+    #[error("Unknown file type {1} allowed for prefix {0}")]
+    UnknownFileType(String, String),
+    #[error("No file type allowed for prefix {0}")]
+    NoAllowedType(String),
+    #[error("Minimum size of prefix {0} is above its maximum size")]
+    InvalidSizes(String),
+    #[error("Maximum url lifetime of prefix {0} must be positive")]
+    InvalidTtl(String),
+    #[error("Retention of prefix {0} must be between 1 and 2147483647 days")]
+    InvalidRetention(String),
+    #[error("Cache control of prefix {0} is not a valid header value")]
+    InvalidCacheControl(String),
+    #[error("Invalid derivative of prefix {0}: {1}")]
+    InvalidDerivative(String, String),
+    #[error("Image limits of prefix {0} must be positive")]
+    InvalidImageLimits(String),
+    #[error("Invalid bucket {1:?} of prefix {0}")]
+    InvalidBucket(String, String),
+    #[error("Invalid key prefix {1:?} of prefix {0}, it needs non empty `/` separated segments")]
+    InvalidKeyPrefix(String, String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    prefixes: BTreeMap<String, PrefixPolicy>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrefixPolicy {
+    allowed_types: Vec<String>,
+    max_size: Option<u64>,
+    min_size: Option<u64>,
+    #[serde(default)]
+    public: bool,
+    max_ttl_secs: Option<u64>,
+    retention_days: Option<u32>,
+    cache_control: Option<String>,
+    #[serde(default)]
+    derivatives: Vec<DerivativePolicy>,
+    #[serde(default)]
+    strip_metadata: bool,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    max_pixels: Option<u64>,
+    max_frames: Option<u32>,
+    bucket: Option<String>,
+    key_prefix: Option<String>,
+}
+
+/// A variant rendered when an image is uploaded, see `VariantParams`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DerivativePolicy {
+    w: Option<u32>,
+    h: Option<u32>,
+    fit: Option<Fit>,
+    format: Option<VariantFormat>,
+}
+
+/// Builds the guards from the policy file at `path`, or from the default policy
+pub fn load(path: Option<&Path>) -> Result<Guards, PolicyError> {
+    match path {
+        Some(path) => {
+            let policy = std::fs::read_to_string(path)
+                .map_err(|e| PolicyError::Read(path.display().to_string(), e.to_string()))?;
+            parse(&policy)
+        }
+        None => parse(DEFAULT_POLICY),
+    }
+}
+
+/// Builds the guards from a TOML policy, which declares every prefix
+pub fn parse(policy: &str) -> Result<Guards, PolicyError> {
+    let policy: PolicyFile =
+        toml::from_str(policy).map_err(|e| PolicyError::Parse(e.to_string()))?;
+    if policy.prefixes.is_empty() {
+        return Err(PolicyError::NoPrefix);
+    }
+
+    let mut builder = GuardsBuilder::new();
+    for (name, policy) in policy.prefixes {
+        if !is_valid_prefix(&name) {
+            return Err(PolicyError::InvalidPrefix(name));
+        }
+        let guard = policy.into_guard(&name)?;
+        builder.add(&name, guard);
+    }
+
+    Ok(builder.build())
+}
+
+/// Prefixes are the first segment of the object paths, `public` and `list`
+/// are taken by the routes serving the public prefixes and the listings.
+fn is_valid_prefix(name: &str) -> bool {
+    !name.is_empty()
+        && name != "public"
+        && name != "list"
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// S3 bucket names are 3 to 63 lowercase alphanumerics, `-` and `.`, which
+/// start and end with an alphanumeric
+fn is_valid_bucket(name: &str) -> bool {
+    (3..=63).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+}
+
+/// Key prefixes are joined to the keys with a `/`, so they can't start or end
+/// with one, nor hold empty or relative segments
+fn is_valid_key_prefix(key_prefix: &str) -> bool {
+    key_prefix
+        .split('/')
+        .all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+}
+
+impl PrefixPolicy {
+    fn into_guard(self, prefix: &str) -> Result<Guard, PolicyError> {
+        if self.allowed_types.is_empty() {
+            return Err(PolicyError::NoAllowedType(prefix.to_string()));
+        }
+        let allowed_file_types = self
+            .allowed_types
+            .iter()
+            .map(|t| {
+                FileType::parse(t)
+                    .ok_or_else(|| PolicyError::UnknownFileType(prefix.to_string(), t.clone()))
+            })
+            .collect::<Result<Vec<FileType>, PolicyError>>()?;
+
+        let mut guard = Guard::new(allowed_file_types)
+            .with_public(self.public)
+            .with_strip_metadata(self.strip_metadata);
+        if let Some(max_size) = self.max_size {
+            guard = guard.with_max_size(max_size);
+        }
+        if let Some(min_size) = self.min_size {
+            if self.max_size.is_some_and(|max_size| min_size > max_size) {
+                return Err(PolicyError::InvalidSizes(prefix.to_string()));
+            }
+            guard = guard.with_min_size(min_size);
+        }
+        if let Some(max_ttl) = self.max_ttl_secs {
+            if max_ttl == 0 {
+                return Err(PolicyError::InvalidTtl(prefix.to_string()));
+            }
+            guard = guard.with_max_ttl(max_ttl);
+        }
+        if let Some(retention_days) = self.retention_days {
+            // S3 lifecycle rules count the days of their expiration in an `i32`
+            if retention_days == 0 || i32::try_from(retention_days).is_err() {
+                return Err(PolicyError::InvalidRetention(prefix.to_string()));
+            }
+            guard = guard.with_retention_days(retention_days);
+        }
+        if let Some(bucket) = self.bucket {
+            if !is_valid_bucket(&bucket) {
+                return Err(PolicyError::InvalidBucket(prefix.to_string(), bucket));
+            }
+            guard = guard.with_bucket(&bucket);
+        }
+        if let Some(key_prefix) = self.key_prefix {
+            if !is_valid_key_prefix(&key_prefix) {
+                return Err(PolicyError::InvalidKeyPrefix(
+                    prefix.to_string(),
+                    key_prefix,
+                ));
+            }
+            guard = guard.with_key_prefix(&key_prefix);
+        }
+        if let Some(cache_control) = self.cache_control {
+            if HeaderValue::from_str(&cache_control).is_err() {
+                return Err(PolicyError::InvalidCacheControl(prefix.to_string()));
+            }
+            guard = guard.with_cache_control(&cache_control);
+        }
+        let derivatives = self
+            .derivatives
+            .into_iter()
+            .map(|derivative| {
+                let variant = VariantParams {
+                    w: derivative.w,
+                    h: derivative.h,
+                    fit: derivative.fit,
+                    format: derivative.format,
+                };
+                if variant.is_empty() {
+                    return Err(PolicyError::InvalidDerivative(
+                        prefix.to_string(),
+                        "a derivative needs a size or a format".to_string(),
+                    ));
+                }
+                variant.validate().map_err(|e| {
+                    PolicyError::InvalidDerivative(prefix.to_string(), e.to_string())
+                })?;
+                Ok(variant)
+            })
+            .collect::<Result<Vec<VariantParams>, PolicyError>>()?;
+        guard = guard.with_derivatives(derivatives);
+
+        let image_limits = ImageLimits {
+            max_width: self.max_width,
+            max_height: self.max_height,
+            max_pixels: self.max_pixels,
+            max_frames: self.max_frames,
+        };
+        if [
+            image_limits.max_width.map(u64::from),
+            image_limits.max_height.map(u64::from),
+            image_limits.max_pixels,
+            image_limits.max_frames.map(u64::from),
+        ]
+        .contains(&Some(0))
+        {
+            return Err(PolicyError::InvalidImageLimits(prefix.to_string()));
+        }
+        Ok(guard.with_image_limits(image_limits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guards::Location;
+
+    const POLICY: &str = r#"
+        [prefixes.profile_picture]
+        allowed_types = ["image/png"]
+        max_size = 1024
+        public = true
+        max_ttl_secs = 60
+        cache_control = "public, max-age=31536000, immutable"
+        derivatives = [{ w = 32, h = 32, fit = "cover", format = "webp" }]
+        strip_metadata = true
+        max_width = 1024
+        max_height = 1024
+        max_frames = 50
+
+        [prefixes.server_picture]
+        allowed_types = ["image/png"]
+
+        [prefixes.server_banner]
+        allowed_types = ["image/png"]
+
+        [prefixes.message_attachment]
+        allowed_types = ["*"]
+        min_size = 1
+        retention_days = 30
+        bucket = "beep-attachments"
+        key_prefix = "uploads/v1"
+    "#;
+
+    #[test]
+    fn test_default_policy() {
+        let guards = load(None).expect("Invalid default policy");
+        let guard = guards.guard("profile_picture").expect("Missing guard");
+        assert!(guard.is_public());
+        for prefix in ["server_picture", "server_banner"] {
+            assert!(guards.guard(prefix).expect("Missing guard").is_public());
+        }
+        let guard = guards.guard("message_attachment").expect("Missing guard");
+        assert!(!guard.is_public());
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let guards = parse(POLICY).expect("Invalid policy");
+        let guard = guards.guard("profile_picture").expect("Missing guard");
+        assert!(guard.is_public());
+        assert_eq!(guard.max_ttl(), Some(60));
+        assert!(guard.strips_metadata());
+        assert!(guard.limits_images("image/png"));
+        assert!(
+            !guards
+                .guard("server_picture")
+                .expect("Missing guard")
+                .limits_images("image/png")
+        );
+        assert!(
+            !guards
+                .guard("server_picture")
+                .expect("Missing guard")
+                .strips_metadata()
+        );
+        assert_eq!(
+            guard.cache_control(),
+            Some("public, max-age=31536000, immutable")
+        );
+        assert_eq!(
+            guard.derivatives(),
+            [VariantParams {
+                w: Some(32),
+                h: Some(32),
+                fit: Some(Fit::Cover),
+                format: Some(VariantFormat::Webp),
+            }]
+        );
+        assert_eq!(
+            guards.retentions("beep"),
+            BTreeMap::from([
+                ("beep".to_string(), vec![]),
+                (
+                    "beep-attachments".to_string(),
+                    vec![("uploads/v1/message_attachment".to_string(), 30)]
+                ),
+            ])
+        );
+        assert_eq!(
+            guards.location("beep", "message_attachment", "notes.txt"),
+            Location {
+                bucket: "beep-attachments".to_string(),
+                key: "uploads/v1/message_attachment/notes.txt".to_string(),
+            }
+        );
+        assert_eq!(
+            guards.location("beep", "profile_picture", "me.png"),
+            Location {
+                bucket: "beep".to_string(),
+                key: "profile_picture/me.png".to_string(),
+            }
+        );
+
+        let png = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        assert!(
+            guards
+                .check("profile_picture", "index.png", &png, 2048, "image/png")
+                .is_err()
+        );
+        assert!(
+            guards
+                .check("message_attachment", "empty.txt", &[], 0, "text/plain")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_declared_prefixes() {
+        let policy = format!(
+            "{}\n[prefixes.emoji]\nallowed_types = [\"image/png\"]",
+            POLICY
+        );
+        let guards = parse(&policy).expect("Invalid policy");
+        assert!(guards.guard("emoji").is_ok());
+        assert!(guards.guard("sticker").is_err());
+    }
+
+    #[test]
+    fn test_invalid_policies() {
+        assert!(matches!(parse("[prefixes]"), Err(PolicyError::NoPrefix)));
+
+        for name in ["public", "list", "Emoji", "emoji/big", ""] {
+            let invalid_prefix = format!(
+                "{}\n[prefixes.\"{}\"]\nallowed_types = [\"*\"]",
+                POLICY, name
+            );
+            assert!(matches!(
+                parse(&invalid_prefix),
+                Err(PolicyError::InvalidPrefix(_))
+            ));
+        }
+
+        let unknown_type = POLICY.replace(
+            "\"image/png\"]\n        max_size",
+            "\"image/png\", \"image/tiff\"]\n        max_size",
+        );
+        assert!(matches!(
+            parse(&unknown_type),
+            Err(PolicyError::UnknownFileType(_, file_type)) if file_type == "image/tiff"
+        ));
+
+        let invalid_sizes = POLICY.replace("min_size = 1", "min_size = 1\n        max_size = 0");
+        assert!(matches!(
+            parse(&invalid_sizes),
+            Err(PolicyError::InvalidSizes(_))
+        ));
+
+        let invalid_limits = POLICY.replace("max_frames = 50", "max_frames = 0");
+        assert!(matches!(
+            parse(&invalid_limits),
+            Err(PolicyError::InvalidImageLimits(_))
+        ));
+
+        for retention_days in ["0", "2147483648"] {
+            let invalid_retention = POLICY.replace(
+                "retention_days = 30",
+                &format!("retention_days = {}", retention_days),
+            );
+            assert!(matches!(
+                parse(&invalid_retention),
+                Err(PolicyError::InvalidRetention(_))
+            ));
+        }
+
+        let invalid_cache_control = POLICY.replace("immutable\"", "immutable\\n\"");
+        assert!(matches!(
+            parse(&invalid_cache_control),
+            Err(PolicyError::InvalidCacheControl(_))
+        ));
+
+        for derivative in ["{ w = 30 }", "{}", "{ width = 32 }"] {
+            let invalid_derivative = POLICY.replace(
+                "[{ w = 32, h = 32, fit = \"cover\", format = \"webp\" }]",
+                &format!("[{}]", derivative),
+            );
+            assert!(
+                matches!(
+                    parse(&invalid_derivative),
+                    Err(PolicyError::InvalidDerivative(..) | PolicyError::Parse(_))
+                ),
+                "{}",
+                derivative
+            );
+        }
+
+        for bucket in ["Beep", "be", "-beep", "beep_attachments"] {
+            let invalid_bucket = POLICY.replace("\"beep-attachments\"", &format!("{:?}", bucket));
+            assert!(
+                matches!(parse(&invalid_bucket), Err(PolicyError::InvalidBucket(..))),
+                "{}",
+                bucket
+            );
+        }
+        for key_prefix in ["", "/uploads", "uploads/", "uploads//v1", "uploads/../v1"] {
+            let invalid_key_prefix = POLICY.replace("\"uploads/v1\"", &format!("{:?}", key_prefix));
+            assert!(
+                matches!(
+                    parse(&invalid_key_prefix),
+                    Err(PolicyError::InvalidKeyPrefix(..))
+                ),
+                "{}",
+                key_prefix
+            );
+        }
+
+        let unknown_field = POLICY.replace("public = true", "publicly = true");
+        assert!(matches!(parse(&unknown_field), Err(PolicyError::Parse(_))));
+    }
+}
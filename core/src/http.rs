@@ -44,7 +44,9 @@ pub fn default_cors_layer(origins: &[String]) -> Result<CorsLayer, CoreError> {
             header::AUTHORIZATION,
             header::ACCEPT,
             header::ORIGIN,
+            header::RANGE,
         ])
+        .expose_headers([header::ACCEPT_RANGES, header::CONTENT_RANGE])
         .allow_origin(origins))
 }
 
@@ -0,0 +1,247 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, ImageReader};
+
+use crate::images::ImageError;
+
+/// Largest images accepted by a prefix, checked against the headers of the
+/// uploads so that images which would take too much memory to decode are
+/// refused without being decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImageLimits {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Maximum width times height
+    pub max_pixels: Option<u64>,
+    /// Maximum number of frames of a GIF
+    pub max_frames: Option<u32>,
+}
+
+/// Formats whose headers are checked against the limits
+pub fn limited_format(media_type: &str) -> Option<ImageFormat> {
+    match media_type {
+        "image/png" => Some(ImageFormat::Png),
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/gif" => Some(ImageFormat::Gif),
+        "image/webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+impl ImageLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Checks the dimensions declared by an image, and for GIFs the number
+    /// and dimensions of their frames, which can exceed those of the image.
+    pub fn check(&self, data: &[u8], format: ImageFormat) -> Result<(), ImageError> {
+        let dimensions = ImageReader::with_format(Cursor::new(data), format)
+            .into_dimensions()
+            .map_err(|e| ImageError::Decode(e.to_string()))?;
+        self.check_dimensions(dimensions)?;
+
+        if format == ImageFormat::Gif {
+            let frames = gif_frames(data)?;
+            if let Some(max_frames) = self.max_frames
+                && frames.len() > max_frames as usize
+            {
+                return Err(ImageError::LimitExceeded(format!(
+                    "{} frames, the maximum is {}",
+                    frames.len(),
+                    max_frames
+                )));
+            }
+            for frame in frames {
+                self.check_dimensions(frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_dimensions(&self, (width, height): (u32, u32)) -> Result<(), ImageError> {
+        if let Some(max_width) = self.max_width
+            && width > max_width
+        {
+            return Err(ImageError::LimitExceeded(format!(
+                "{} pixels wide, the maximum is {}",
+                width, max_width
+            )));
+        }
+        if let Some(max_height) = self.max_height
+            && height > max_height
+        {
+            return Err(ImageError::LimitExceeded(format!(
+                "{} pixels high, the maximum is {}",
+                height, max_height
+            )));
+        }
+        let pixels = width as u64 * height as u64;
+        if let Some(max_pixels) = self.max_pixels
+            && pixels > max_pixels
+        {
+            return Err(ImageError::LimitExceeded(format!(
+                "{} pixels, the maximum is {}",
+                pixels, max_pixels
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Walks the blocks of a GIF, without decompressing them, to list the
+/// dimensions of its frames
+fn gif_frames(data: &[u8]) -> Result<Vec<(u32, u32)>, ImageError> {
+    let invalid = || ImageError::Decode("Invalid GIF structure".to_string());
+    let byte = |position: usize| data.get(position).copied().ok_or_else(invalid);
+    let color_table_length = |flags: u8| {
+        if flags & 0x80 != 0 {
+            3 * (1 << ((flags & 0x07) + 1))
+        } else {
+            0
+        }
+    };
+    // Data sub-blocks are prefixed by their length and end with an empty one
+    let skip_sub_blocks = |mut position: usize| -> Result<usize, ImageError> {
+        loop {
+            let length = byte(position)? as usize;
+            position += 1 + length;
+            if length == 0 {
+                return Ok(position);
+            }
+        }
+    };
+
+    if !data.starts_with(b"GIF87a") && !data.starts_with(b"GIF89a") {
+        return Err(invalid());
+    }
+    // Header, then logical screen descriptor and global color table
+    let mut position = 13 + color_table_length(byte(10)?);
+    let mut frames = Vec::new();
+
+    // Some encoders omit the trailer, which is tolerated by decoders
+    while position < data.len() {
+        match byte(position)? {
+            // Extension, made of a label and sub-blocks
+            0x21 => position = skip_sub_blocks(position + 2)?,
+            // Image descriptor, then local color table, LZW code size and sub-blocks
+            0x2C => {
+                let descriptor = data.get(position + 1..position + 10).ok_or_else(invalid)?;
+                let width = u16::from_le_bytes([descriptor[4], descriptor[5]]) as u32;
+                let height = u16::from_le_bytes([descriptor[6], descriptor[7]]) as u32;
+                frames.push((width, height));
+                position = skip_sub_blocks(position + 11 + color_table_length(descriptor[8]))?;
+            }
+            // Trailer
+            0x3B => break,
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::variants::tests::fake_png;
+
+    /// A GIF of `frames` frames of a single pixel, in a logical screen of `width`x`height`
+    fn fake_gif(width: u16, height: u16, frames: usize) -> Vec<u8> {
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&width.to_le_bytes());
+        gif.extend_from_slice(&height.to_le_bytes());
+        // Global color table of two colors
+        gif.extend_from_slice(&[0x80, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF]);
+        for _ in 0..frames {
+            // Graphic control extension
+            gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00, 0x0A, 0x00, 0x00, 0x00]);
+            gif.extend_from_slice(&[0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0x00]);
+            gif.extend_from_slice(&[0x02, 0x02, 0x44, 0x01, 0x00]);
+        }
+        gif.push(0x3B);
+        gif
+    }
+
+    /// A PNG whose header declares `width`x`height` pixels
+    fn fake_png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut png = fake_png(1, 1);
+        png[16..20].copy_from_slice(&width.to_be_bytes());
+        png[20..24].copy_from_slice(&height.to_be_bytes());
+        // CRC of the chunk type and data of the IHDR chunk
+        let crc = !png[12..29].iter().fold(!0u32, |mut crc, byte| {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+            crc
+        });
+        png[29..33].copy_from_slice(&crc.to_be_bytes());
+        png
+    }
+
+    fn limits() -> ImageLimits {
+        ImageLimits {
+            max_width: Some(4096),
+            max_height: Some(4096),
+            max_pixels: Some(4096 * 2048),
+            max_frames: Some(10),
+        }
+    }
+
+    #[test]
+    fn test_gif_frames() {
+        assert_eq!(
+            gif_frames(&fake_gif(4, 4, 3)).expect("Invalid GIF").len(),
+            3
+        );
+        let mut truncated = fake_gif(4, 4, 3);
+        truncated.truncate(30);
+        assert!(gif_frames(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_check_limits() {
+        assert!(
+            limits()
+                .check(&fake_png_header(1024, 1024), ImageFormat::Png)
+                .is_ok()
+        );
+        assert!(
+            limits()
+                .check(&fake_gif(4, 4, 10), ImageFormat::Gif)
+                .is_ok()
+        );
+
+        for (image, format) in [
+            (fake_png_header(50000, 50000), ImageFormat::Png),
+            (fake_png_header(4097, 1), ImageFormat::Png),
+            (fake_png_header(4096, 4096), ImageFormat::Png),
+            (fake_gif(4, 4, 11), ImageFormat::Gif),
+        ] {
+            assert!(matches!(
+                limits().check(&image, format),
+                Err(ImageError::LimitExceeded(_))
+            ));
+        }
+        assert!(matches!(
+            limits().check(b"\x89PNG", ImageFormat::Png),
+            Err(ImageError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_gif_frame_larger_than_screen() {
+        let mut gif = fake_gif(4, 4, 1);
+        // Width of the frame descriptor following the graphic control extension
+        let descriptor = gif.iter().position(|b| *b == 0x2C).expect("No frame");
+        gif[descriptor + 5..descriptor + 7].copy_from_slice(&60000u16.to_le_bytes());
+        assert!(matches!(
+            limits().check(&gif, ImageFormat::Gif),
+            Err(ImageError::LimitExceeded(_))
+        ));
+    }
+}
@@ -0,0 +1,342 @@
+use std::io::Cursor;
+
+use image::{
+    DynamicImage, ImageDecoder, ImageFormat, ImageReader, codecs::jpeg::JpegEncoder,
+    metadata::Orientation,
+};
+
+use crate::images::ImageError;
+
+/// Quality of the JPEG images encoded again to apply their orientation
+const JPEG_QUALITY: u8 = 90;
+
+/// Ancillary PNG chunks needed to render an image, the other ones are
+/// dropped since they can carry metadata (text, EXIF, timestamps...).
+const PNG_RENDERING_CHUNKS: [&[u8; 4]; 15] = [
+    b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"pHYs", b"bKGD", b"hIST", b"sPLT",
+    b"acTL", b"fcTL", b"fdAT", b"cICP", b"cLLi",
+];
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// VP8X flags telling that a WebP image has EXIF and XMP chunks
+const WEBP_EXIF_FLAG: u8 = 0x08;
+const WEBP_XMP_FLAG: u8 = 0x04;
+
+/// Formats whose metadata can be stripped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl MetadataFormat {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "image/jpeg" => Some(MetadataFormat::Jpeg),
+            "image/png" => Some(MetadataFormat::Png),
+            "image/webp" => Some(MetadataFormat::Webp),
+            _ => None,
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            MetadataFormat::Jpeg => ImageFormat::Jpeg,
+            MetadataFormat::Png => ImageFormat::Png,
+            MetadataFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Removes the EXIF, XMP, IPTC and text metadata of an image.
+/// Images are left as is apart from their metadata, except for those with
+/// an EXIF orientation, which is applied to their pixels as it would be lost
+/// otherwise. Those are encoded again, losslessly but for JPEG images.
+pub fn strip_metadata(data: &[u8], format: MetadataFormat) -> Result<Vec<u8>, ImageError> {
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format.image_format())
+        .into_decoder()
+        .map_err(|e| ImageError::Decode(e.to_string()))?;
+    let orientation = decoder
+        .orientation()
+        .map_err(|e| ImageError::Decode(e.to_string()))?;
+    if orientation != Orientation::NoTransforms {
+        let mut image =
+            DynamicImage::from_decoder(decoder).map_err(|e| ImageError::Decode(e.to_string()))?;
+        image.apply_orientation(orientation);
+        return encode(image, format);
+    }
+
+    match format {
+        MetadataFormat::Jpeg => strip_jpeg(data),
+        MetadataFormat::Png => strip_png(data),
+        MetadataFormat::Webp => strip_webp(data),
+    }
+}
+
+fn encode(image: DynamicImage, format: MetadataFormat) -> Result<Vec<u8>, ImageError> {
+    let mut encoded = Cursor::new(Vec::new());
+    match format {
+        MetadataFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY)),
+        MetadataFormat::Png => image.write_to(&mut encoded, ImageFormat::Png),
+        MetadataFormat::Webp => {
+            DynamicImage::ImageRgba8(image.to_rgba8()).write_to(&mut encoded, ImageFormat::WebP)
+        }
+    }
+    .map_err(|e| ImageError::Encode(e.to_string()))?;
+    Ok(encoded.into_inner())
+}
+
+fn invalid(format: &str) -> ImageError {
+    ImageError::Decode(format!("Invalid {} structure", format))
+}
+
+/// Drops the APP1 (EXIF, XMP), APP13 (IPTC) and comment segments along with
+/// the application segments not needed to render the image. APP0 (JFIF),
+/// APP2 (ICC profile) and APP14 (Adobe color transform) are kept.
+fn strip_jpeg(data: &[u8]) -> Result<Vec<u8>, ImageError> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(invalid("JPEG"));
+    }
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(&data[..2]);
+    let mut position = 2;
+
+    loop {
+        // Markers may be preceded by any number of fill bytes
+        while data.get(position) == Some(&0xFF) && data.get(position + 1) == Some(&0xFF) {
+            position += 1;
+        }
+        let (&prefix, &marker) = data
+            .get(position)
+            .zip(data.get(position + 1))
+            .ok_or_else(|| invalid("JPEG"))?;
+        if prefix != 0xFF {
+            return Err(invalid("JPEG"));
+        }
+        match marker {
+            // End of image, or markers without a length
+            0xD9 => {
+                stripped.extend_from_slice(&data[position..position + 2]);
+                return Ok(stripped);
+            }
+            0x01 | 0xD0..=0xD7 => {
+                stripped.extend_from_slice(&data[position..position + 2]);
+                position += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let length = data
+            .get(position + 2..position + 4)
+            .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+            .filter(|length| *length >= 2)
+            .ok_or_else(|| invalid("JPEG"))?;
+        let end = position + 2 + length;
+        if end > data.len() {
+            return Err(invalid("JPEG"));
+        }
+
+        let metadata = matches!(marker, 0xE1 | 0xE3..=0xED | 0xEF | 0xFE);
+        if !metadata {
+            stripped.extend_from_slice(&data[position..end]);
+        }
+        position = end;
+
+        // The entropy coded data after the start of scan holds no metadata
+        if marker == 0xDA {
+            stripped.extend_from_slice(&data[position..]);
+            return Ok(stripped);
+        }
+    }
+}
+
+fn strip_png(data: &[u8]) -> Result<Vec<u8>, ImageError> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return Err(invalid("PNG"));
+    }
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(PNG_SIGNATURE);
+    let mut position = PNG_SIGNATURE.len();
+
+    while position < data.len() {
+        let header = data
+            .get(position..position + 8)
+            .ok_or_else(|| invalid("PNG"))?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk_type: &[u8; 4] = header[4..8].try_into().map_err(|_| invalid("PNG"))?;
+        // Length, type, data and CRC
+        let end = position + 12 + length;
+        if end > data.len() {
+            return Err(invalid("PNG"));
+        }
+
+        // Critical chunks have an uppercase first letter
+        if chunk_type[0].is_ascii_uppercase() || PNG_RENDERING_CHUNKS.contains(&chunk_type) {
+            stripped.extend_from_slice(&data[position..end]);
+        }
+        position = end;
+        if chunk_type == b"IEND" {
+            return Ok(stripped);
+        }
+    }
+    Err(invalid("PNG"))
+}
+
+/// Drops the EXIF and XMP chunks of a WebP image and clears their VP8X flags
+fn strip_webp(data: &[u8]) -> Result<Vec<u8>, ImageError> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err(invalid("WebP"));
+    }
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(&data[..12]);
+    let mut position = 12;
+
+    while position < data.len() {
+        let header = data
+            .get(position..position + 8)
+            .ok_or_else(|| invalid("WebP"))?;
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        // Chunks are padded to an even length
+        let end = (position + 8 + length + length % 2).min(data.len());
+        if position + 8 + length > data.len() {
+            return Err(invalid("WebP"));
+        }
+
+        match &header[..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = stripped.len();
+                stripped.extend_from_slice(&data[position..end]);
+                if let Some(flags) = stripped.get_mut(start + 8) {
+                    *flags &= !(WEBP_EXIF_FLAG | WEBP_XMP_FLAG);
+                }
+            }
+            _ => stripped.extend_from_slice(&data[position..end]),
+        }
+        position = end;
+    }
+
+    let riff_length = u32::try_from(stripped.len() - 8).map_err(|_| invalid("WebP"))?;
+    stripped[4..8].copy_from_slice(&riff_length.to_le_bytes());
+    Ok(stripped)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use image::{GenericImageView, RgbImage};
+
+    use super::*;
+    use crate::images::variants::tests::fake_png;
+
+    /// A minimal EXIF chunk holding only an orientation
+    fn exif_with_orientation(orientation: u8) -> Vec<u8> {
+        let mut exif = b"MM\x00\x2A\x00\x00\x00\x08\x00\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        exif.extend_from_slice(&[0x00, orientation, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        exif
+    }
+
+    /// A black JPEG image with a comment, and an EXIF orientation when given
+    pub fn fake_jpeg(width: u32, height: u32, orientation: Option<u8>) -> Vec<u8> {
+        let mut jpeg = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut jpeg, ImageFormat::Jpeg)
+            .expect("Invalid image");
+        let jpeg = jpeg.into_inner();
+
+        let mut segments = Vec::new();
+        if let Some(orientation) = orientation {
+            let exif = [b"Exif\x00\x00".to_vec(), exif_with_orientation(orientation)].concat();
+            segments.extend_from_slice(&[0xFF, 0xE1]);
+            segments.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+            segments.extend_from_slice(&exif);
+        }
+        let comment = b"Taken at 48.8584 N, 2.2945 E";
+        segments.extend_from_slice(&[0xFF, 0xFE]);
+        segments.extend_from_slice(&(comment.len() as u16 + 2).to_be_bytes());
+        segments.extend_from_slice(comment);
+
+        [&jpeg[..2], &segments, &jpeg[2..]].concat()
+    }
+
+    fn contains(data: &[u8], pattern: &[u8]) -> bool {
+        data.windows(pattern.len()).any(|window| window == pattern)
+    }
+
+    #[test]
+    fn test_strip_jpeg() {
+        let jpeg = fake_jpeg(4, 2, Some(1));
+        let stripped = strip_metadata(&jpeg, MetadataFormat::Jpeg).expect("Strip failed");
+        assert!(!contains(&stripped, b"Exif"));
+        assert!(!contains(&stripped, b"48.8584"));
+        let image = image::load_from_memory_with_format(&stripped, ImageFormat::Jpeg)
+            .expect("Invalid image");
+        assert_eq!(image.dimensions(), (4, 2));
+    }
+
+    #[test]
+    fn test_strip_jpeg_applies_orientation() {
+        // 6 rotates the image by 90 degrees clockwise
+        let jpeg = fake_jpeg(4, 2, Some(6));
+        let stripped = strip_metadata(&jpeg, MetadataFormat::Jpeg).expect("Strip failed");
+        assert!(!contains(&stripped, b"Exif"));
+        let image = image::load_from_memory_with_format(&stripped, ImageFormat::Jpeg)
+            .expect("Invalid image");
+        assert_eq!(image.dimensions(), (2, 4));
+    }
+
+    #[test]
+    fn test_strip_png() {
+        let png = fake_png(4, 2);
+        // Insert a text chunk after IHDR, whose chunk is 25 bytes long
+        let text = b"tEXtComment\x00Taken at 48.8584 N";
+        let mut chunk = ((text.len() - 4) as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(text);
+        chunk.extend_from_slice(&[0, 0, 0, 0]);
+        let png = [&png[..33], &chunk, &png[33..]].concat();
+
+        let stripped = strip_metadata(&png, MetadataFormat::Png).expect("Strip failed");
+        assert!(!contains(&stripped, b"48.8584"));
+        assert_eq!(stripped, fake_png(4, 2));
+    }
+
+    #[test]
+    fn test_strip_webp() {
+        let exif = exif_with_orientation(1);
+        let mut webp = b"RIFF\x00\x00\x00\x00WEBP".to_vec();
+        webp.extend_from_slice(b"VP8X\x0A\x00\x00\x00");
+        webp.extend_from_slice(&[WEBP_EXIF_FLAG, 0, 0, 0, 3, 0, 0, 1, 0, 0]);
+        let mut image = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(image::RgbaImage::new(4, 2))
+            .write_to(&mut image, ImageFormat::WebP)
+            .expect("Invalid image");
+        webp.extend_from_slice(&image.into_inner()[12..]);
+        webp.extend_from_slice(b"EXIF");
+        webp.extend_from_slice(&(exif.len() as u32).to_le_bytes());
+        webp.extend_from_slice(&exif);
+        let riff_length = (webp.len() - 8) as u32;
+        webp[4..8].copy_from_slice(&riff_length.to_le_bytes());
+
+        let stripped = strip_webp(&webp).expect("Strip failed");
+        assert!(!contains(&stripped, b"EXIF"));
+        assert_eq!(stripped[20] & WEBP_EXIF_FLAG, 0);
+        let image = image::load_from_memory_with_format(&stripped, ImageFormat::WebP)
+            .expect("Invalid image");
+        assert_eq!(image.dimensions(), (4, 2));
+    }
+
+    #[test]
+    fn test_strip_invalid_image() {
+        assert!(matches!(
+            strip_metadata(b"GIF89a", MetadataFormat::Png),
+            Err(ImageError::Decode(_))
+        ));
+        let mut png = fake_png(4, 2);
+        png.truncate(40);
+        assert!(strip_png(&png).is_err());
+    }
+}
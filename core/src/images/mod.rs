@@ -0,0 +1,36 @@
+use thiserror::Error;
+
+use crate::error::ApiError;
+
+pub mod limits;
+pub mod metadata;
+pub mod variants;
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error("Invalid variant: {0}")]
+    InvalidVariant(String),
+    #[error("Variants are only available for PNG, JPEG and GIF images")]
+    NotAnImage,
+    #[error("Cannot decode the image: {0}")]
+    Decode(String),
+    #[error("Cannot encode the variant: {0}")]
+    Encode(String),
+    #[error("Image too large: {0}")]
+    LimitExceeded(String),
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<ApiError> for ImageError {
+    fn into(self) -> ApiError {
+        match self {
+            ImageError::InvalidVariant(_) | ImageError::NotAnImage => {
+                ApiError::BadRequest(self.to_string())
+            }
+            ImageError::LimitExceeded(_) => ApiError::UnProcessableEntity(self.to_string()),
+            ImageError::Decode(_) | ImageError::Encode(_) => {
+                ApiError::InternalServerError(self.to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, imageops::FilterType};
+use serde::Deserialize;
+
+use crate::images::ImageError;
+
+/// Bounds of the sides of a variant, in pixels. Sides must also be powers of
+/// two, which bounds the number of variants cached for a single image.
+pub const MIN_VARIANT_SIDE: u32 = 16;
+pub const MAX_VARIANT_SIDE: u32 = 4096;
+
+/// How the image is fitted in the `w`x`h` box of a variant
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Covers the whole box, cropping what overflows it
+    Cover,
+    /// Fits inside the box, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// Stretches to the box
+    Fill,
+}
+
+impl Fit {
+    fn as_str(self) -> &'static str {
+        match self {
+            Fit::Cover => "cover",
+            Fit::Contain => "contain",
+            Fit::Fill => "fill",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl VariantFormat {
+    /// Formats variants can be rendered from
+    pub fn from_source(content_type: &str) -> Option<Self> {
+        match content_type {
+            "image/png" => Some(VariantFormat::Png),
+            "image/jpeg" => Some(VariantFormat::Jpeg),
+            "image/gif" => Some(VariantFormat::Gif),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            VariantFormat::Png => "image/png",
+            VariantFormat::Jpeg => "image/jpeg",
+            VariantFormat::Gif => "image/gif",
+            VariantFormat::Webp => "image/webp",
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            VariantFormat::Png => "png",
+            VariantFormat::Jpeg => "jpeg",
+            VariantFormat::Gif => "gif",
+            VariantFormat::Webp => "webp",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            VariantFormat::Png => "png",
+            VariantFormat::Jpeg => "jpg",
+            VariantFormat::Gif => "gif",
+            VariantFormat::Webp => "webp",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            VariantFormat::Png => ImageFormat::Png,
+            VariantFormat::Jpeg => ImageFormat::Jpeg,
+            VariantFormat::Gif => ImageFormat::Gif,
+            VariantFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Query params describing a resized or converted variant of an image,
+/// e.g. `?w=128&h=128&fit=cover&format=webp`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct VariantParams {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fit: Option<Fit>,
+    pub format: Option<VariantFormat>,
+}
+
+impl VariantParams {
+    /// Whether the original object is requested rather than a variant
+    pub fn is_empty(&self) -> bool {
+        self.w.is_none() && self.h.is_none() && self.fit.is_none() && self.format.is_none()
+    }
+
+    pub fn validate(&self) -> Result<(), ImageError> {
+        for side in [self.w, self.h].into_iter().flatten() {
+            if !(MIN_VARIANT_SIDE..=MAX_VARIANT_SIDE).contains(&side) || !side.is_power_of_two() {
+                return Err(ImageError::InvalidVariant(format!(
+                    "sides must be powers of two between {} and {}",
+                    MIN_VARIANT_SIDE, MAX_VARIANT_SIDE
+                )));
+            }
+        }
+        if self.fit.is_some() && self.w.is_none() && self.h.is_none() {
+            return Err(ImageError::InvalidVariant(
+                "fit requires a width or a height".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Query string requesting this variant from the public route
+    pub fn query(&self) -> String {
+        let mut query = Vec::new();
+        if let Some(w) = self.w {
+            query.push(format!("w={}", w));
+        }
+        if let Some(h) = self.h {
+            query.push(format!("h={}", h));
+        }
+        if let Some(fit) = self.fit {
+            query.push(format!("fit={}", fit.as_str()));
+        }
+        if let Some(format) = self.format {
+            query.push(format!("format={}", format.as_str()));
+        }
+        query.join("&")
+    }
+
+    /// Key the variant of the object at `key` is cached under. Object keys are
+    /// made of a single `/`, so variant keys can't collide with uploads.
+    /// `source_tag` is the ETag of the source, replacing the source changes
+    /// the key of its variants rather than serving stale ones.
+    pub fn key(&self, key: &str, source_tag: &str, source: VariantFormat) -> String {
+        let mut name: String = source_tag
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        if let Some(w) = self.w {
+            name.push_str(&format!("-w{}", w));
+        }
+        if let Some(h) = self.h {
+            name.push_str(&format!("-h{}", h));
+        }
+        if self.w.is_some() || self.h.is_some() {
+            name.push('-');
+            name.push_str(self.fit.unwrap_or_default().as_str());
+        }
+        format!(
+            "{}/variants/{}.{}",
+            key,
+            name,
+            self.format.unwrap_or(source).extension()
+        )
+    }
+
+    /// Renders the variant of an image, returning it along with its format.
+    /// The EXIF orientation of the image is applied, and animated GIFs are
+    /// reduced to their first frame.
+    pub fn render(
+        &self,
+        data: &[u8],
+        source: VariantFormat,
+    ) -> Result<(Vec<u8>, VariantFormat), ImageError> {
+        let mut decoder = ImageReader::with_format(Cursor::new(data), source.image_format())
+            .into_decoder()
+            .map_err(|e| ImageError::Decode(e.to_string()))?;
+        let orientation = decoder
+            .orientation()
+            .map_err(|e| ImageError::Decode(e.to_string()))?;
+        let mut image =
+            DynamicImage::from_decoder(decoder).map_err(|e| ImageError::Decode(e.to_string()))?;
+        image.apply_orientation(orientation);
+        let image = self.resize(image);
+
+        let format = self.format.unwrap_or(source);
+        // JPEG has no alpha channel, the other encoders take 8 bits RGBA
+        let image = match format {
+            VariantFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()),
+            _ => DynamicImage::ImageRgba8(image.to_rgba8()),
+        };
+
+        let mut variant = Cursor::new(Vec::new());
+        image
+            .write_to(&mut variant, format.image_format())
+            .map_err(|e| ImageError::Encode(e.to_string()))?;
+        Ok((variant.into_inner(), format))
+    }
+
+    fn resize(&self, image: DynamicImage) -> DynamicImage {
+        let filter = FilterType::Lanczos3;
+        match (self.w, self.h, self.fit.unwrap_or_default()) {
+            (None, None, _) => image,
+            (Some(w), Some(h), Fit::Cover) => image.resize_to_fill(w, h, filter),
+            (w, h, Fit::Fill) => image.resize_exact(
+                w.unwrap_or(image.width()),
+                h.unwrap_or(image.height()),
+                filter,
+            ),
+            // A single side is covered by fitting the image to that side
+            (w, h, _) => image.resize(w.unwrap_or(u32::MAX), h.unwrap_or(u32::MAX), filter),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use image::{GenericImageView, RgbaImage};
+
+    use super::*;
+
+    /// A transparent PNG image of `width`x`height` pixels
+    pub fn fake_png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(RgbaImage::new(width, height))
+            .write_to(&mut png, ImageFormat::Png)
+            .expect("Invalid image");
+        png.into_inner()
+    }
+
+    fn params(w: Option<u32>, h: Option<u32>, fit: Option<Fit>) -> VariantParams {
+        VariantParams {
+            w,
+            h,
+            fit,
+            format: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(
+            params(Some(128), Some(64), Some(Fit::Cover))
+                .validate()
+                .is_ok()
+        );
+        assert!(params(Some(100), None, None).validate().is_err());
+        assert!(params(Some(8), None, None).validate().is_err());
+        assert!(params(None, Some(8192), None).validate().is_err());
+        assert!(params(None, None, Some(Fit::Cover)).validate().is_err());
+    }
+
+    #[test]
+    fn test_key() {
+        let variant = VariantParams {
+            format: Some(VariantFormat::Webp),
+            ..params(Some(128), Some(128), Some(Fit::Cover))
+        };
+        assert_eq!(
+            variant.key("profile_picture/me.png", "\"ab-12\"", VariantFormat::Png),
+            "profile_picture/me.png/variants/ab12-w128-h128-cover.webp"
+        );
+        assert_eq!(variant.query(), "w=128&h=128&fit=cover&format=webp");
+        assert_eq!(
+            params(Some(64), None, None).key("profile_picture/me.png", "ab", VariantFormat::Png),
+            "profile_picture/me.png/variants/ab-w64-contain.png"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let source = fake_png(400, 200);
+        for (fit, dimensions) in [
+            (Fit::Cover, (64, 64)),
+            (Fit::Contain, (64, 32)),
+            (Fit::Fill, (64, 64)),
+        ] {
+            let (variant, format) = params(Some(64), Some(64), Some(fit))
+                .render(&source, VariantFormat::Png)
+                .expect("Render failed");
+            assert_eq!(format, VariantFormat::Png);
+            let variant = image::load_from_memory(&variant).expect("Invalid variant");
+            assert_eq!(variant.dimensions(), dimensions, "{:?}", fit);
+        }
+
+        let (variant, format) = VariantParams {
+            format: Some(VariantFormat::Jpeg),
+            ..params(None, Some(32), None)
+        }
+        .render(&source, VariantFormat::Png)
+        .expect("Render failed");
+        assert_eq!(format, VariantFormat::Jpeg);
+        let variant = image::load_from_memory_with_format(&variant, ImageFormat::Jpeg)
+            .expect("Invalid variant");
+        assert_eq!(variant.dimensions(), (64, 32));
+
+        assert!(matches!(
+            params(Some(64), None, None).render(b"not a png", VariantFormat::Png),
+            Err(ImageError::Decode(_))
+        ));
+    }
+}
@@ -8,10 +8,9 @@ use crate::{
     guards::{FileType, Guard, GuardsBuilder},
     healthcheck::router::healthcheck_router,
     plumbing::create_service,
-    prefixes::Prefix,
     s3::{Garage, S3},
     signed_url::service::HMACUrlService,
-    signer::HMACSigner,
+    signer::KeyringSigner,
     utils::get_time,
 };
 
@@ -26,11 +25,14 @@ async fn test_probe_with_real_s3() {
     let res = s3.show_buckets().await;
     assert!(res.is_ok());
 
-    let content_service =
-        Arc::new(create_service(config.clone()).expect("Service creation failed"));
+    let content_service = Arc::new(
+        create_service(config.clone())
+            .await
+            .expect("Service creation failed"),
+    );
     let signer_service = Arc::new(
         HMACUrlService::new(
-            HMACSigner::new(config.key_id.clone()).expect("Invalid signing key"),
+            KeyringSigner::from_config(&config).expect("Invalid signing key"),
             get_time(),
             "https://beep.com".to_string(),
         )
@@ -38,10 +40,7 @@ async fn test_probe_with_real_s3() {
     );
     let guards = Arc::new(
         GuardsBuilder::new()
-            .add(
-                Prefix::ProfilePicture,
-                Guard::new(vec![FileType::ImageJPEG]),
-            )
+            .add("profile_picture", Guard::new(vec![FileType::ImageJPEG]))
             .build(),
     );
     let app_state = AppState::new(content_service, config.clone(), signer_service, guards);
@@ -1,5 +1,7 @@
+use axum::body::Body;
+
 use crate::config::tests::bootstrap_integration_tests;
-use crate::s3::{FileObject, Garage, S3};
+use crate::s3::{FileObject, Garage, S3, S3Error, UploadedPart};
 
 fn setup_s3() -> Garage {
     let config = bootstrap_integration_tests();
@@ -20,8 +22,9 @@ async fn test_show_buckets() {
 async fn test_put_object() {
     let s3 = setup_s3();
     let file = FileObject {
-        data: vec![1, 2, 3],
+        data: Body::from(vec![1, 2, 3]),
         content_type: "application/octet-stream".to_string(),
+        content_length: 3,
     };
     let res = s3.put_object("test", "test.txt", file).await;
     assert!(res.is_ok());
@@ -31,8 +34,9 @@ async fn test_put_object() {
 async fn test_put_object_with_prefix() {
     let s3 = setup_s3();
     let file = FileObject {
-        data: vec![1, 2, 3],
+        data: Body::from(vec![1, 2, 3]),
         content_type: "application/octet-stream".to_string(),
+        content_length: 3,
     };
     let res = s3.put_object("test", "tkt/test.txt", file).await;
     assert!(res.is_ok());
@@ -42,11 +46,12 @@ async fn test_put_object_with_prefix() {
 async fn test_get_object() {
     let s3 = setup_s3();
     let file = FileObject {
-        data: vec![1, 2, 3],
+        data: Body::from(vec![1, 2, 3]),
         content_type: "application/octet-stream".to_string(),
+        content_length: 3,
     };
     let _ = s3.put_object("test", "test2.txt", file).await;
-    let res = s3.get_object("test", "test2.txt").await;
+    let res = s3.get_object("test", "test2.txt", None).await;
     assert!(res.is_ok());
 }
 
@@ -54,16 +59,77 @@ async fn test_get_object() {
 async fn test_mime_types_on_object() {
     let s3 = setup_s3();
     let file = FileObject {
-        data: "test".as_bytes().to_vec(),
+        data: Body::from("test"),
         content_type: "text/plain".to_string(),
+        content_length: 4,
     };
     let _ = s3
         .put_object("test", "test4.txt", file)
         .await
         .expect("should upload the file");
-    let (_, mime_type) = s3
-        .get_object("test", "test4.txt")
+    let object = s3
+        .get_object("test", "test4.txt", None)
+        .await
+        .expect("should be able to retrieve file");
+    assert_eq!(object.content_type, "text/plain".to_string());
+}
+
+#[tokio::test]
+async fn test_head_object() {
+    let s3 = setup_s3();
+    let file = FileObject {
+        data: Body::from("test"),
+        content_type: "text/plain".to_string(),
+        content_length: 4,
+    };
+    let _ = s3
+        .put_object("test", "test5.txt", file)
+        .await
+        .expect("should upload the file");
+    let metadata = s3
+        .head_object("test", "test5.txt")
+        .await
+        .expect("should be able to retrieve metadata");
+    assert_eq!(metadata.content_type, "text/plain".to_string());
+    assert_eq!(metadata.content_length, 4);
+    assert!(metadata.e_tag.is_some());
+
+    let res = s3.head_object("test", "missing.txt").await;
+    assert!(matches!(res, Err(S3Error::ObjectNotFound(_))));
+}
+
+#[tokio::test]
+async fn test_multipart_upload() {
+    let s3 = setup_s3();
+    let upload_id = s3
+        .create_multipart_upload("test", "multipart.txt", "text/plain")
+        .await
+        .expect("should create the upload");
+    let e_tag = s3
+        .upload_part(
+            "test",
+            "multipart.txt",
+            &upload_id,
+            1,
+            Body::from("test"),
+            4,
+        )
+        .await
+        .expect("should upload the part");
+    s3.complete_multipart_upload(
+        "test",
+        "multipart.txt",
+        &upload_id,
+        vec![UploadedPart {
+            part_number: 1,
+            e_tag,
+        }],
+    )
+    .await
+    .expect("should complete the upload");
+    let object = s3
+        .get_object("test", "multipart.txt", None)
         .await
         .expect("should be able to retrieve file");
-    assert_eq!(mime_type, "text/plain".to_string());
+    assert_eq!(object.content_length, 4);
 }
@@ -1,21 +1,31 @@
 use std::sync::Arc;
 
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
-    app::AppState, config::Config, error::CoreError, guards::GuardsBuilder,
-    plumbing::create_service, prefixes::Prefix, signed_url::service::HMACUrlService,
-    signer::HMACSigner, utils::RealTime,
+    app::AppState,
+    config::Config,
+    error::CoreError,
+    guards::policy,
+    plumbing::{ContentService, Service, create_service},
+    s3::S3,
+    signed_url::service::HMACUrlService,
+    signer::KeyringSigner,
+    utils::RealTime,
 };
 
+pub use s3::{Backend, Filesystem, Memory, memory::Operation};
+
 mod app;
+pub mod auth;
 pub mod config;
 pub mod error;
 mod healthcheck;
 mod http;
+mod images;
 mod openapi;
 mod plumbing;
-mod prefixes;
+mod range;
 mod router;
 mod s3;
 mod signed_url;
@@ -33,51 +43,66 @@ mod router_test;
 mod integrations;
 
 pub async fn app(config: Arc<Config>, time: RealTime) -> Result<(), CoreError> {
-    let content_service = Arc::new(
-        create_service(config.clone()).map_err(|e| CoreError::StorageError(e.to_string()))?,
-    );
+    let content_service = create_service(config.clone())
+        .await
+        .map_err(|e| CoreError::StorageError(e.to_string()))?;
+    serve(config, time, content_service).await
+}
+
+/// Starts the server on `storage` rather than the storage selected by the
+/// config, e.g. on a shared `Memory` whose failures are injected by tests
+pub async fn app_with_storage(
+    config: Arc<Config>,
+    time: RealTime,
+    storage: Backend,
+) -> Result<(), CoreError> {
+    serve(
+        config,
+        time,
+        Service {
+            s3: Arc::new(storage),
+        },
+    )
+    .await
+}
+
+async fn serve(
+    config: Arc<Config>,
+    time: RealTime,
+    content_service: ContentService,
+) -> Result<(), CoreError> {
+    if config.callers.is_empty() {
+        warn!("No callers are configured, signed urls can't be requested");
+    }
+
+    let content_service = Arc::new(content_service);
 
     let signer_service = Arc::new(
         HMACUrlService::new(
-            HMACSigner::new(config.key_id.clone())
+            KeyringSigner::from_config(&config)
                 .map_err(|e| CoreError::SigningKeyError(e.to_string()))?,
             time,
             config.base_url.clone(),
         )
-        .map_err(|e| CoreError::SigningKeyError(e.to_string()))?,
+        .map_err(|e| CoreError::SigningKeyError(e.to_string()))?
+        .with_max_ttls(config.max_ttls),
     );
     let guards = Arc::new(
-        GuardsBuilder::new()
-            .add(
-                Prefix::ServerPicture,
-                crate::guards::Guard::new(vec![
-                    crate::guards::FileType::ImagePNG,
-                    crate::guards::FileType::ImageJPEG,
-                    crate::guards::FileType::ImageGIF,
-                ]),
-            )
-            .add(
-                Prefix::ServerBanner,
-                crate::guards::Guard::new(vec![
-                    crate::guards::FileType::ImagePNG,
-                    crate::guards::FileType::ImageJPEG,
-                    crate::guards::FileType::ImageGIF,
-                ]),
-            )
-            .add(
-                Prefix::ProfilePicture,
-                crate::guards::Guard::new(vec![
-                    crate::guards::FileType::ImagePNG,
-                    crate::guards::FileType::ImageJPEG,
-                    crate::guards::FileType::ImageGIF,
-                ]),
-            )
-            .add(
-                Prefix::MessageAttachment,
-                crate::guards::Guard::new(vec![crate::guards::FileType::Any]),
-            )
-            .build(),
+        policy::load(config.policy_file.as_deref())
+            .map_err(|e| CoreError::PolicyError(e.to_string()))?,
     );
+    for (bucket, retentions) in guards.retentions(&config.s3_bucket) {
+        if let Err(e) = content_service
+            .s3
+            .put_expiration_rules(&bucket, retentions)
+            .await
+        {
+            warn!(
+                "Failed to apply the retention of the prefixes of bucket {}: {}",
+                bucket, e
+            );
+        }
+    }
     let app_state: AppState =
         AppState::new(content_service, config.clone(), signer_service, guards);
     let root = router::app(app_state)
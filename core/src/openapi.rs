@@ -1,20 +1,50 @@
-use utoipa::OpenApi;
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+};
 
 use crate::healthcheck::handlers::__path_get_healthcheck_handler;
 
 use crate::storage::handlers::{
-    get_object::__path_get_object_handler, post_object::__path_post_sign_url_handler,
+    delete_object::__path_delete_object_handler,
+    get_object::__path_get_object_handler,
+    head_object::__path_head_object_handler,
+    list_objects::__path_list_objects_handler,
+    multipart::{
+        __path_delete_multipart_handler, __path_post_multipart_handler, __path_put_part_handler,
+    },
+    post_object::__path_post_sign_url_handler,
     put_object::__path_put_object_handler,
 };
 
+/// Bearer tokens authenticating the microservices requesting signed urls
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
 #[derive(OpenApi)]
 #[openapi(
     info(title = "Beep Content API",),
+    modifiers(&BearerAuth),
     paths(
         get_healthcheck_handler,
         put_object_handler,
         post_sign_url_handler,
-        get_object_handler
+        get_object_handler,
+        head_object_handler,
+        delete_object_handler,
+        list_objects_handler,
+        post_multipart_handler,
+        put_part_handler,
+        delete_multipart_handler
     )
 )]
 pub struct ApiDoc;
@@ -1,6 +1,10 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use crate::{config, error::CoreError, s3};
+use crate::{
+    config::{self, S3Addressing, S3Credentials, StorageBackend},
+    error::CoreError,
+    s3,
+};
 
 #[derive(Clone)]
 pub struct Service<S>
@@ -10,16 +14,104 @@ where
     pub s3: Arc<S>,
 }
 
-pub type ContentService = Service<s3::Garage>;
-
-pub fn create_service(config: Arc<config::Config>) -> Result<ContentService, CoreError> {
-    let s3 = s3::Garage::new(
-        config
-            .s3_endpoint
-            .parse()
-            .map_err(|_| CoreError::S3EndpointError("Invalid S3 endpoint".to_string()))?,
-        &config.key_id,
-        &config.secret_key,
-    );
+pub type ContentService = Service<s3::Backend>;
+
+pub async fn create_service(config: Arc<config::Config>) -> Result<ContentService, CoreError> {
+    let s3 = match config.storage_backend {
+        StorageBackend::S3 => s3::Backend::Garage(connect_provider(&config).await?),
+        StorageBackend::Filesystem => {
+            s3::Backend::Filesystem(s3::Filesystem::new(config.storage_path.clone()))
+        }
+        StorageBackend::Memory => s3::Backend::Memory(Arc::new(s3::Memory::new())),
+    };
     Ok(Service { s3: Arc::new(s3) })
 }
+
+/// Client of the S3 provider described by the `s3_*` settings of the config
+async fn connect_provider(config: &config::Config) -> Result<s3::Garage, CoreError> {
+    let endpoint = config
+        .s3_endpoint
+        .parse()
+        .map_err(|_| CoreError::S3EndpointError("Invalid S3 endpoint".to_string()))?;
+    let ca_bundle = match &config.s3_ca_bundle {
+        Some(path) => Some(tokio::fs::read(path).await.map_err(|e| {
+            CoreError::S3EndpointError(format!("Unreadable CA bundle {}: {}", path.display(), e))
+        })?),
+        None => None,
+    };
+    let settings = s3::ProviderSettings {
+        region: config.s3_region.clone(),
+        path_style: config.s3_addressing == S3Addressing::Path,
+        ca_bundle,
+        connect_timeout: config.s3_connect_timeout_ms.map(Duration::from_millis),
+        read_timeout: config.s3_read_timeout_ms.map(Duration::from_millis),
+        max_attempts: config.s3_max_attempts,
+        initial_backoff: Duration::from_millis(config.s3_initial_backoff_ms),
+    };
+    let credentials = match config.s3_credentials {
+        S3Credentials::Static => s3::ProviderCredentials::Static {
+            key_id: config.key_id.clone(),
+            secret_key: config.secret_key.clone(),
+        },
+        S3Credentials::Chain => s3::ProviderCredentials::Chain,
+    };
+
+    s3::Garage::connect(endpoint, &settings, credentials)
+        .await
+        .map_err(|e| CoreError::S3EndpointError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn provider_config() -> Config {
+        Config {
+            s3_endpoint: "https://s3.eu-west-3.amazonaws.com".to_string(),
+            s3_region: "eu-west-3".to_string(),
+            s3_addressing: S3Addressing::VirtualHosted,
+            s3_connect_timeout_ms: Some(3000),
+            s3_read_timeout_ms: Some(30000),
+            s3_max_attempts: 5,
+            s3_initial_backoff_ms: 200,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_provider() {
+        assert!(connect_provider(&provider_config()).await.is_ok());
+        assert!(
+            connect_provider(&Config {
+                s3_credentials: S3Credentials::Chain,
+                ..provider_config()
+            })
+            .await
+            .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_ca_bundle() {
+        let path = std::env::temp_dir().join(format!("ca-bundle-{}.pem", std::process::id()));
+        tokio::fs::write(&path, "not a certificate")
+            .await
+            .expect("Write failed");
+
+        let invalid = connect_provider(&Config {
+            s3_ca_bundle: Some(path.clone()),
+            ..provider_config()
+        })
+        .await;
+        let missing = connect_provider(&Config {
+            s3_ca_bundle: Some(path.with_extension("missing")),
+            ..provider_config()
+        })
+        .await;
+        let _ = tokio::fs::remove_file(&path).await;
+
+        assert!(matches!(invalid, Err(CoreError::S3EndpointError(_))));
+        assert!(matches!(missing, Err(CoreError::S3EndpointError(_))));
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt::{Display, Formatter};
+
+/// A single byte range as sent in a `Range` header.
+/// Multiple ranges are not supported, in which case the header is ignored
+/// and the whole object is served, as allowed by RFC 9110.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ByteRange {
+    /// `bytes=start-`
+    From(u64),
+    /// `bytes=start-end`, both bounds are inclusive
+    Bounded(u64, u64),
+    /// `bytes=-length`, the last `length` bytes of the object
+    Suffix(u64),
+}
+
+impl ByteRange {
+    pub fn parse(header: &str) -> Option<Self> {
+        let ranges = header.trim().strip_prefix("bytes=")?;
+        if ranges.contains(',') {
+            return None;
+        }
+        let (start, end) = ranges.trim().split_once('-')?;
+        match (start.trim(), end.trim()) {
+            ("", "") => None,
+            ("", length) => match length.parse().ok()? {
+                0 => None,
+                length => Some(ByteRange::Suffix(length)),
+            },
+            (start, "") => Some(ByteRange::From(start.parse().ok()?)),
+            (start, end) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                if end < start {
+                    return None;
+                }
+                Some(ByteRange::Bounded(start, end))
+            }
+        }
+    }
+
+    /// Resolves the range against the length of an object, returning its
+    /// first and last byte. Ranges starting past the end are not satisfiable.
+    pub fn resolve(self, length: u64) -> Option<(u64, u64)> {
+        match self {
+            ByteRange::From(start) if start < length => Some((start, length - 1)),
+            ByteRange::Bounded(start, end) if start < length => Some((start, end.min(length - 1))),
+            ByteRange::Suffix(suffix) if length > 0 => {
+                Some((length - suffix.min(length), length - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the complete length of an object from a `Content-Range` header,
+/// e.g. `bytes 0-99/1234`. Unknown lengths (`*`) yield `None`.
+pub fn complete_length(content_range: &str) -> Option<u64> {
+    content_range.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+impl Display for ByteRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ByteRange::From(start) => write!(f, "bytes={}-", start),
+            ByteRange::Bounded(start, end) => write!(f, "bytes={}-{}", start, end),
+            ByteRange::Suffix(length) => write!(f, "bytes=-{}", length),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-99"),
+            Some(ByteRange::Bounded(0, 99))
+        );
+        assert_eq!(ByteRange::parse("bytes=100-"), Some(ByteRange::From(100)));
+        assert_eq!(ByteRange::parse("bytes=-500"), Some(ByteRange::Suffix(500)));
+    }
+
+    #[test]
+    fn test_resolve_ranges() {
+        assert_eq!(ByteRange::Bounded(0, 99).resolve(50), Some((0, 49)));
+        assert_eq!(ByteRange::From(10).resolve(50), Some((10, 49)));
+        assert_eq!(ByteRange::Suffix(500).resolve(50), Some((0, 49)));
+        assert_eq!(ByteRange::Suffix(5).resolve(50), Some((45, 49)));
+        assert_eq!(ByteRange::From(50).resolve(50), None);
+        assert_eq!(ByteRange::Suffix(5).resolve(0), None);
+    }
+
+    #[test]
+    fn test_parse_ignored_ranges() {
+        assert_eq!(ByteRange::parse("bytes=0-99,200-299"), None);
+        assert_eq!(ByteRange::parse("bytes=99-0"), None);
+        assert_eq!(ByteRange::parse("bytes=-0"), None);
+        assert_eq!(ByteRange::parse("bytes=-"), None);
+        assert_eq!(ByteRange::parse("items=0-99"), None);
+        assert_eq!(ByteRange::parse("bytes=a-b"), None);
+    }
+
+    #[test]
+    fn test_complete_length() {
+        assert_eq!(complete_length("bytes 0-99/1234"), Some(1234));
+        assert_eq!(complete_length("bytes 0-99/*"), None);
+        assert_eq!(complete_length("bytes */1234"), Some(1234));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for header in ["bytes=0-99", "bytes=100-", "bytes=-500"] {
+            let range = ByteRange::parse(header).expect("Range should parse");
+            assert_eq!(range.to_string(), header);
+        }
+    }
+}
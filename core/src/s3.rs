@@ -1,14 +1,71 @@
 use mockall::automock;
-use std::fmt::{Display, Formatter};
-use tracing::info;
+use std::{
+    fmt::{Display, Formatter},
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use sync_wrapper::SyncWrapper;
+use tracing::{info, warn};
 
-use aws_config::BehaviorVersion;
-use aws_sdk_s3::{self as s3, config::Credentials};
-use axum::http::Uri;
+use aws_config::{BehaviorVersion, default_provider::credentials::DefaultCredentialsChain};
+use aws_sdk_s3::{
+    self as s3,
+    config::{
+        Credentials, Region, SharedCredentialsProvider, SharedHttpClient, retry::RetryConfig,
+        timeout::TimeoutConfig,
+    },
+    error::ProvideErrorMetadata,
+    primitives::ByteStream,
+    types::{
+        BucketLifecycleConfiguration, CompletedMultipartUpload, CompletedPart, ExpirationStatus,
+        LifecycleExpiration, LifecycleRule, LifecycleRuleFilter,
+    },
+};
+use aws_smithy_http_client::tls::{Provider, TlsContext, TrustStore, rustls_provider::CryptoMode};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::Uri,
+};
+use chrono::{DateTime, Utc};
+use http_body::Frame;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use rustls::{
+    RootCertStore,
+    pki_types::{CertificateDer, pem::PemObject},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
 
-use crate::error::ApiError;
+use crate::{error::ApiError, range::ByteRange};
+
+/// Largest object a single `CopyObject` request copies
+const MAX_COPY_LENGTH: u64 = 5 * 1024 * 1024 * 1024;
+/// Length of the parts larger objects are copied by
+const COPY_PART_LENGTH: u64 = 1024 * 1024 * 1024;
+/// Distinguishes the ids generated by this process
+static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Characters kept as is in the `bucket/key` source of a copy
+const COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+pub mod filesystem;
+pub mod memory;
+
+pub use filesystem::Filesystem;
+pub use memory::Memory;
 
 pub trait S3: Send + Sync {
+    /// Stores an object, returning its ETag
     async fn put_object(
         &self,
         bucket: &str,
@@ -16,35 +73,264 @@ pub trait S3: Send + Sync {
         file: FileObject,
     ) -> Result<String, S3Error>;
     async fn show_buckets(&self) -> Result<Vec<String>, S3Error>;
-    async fn get_object(&self, bucket: &str, key: &str) -> Result<(Vec<u8>, String), S3Error>;
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, S3Error>;
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, S3Error>;
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), S3Error>;
+    /// Copies an object within a bucket along with its content type,
+    /// returning the ETag of the copy
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        key: &str,
+    ) -> Result<String, S3Error>;
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, S3Error>;
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u16,
+        data: Body,
+        content_length: u64,
+    ) -> Result<String, S3Error>;
+    /// Assembles the parts of a multipart upload, returning the ETag of the object
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<String, S3Error>;
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), S3Error>;
+    /// Sets the expiration rules of the service on a bucket, so that the
+    /// objects under each prefix are deleted after its number of days. The
+    /// former rules of the service are replaced, the other rules of the bucket
+    /// are kept.
+    async fn put_expiration_rules(
+        &self,
+        bucket: &str,
+        rules: Vec<(String, u32)>,
+    ) -> Result<(), S3Error>;
+    /// Lists a page of the objects whose key starts with `prefix`, in key
+    /// order. Keys holding a `/` after the prefix, such as the variants cached
+    /// under an image or the parts of a multipart upload being assembled, are
+    /// skipped. S3 still counts them against `max_keys`, grouped by what comes
+    /// before their `/`, so its pages can hold fewer objects than asked, or
+    /// none, while carrying a continuation token.
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        options: ListOptions,
+    ) -> Result<ObjectList, S3Error>;
 }
 
 pub struct Garage {
     client: s3::Client,
-    url: Uri,
+}
+
+/// How the client reaches an S3 compatible provider, Garage by default
+#[derive(Debug, Clone)]
+pub struct ProviderSettings {
+    pub region: String,
+    /// Whether buckets are addressed in the path rather than in the host name
+    pub path_style: bool,
+    /// PEM certificates trusted along with the platform roots
+    pub ca_bundle: Option<Vec<u8>>,
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    /// Attempts of each request, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, which grows exponentially
+    pub initial_backoff: Duration,
+}
+
+impl Default for ProviderSettings {
+    fn default() -> Self {
+        Self {
+            region: "garage".to_string(),
+            path_style: true,
+            ca_bundle: None,
+            connect_timeout: None,
+            read_timeout: None,
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Where the client finds its credentials
+pub enum ProviderCredentials {
+    Static {
+        key_id: String,
+        secret_key: String,
+    },
+    /// The standard AWS provider chain: environment, profiles, web identity,
+    /// then container and instance metadata
+    Chain,
 }
 
 impl Garage {
     pub fn new(url: Uri, key_id: &str, secret_key: &str) -> Self {
-        let credentials = Credentials::new(key_id, secret_key, None, None, "beep");
+        Self::build(
+            url,
+            &ProviderSettings::default(),
+            static_credentials(key_id, secret_key),
+            None,
+        )
+    }
+
+    /// Client of any S3 compatible provider, e.g. MinIO, Ceph RGW or AWS S3
+    pub async fn connect(
+        url: Uri,
+        settings: &ProviderSettings,
+        credentials: ProviderCredentials,
+    ) -> Result<Self, S3Error> {
+        let credentials = match credentials {
+            ProviderCredentials::Static { key_id, secret_key } => {
+                static_credentials(&key_id, &secret_key)
+            }
+            ProviderCredentials::Chain => SharedCredentialsProvider::new(
+                DefaultCredentialsChain::builder()
+                    .region(Region::new(settings.region.clone()))
+                    .build()
+                    .await,
+            ),
+        };
+        let http_client = settings
+            .ca_bundle
+            .as_deref()
+            .map(trusting_client)
+            .transpose()?;
+
+        Ok(Self::build(url, settings, credentials, http_client))
+    }
 
-        let s3_config = s3::config::Builder::new()
+    fn build(
+        url: Uri,
+        settings: &ProviderSettings,
+        credentials: SharedCredentialsProvider,
+        http_client: Option<SharedHttpClient>,
+    ) -> Self {
+        let mut timeouts = TimeoutConfig::builder();
+        timeouts
+            .set_connect_timeout(settings.connect_timeout)
+            .set_read_timeout(settings.read_timeout);
+        let retries = RetryConfig::standard()
+            .with_max_attempts(settings.max_attempts)
+            .with_initial_backoff(settings.initial_backoff);
+
+        let mut s3_config = s3::config::Builder::new()
             .credentials_provider(credentials)
             .endpoint_url(url.to_string())
-            .force_path_style(true)
+            .force_path_style(settings.path_style)
             .behavior_version(BehaviorVersion::latest())
-            .region(s3::config::Region::new("garage"))
-            .build();
+            .region(Region::new(settings.region.clone()))
+            .timeout_config(timeouts.build())
+            .retry_config(retries);
+        s3_config.set_http_client(http_client);
 
-        let client = s3::Client::from_conf(s3_config);
+        let client = s3::Client::from_conf(s3_config.build());
 
-        Self { client, url }
+        Self { client }
     }
+
+    /// Copies an object by ranges into the parts of a multipart upload
+    async fn copy_parts(
+        &self,
+        bucket: &str,
+        copy_source: &str,
+        key: &str,
+        upload_id: &str,
+        content_length: u64,
+    ) -> Result<Vec<UploadedPart>, S3Error> {
+        let mut parts = Vec::new();
+        for (index, start) in (0..content_length)
+            .step_by(COPY_PART_LENGTH as usize)
+            .enumerate()
+        {
+            let part_number =
+                u16::try_from(index + 1).map_err(|e| S3Error::MultipartFailure(e.to_string()))?;
+            let end = (start + COPY_PART_LENGTH).min(content_length) - 1;
+            let part = self
+                .client
+                .upload_part_copy()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number.into())
+                .copy_source(copy_source)
+                .copy_source_range(ByteRange::Bounded(start, end).to_string())
+                .send()
+                .await
+                .map_err(|e| {
+                    let service_error = e.into_service_error();
+                    S3Error::MultipartFailure(service_error.to_string())
+                })?;
+            let e_tag = part
+                .copy_part_result
+                .and_then(|result| result.e_tag)
+                .ok_or(S3Error::MultipartFailure("No ETag for part".to_string()))?;
+            parts.push(UploadedPart { part_number, e_tag });
+        }
+        Ok(parts)
+    }
+}
+
+fn static_credentials(key_id: &str, secret_key: &str) -> SharedCredentialsProvider {
+    SharedCredentialsProvider::new(Credentials::new(key_id, secret_key, None, None, "beep"))
+}
+
+/// HTTPS client trusting the certificates of `ca_bundle` besides the platform
+/// roots. The SDK only parses them when connecting and panics if they are
+/// invalid, so they are checked upfront.
+fn trusting_client(ca_bundle: &[u8]) -> Result<SharedHttpClient, S3Error> {
+    let certificates = CertificateDer::pem_slice_iter(ca_bundle)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| S3Error::InvalidConfiguration(format!("Invalid CA bundle: {}", e)))?;
+    if certificates.is_empty() {
+        return Err(S3Error::InvalidConfiguration(
+            "The CA bundle holds no certificate".to_string(),
+        ));
+    }
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates {
+        roots
+            .add(certificate)
+            .map_err(|e| S3Error::InvalidConfiguration(format!("Invalid CA bundle: {}", e)))?;
+    }
+
+    let tls_context = TlsContext::builder()
+        .with_trust_store(TrustStore::default().with_pem_certificate(ca_bundle.to_vec()))
+        .build()
+        .map_err(|e| S3Error::InvalidConfiguration(e.to_string()))?;
+    Ok(aws_smithy_http_client::Builder::new()
+        .tls_provider(Provider::Rustls(CryptoMode::AwsLc))
+        .tls_context(tls_context)
+        .build_https())
 }
 
 #[automock]
 impl S3 for Garage {
-    /// Uploads a byte array to S3
+    /// Streams a body to S3, returning the ETag of the stored object
+    /// The body is forwarded chunk by chunk, `content_length` has to match the
+    /// number of bytes the body yields since S3 needs it upfront.
     ///
     /// # Examples
     ///
@@ -55,9 +341,9 @@ impl S3 for Garage {
     ///     "secret_key",
     /// );
     /// let file = FileObject {
-    ///     data: vec![1, 2, 3],
+    ///     data: Body::from(vec![1, 2, 3]),
     ///     content_type: "application/octet-stream".to_string(),
-    ///     file_name: "test.txt".to_string(),
+    ///     content_length: 3,
     /// };
     /// let res = s3.put_object("test", "test.txt", file).await;
     /// assert!(res.is_ok());
@@ -68,15 +354,17 @@ impl S3 for Garage {
         key: &str,
         file: FileObject,
     ) -> Result<String, S3Error> {
-        let body = file.data;
-        let content_type = file.content_type;
-        let body_stream = aws_sdk_s3::primitives::ByteStream::from(body);
+        let content_length = i64::try_from(file.content_length)
+            .map_err(|e| S3Error::UploadFailure(e.to_string()))?;
+        let body_stream = ByteStream::from_body_1_x(SyncBody(SyncWrapper::new(file.data)));
 
-        self.client
+        let object = self
+            .client
             .put_object()
             .bucket(bucket)
             .key(key)
-            .content_type(content_type)
+            .content_type(file.content_type)
+            .content_length(content_length)
             .body(body_stream)
             .send()
             .await
@@ -85,9 +373,7 @@ impl S3 for Garage {
                 S3Error::UploadFailure(service_error.to_string())
             })?;
 
-        let object_url = self.url.to_string() + "/" + bucket + "/" + key;
-
-        Ok(object_url)
+        Ok(object.e_tag.unwrap_or_default())
     }
 
     /// List all buckets on S3
@@ -127,8 +413,9 @@ impl S3 for Garage {
     }
 
     /// Download an object from an s3
-    /// This functions if successful returns `(Vec<u8>, String)`. The Vec<u8> is an
-    /// array containing the file. The String defines the file content type.
+    /// This functions if successful returns an `ObjectStream` whose body is
+    /// streamed from S3 as it is read. When a `range` is given, only that part
+    /// of the object is fetched and `content_range` is set accordingly.
     ///
     /// # Examples
     ///
@@ -138,35 +425,656 @@ impl S3 for Garage {
     ///     "key_id",
     ///     "secret_key",
     /// )
-    /// let res = s3.show_buckets().await;
+    /// let res = s3.get_object("test", "test.txt", Some(ByteRange::Bounded(0, 99))).await;
     /// assert!(res.is_ok());
     /// ```
-    async fn get_object(&self, bucket: &str, key: &str) -> Result<(Vec<u8>, String), S3Error> {
-        let object = self
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, S3Error> {
+        let object = match self
             .client
             .get_object()
             .bucket(bucket)
             .key(key)
+            .set_range(range.map(|range| range.to_string()))
             .send()
             .await
-            .map_err(|e| {
+        {
+            Ok(object) => object,
+            Err(e) => {
                 let service_error = e.into_service_error();
-                S3Error::UploadFailure(service_error.to_string())
-            })?;
+                return Err(if service_error.is_no_such_key() {
+                    S3Error::ObjectNotFound(key.to_string())
+                } else if service_error.code() == Some("InvalidRange") {
+                    // The error doesn't tell the length of the object, which
+                    // the 416 response carries
+                    let length = self.head_object(bucket, key).await?.content_length;
+                    S3Error::InvalidRange(service_error.to_string(), length)
+                } else {
+                    S3Error::DownloadFailure(service_error.to_string())
+                });
+            }
+        };
 
         info!("Downloading object from S3 {:?}", object);
 
-        let mime_type = object
+        let content_type = object
             .content_type
-            .clone()
             .unwrap_or("application/octet-stream".to_string());
+        let content_length = object
+            .content_length
+            .unwrap_or_default()
+            .try_into()
+            .map_err(|_| S3Error::DownloadFailure("Invalid content length".to_string()))?;
+
+        Ok(ObjectStream {
+            data: Body::new(object.body.into_inner()),
+            content_type,
+            content_length,
+            content_range: object.content_range,
+            e_tag: object.e_tag,
+            last_modified: object
+                .last_modified
+                .and_then(|date| DateTime::from_timestamp(date.secs(), 0)),
+        })
+    }
+
+    /// Fetch the metadata of an object from an s3, without its body
+    ///
+    /// # Examples
+    ///
+    ///```
+    /// let s3 = Garage::new(
+    ///     "https://s3.us-west-2.amazonaws.com".parse().unwrap(),
+    ///     "key_id",
+    ///     "secret_key",
+    /// )
+    /// let res = s3.head_object("test", "test.txt").await;
+    /// assert!(res.is_ok());
+    /// ```
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, S3Error> {
+        let object = self
+            .client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                let service_error = e.into_service_error();
+                if service_error.is_not_found() {
+                    S3Error::ObjectNotFound(key.to_string())
+                } else {
+                    S3Error::DownloadFailure(service_error.to_string())
+                }
+            })?;
+
+        Ok(ObjectMetadata {
+            content_type: object
+                .content_type
+                .unwrap_or("application/octet-stream".to_string()),
+            content_length: object
+                .content_length
+                .unwrap_or_default()
+                .try_into()
+                .map_err(|_| S3Error::DownloadFailure("Invalid content length".to_string()))?,
+            e_tag: object.e_tag,
+            last_modified: object
+                .last_modified
+                .and_then(|date| DateTime::from_timestamp(date.secs(), 0)),
+        })
+    }
+
+    /// Delete an object from an s3
+    /// S3 deletions are idempotent, so the object is looked up first in order
+    /// to report `S3Error::ObjectNotFound` when there is nothing to delete.
+    ///
+    /// # Examples
+    ///
+    ///```
+    /// let s3 = Garage::new(
+    ///     "https://s3.us-west-2.amazonaws.com".parse().unwrap(),
+    ///     "key_id",
+    ///     "secret_key",
+    /// )
+    /// let res = s3.delete_object("test", "test.txt").await;
+    /// assert!(res.is_ok());
+    /// ```
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), S3Error> {
+        self.client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                let service_error = e.into_service_error();
+                if service_error.is_not_found() {
+                    S3Error::ObjectNotFound(key.to_string())
+                } else {
+                    S3Error::DeleteFailure(service_error.to_string())
+                }
+            })?;
+
+        self.client
+            .delete_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                let service_error = e.into_service_error();
+                S3Error::DeleteFailure(service_error.to_string())
+            })?;
+
+        info!("Deleted object {} from bucket {}", key, bucket);
+
+        Ok(())
+    }
+
+    /// Copies an object within a bucket. S3 copies at most 5 GiB at once,
+    /// larger objects are copied by parts.
+    ///
+    /// # Examples
+    ///
+    ///```
+    /// let s3 = Garage::new(
+    ///     "https://s3.us-west-2.amazonaws.com".parse().unwrap(),
+    ///     "key_id",
+    ///     "secret_key",
+    /// )
+    /// let e_tag = s3.copy_object("test", "test.txt.staging", "test.txt").await;
+    /// assert!(e_tag.is_ok());
+    /// ```
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        key: &str,
+    ) -> Result<String, S3Error> {
+        let source = self.head_object(bucket, source_key).await?;
+        let copy_source = utf8_percent_encode(
+            &format!("{}/{}", bucket, source_key),
+            COPY_SOURCE_ENCODE_SET,
+        )
+        .to_string();
+
+        if source.content_length <= MAX_COPY_LENGTH {
+            let copy = self
+                .client
+                .copy_object()
+                .bucket(bucket)
+                .key(key)
+                .copy_source(copy_source)
+                .send()
+                .await
+                .map_err(|e| {
+                    let service_error = e.into_service_error();
+                    S3Error::UploadFailure(service_error.to_string())
+                })?;
+            return copy
+                .copy_object_result
+                .and_then(|result| result.e_tag)
+                .ok_or(S3Error::UploadFailure("No ETag for copy".to_string()));
+        }
+
+        let upload_id = self
+            .create_multipart_upload(bucket, key, &source.content_type)
+            .await?;
+        let copied = match self
+            .copy_parts(bucket, &copy_source, key, &upload_id, source.content_length)
+            .await
+        {
+            Ok(parts) => {
+                self.complete_multipart_upload(bucket, key, &upload_id, parts)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if copied.is_err()
+            && let Err(e) = self.abort_multipart_upload(bucket, key, &upload_id).await
+        {
+            warn!(
+                "Failed to abort the copy of {} to {}: {}",
+                source_key, key, e
+            );
+        }
+        copied
+    }
+
+    /// Starts a multipart upload and returns its upload id
+    ///
+    /// # Examples
+    ///
+    ///```
+    /// let s3 = Garage::new(
+    ///     "https://s3.us-west-2.amazonaws.com".parse().unwrap(),
+    ///     "key_id",
+    ///     "secret_key",
+    /// )
+    /// let upload_id = s3.create_multipart_upload("test", "test.mp4", "video/mp4").await;
+    /// assert!(upload_id.is_ok());
+    /// ```
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, S3Error> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .content_type(content_type)
+            .send()
+            .await
+            .map_err(|e| {
+                let service_error = e.into_service_error();
+                S3Error::MultipartFailure(service_error.to_string())
+            })?;
+
+        upload
+            .upload_id
+            .ok_or(S3Error::MultipartFailure("No upload id".to_string()))
+    }
+
+    /// Streams one part of a multipart upload to S3 and returns its ETag,
+    /// which has to be handed back when completing the upload.
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u16,
+        data: Body,
+        content_length: u64,
+    ) -> Result<String, S3Error> {
+        let content_length =
+            i64::try_from(content_length).map_err(|e| S3Error::UploadFailure(e.to_string()))?;
+        let body_stream = ByteStream::from_body_1_x(SyncBody(SyncWrapper::new(data)));
+
+        let part = self
+            .client
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number.into())
+            .content_length(content_length)
+            .body(body_stream)
+            .send()
+            .await
+            .map_err(|e| {
+                let service_error = e.into_service_error();
+                if service_error.code() == Some("NoSuchUpload") {
+                    S3Error::ObjectNotFound(upload_id.to_string())
+                } else {
+                    S3Error::UploadFailure(service_error.to_string())
+                }
+            })?;
 
-        let body = object.body.collect().await.map_err(|e| {
-            let service_error = e.to_string();
-            S3Error::UploadFailure(service_error)
-        })?;
+        part.e_tag
+            .ok_or(S3Error::UploadFailure("No ETag for part".to_string()))
+    }
+
+    /// Assembles the uploaded parts into the final object, returning its ETag
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<String, S3Error> {
+        let parts = parts
+            .into_iter()
+            .map(|part| {
+                CompletedPart::builder()
+                    .part_number(part.part_number.into())
+                    .e_tag(part.e_tag)
+                    .build()
+            })
+            .collect();
+
+        let object = self
+            .client
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| {
+                let service_error = e.into_service_error();
+                match service_error.code() {
+                    Some("NoSuchUpload") => S3Error::ObjectNotFound(upload_id.to_string()),
+                    Some("InvalidPart" | "InvalidPartOrder" | "EntityTooSmall") => {
+                        S3Error::InvalidMultipart(service_error.to_string())
+                    }
+                    _ => S3Error::MultipartFailure(service_error.to_string()),
+                }
+            })?;
+
+        Ok(object.e_tag.unwrap_or_default())
+    }
+
+    /// Aborts a multipart upload, freeing the parts already uploaded
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), S3Error> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| {
+                let service_error = e.into_service_error();
+                if service_error.is_no_such_upload() {
+                    S3Error::ObjectNotFound(upload_id.to_string())
+                } else {
+                    S3Error::MultipartFailure(service_error.to_string())
+                }
+            })?;
+
+        Ok(())
+    }
+
+    async fn put_expiration_rules(
+        &self,
+        bucket: &str,
+        rules: Vec<(String, u32)>,
+    ) -> Result<(), S3Error> {
+        let rules = rules
+            .into_iter()
+            .map(|(prefix, days)| {
+                let days = i32::try_from(days).map_err(|_| {
+                    S3Error::LifecycleFailure(format!(
+                        "Retention of {} days of prefix {} is too long",
+                        days, prefix
+                    ))
+                })?;
+                LifecycleRule::builder()
+                    .id(format!("{}{}", EXPIRATION_RULE_ID_PREFIX, prefix))
+                    .status(ExpirationStatus::Enabled)
+                    .filter(
+                        LifecycleRuleFilter::builder()
+                            .prefix(format!("{}/", prefix))
+                            .build(),
+                    )
+                    .expiration(LifecycleExpiration::builder().days(days).build())
+                    .build()
+                    .map_err(|e| S3Error::LifecycleFailure(e.to_string()))
+            })
+            .collect::<Result<Vec<LifecycleRule>, S3Error>>()?;
+
+        let current = match self
+            .client
+            .get_bucket_lifecycle_configuration()
+            .bucket(bucket)
+            .send()
+            .await
+        {
+            Ok(output) => output.rules.unwrap_or_default(),
+            Err(e) => {
+                let service_error = e.into_service_error();
+                if service_error.code() != Some("NoSuchLifecycleConfiguration") {
+                    return Err(S3Error::LifecycleFailure(service_error.to_string()));
+                }
+                Vec::new()
+            }
+        };
+        let merged = merge_expiration_rules(current.clone(), rules);
+        if merged == current {
+            return Ok(());
+        }
+
+        // S3 refuses lifecycle configurations without rules
+        if merged.is_empty() {
+            self.client
+                .delete_bucket_lifecycle()
+                .bucket(bucket)
+                .send()
+                .await
+                .map_err(|e| S3Error::LifecycleFailure(e.into_service_error().to_string()))?;
+            return Ok(());
+        }
+        let configuration = BucketLifecycleConfiguration::builder()
+            .set_rules(Some(merged))
+            .build()
+            .map_err(|e| S3Error::LifecycleFailure(e.to_string()))?;
+        self.client
+            .put_bucket_lifecycle_configuration()
+            .bucket(bucket)
+            .lifecycle_configuration(configuration)
+            .send()
+            .await
+            .map_err(|e| S3Error::LifecycleFailure(e.into_service_error().to_string()))?;
+
+        Ok(())
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        options: ListOptions,
+    ) -> Result<ObjectList, S3Error> {
+        let output = self
+            .client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .delimiter("/")
+            .set_continuation_token(options.continuation_token)
+            .set_start_after(options.start_after)
+            .set_max_keys(options.max_keys.map(i32::from))
+            .send()
+            .await
+            .map_err(|e| S3Error::ListFailure(e.into_service_error().to_string()))?;
+
+        let objects = output
+            .contents()
+            .iter()
+            .filter_map(|object| {
+                Some(ListedObject {
+                    key: object.key()?.to_string(),
+                    size: object.size().unwrap_or_default().try_into().ok()?,
+                    last_modified: object
+                        .last_modified()
+                        .and_then(|date| DateTime::from_timestamp(date.secs(), 0)),
+                })
+            })
+            .collect();
+
+        Ok(ObjectList {
+            objects,
+            next_continuation_token: output.next_continuation_token().map(str::to_string),
+        })
+    }
+}
+
+/// Storage the objects are kept in, as selected by `Config::storage_backend`
+pub enum Backend {
+    Garage(Garage),
+    Filesystem(Filesystem),
+    /// Shared, so that failures can be injected while the service runs
+    Memory(Arc<Memory>),
+}
+
+impl S3 for Backend {
+    async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        file: FileObject,
+    ) -> Result<String, S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.put_object(bucket, key, file).await,
+            Backend::Filesystem(s3) => s3.put_object(bucket, key, file).await,
+            Backend::Memory(s3) => s3.put_object(bucket, key, file).await,
+        }
+    }
+
+    async fn show_buckets(&self) -> Result<Vec<String>, S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.show_buckets().await,
+            Backend::Filesystem(s3) => s3.show_buckets().await,
+            Backend::Memory(s3) => s3.show_buckets().await,
+        }
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.get_object(bucket, key, range).await,
+            Backend::Filesystem(s3) => s3.get_object(bucket, key, range).await,
+            Backend::Memory(s3) => s3.get_object(bucket, key, range).await,
+        }
+    }
+
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.head_object(bucket, key).await,
+            Backend::Filesystem(s3) => s3.head_object(bucket, key).await,
+            Backend::Memory(s3) => s3.head_object(bucket, key).await,
+        }
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.delete_object(bucket, key).await,
+            Backend::Filesystem(s3) => s3.delete_object(bucket, key).await,
+            Backend::Memory(s3) => s3.delete_object(bucket, key).await,
+        }
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        key: &str,
+    ) -> Result<String, S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.copy_object(bucket, source_key, key).await,
+            Backend::Filesystem(s3) => s3.copy_object(bucket, source_key, key).await,
+            Backend::Memory(s3) => s3.copy_object(bucket, source_key, key).await,
+        }
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.create_multipart_upload(bucket, key, content_type).await,
+            Backend::Filesystem(s3) => s3.create_multipart_upload(bucket, key, content_type).await,
+            Backend::Memory(s3) => s3.create_multipart_upload(bucket, key, content_type).await,
+        }
+    }
+
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u16,
+        data: Body,
+        content_length: u64,
+    ) -> Result<String, S3Error> {
+        match self {
+            Backend::Garage(s3) => {
+                s3.upload_part(bucket, key, upload_id, part_number, data, content_length)
+                    .await
+            }
+            Backend::Filesystem(s3) => {
+                s3.upload_part(bucket, key, upload_id, part_number, data, content_length)
+                    .await
+            }
+            Backend::Memory(s3) => {
+                s3.upload_part(bucket, key, upload_id, part_number, data, content_length)
+                    .await
+            }
+        }
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<String, S3Error> {
+        match self {
+            Backend::Garage(s3) => {
+                s3.complete_multipart_upload(bucket, key, upload_id, parts)
+                    .await
+            }
+            Backend::Filesystem(s3) => {
+                s3.complete_multipart_upload(bucket, key, upload_id, parts)
+                    .await
+            }
+            Backend::Memory(s3) => {
+                s3.complete_multipart_upload(bucket, key, upload_id, parts)
+                    .await
+            }
+        }
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.abort_multipart_upload(bucket, key, upload_id).await,
+            Backend::Filesystem(s3) => s3.abort_multipart_upload(bucket, key, upload_id).await,
+            Backend::Memory(s3) => s3.abort_multipart_upload(bucket, key, upload_id).await,
+        }
+    }
 
-        Ok((body.to_vec(), mime_type.to_string()))
+    async fn put_expiration_rules(
+        &self,
+        bucket: &str,
+        rules: Vec<(String, u32)>,
+    ) -> Result<(), S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.put_expiration_rules(bucket, rules).await,
+            Backend::Filesystem(s3) => s3.put_expiration_rules(bucket, rules).await,
+            Backend::Memory(s3) => s3.put_expiration_rules(bucket, rules).await,
+        }
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        options: ListOptions,
+    ) -> Result<ObjectList, S3Error> {
+        match self {
+            Backend::Garage(s3) => s3.list_objects(bucket, prefix, options).await,
+            Backend::Filesystem(s3) => s3.list_objects(bucket, prefix, options).await,
+            Backend::Memory(s3) => s3.list_objects(bucket, prefix, options).await,
+        }
     }
 }
 
@@ -175,12 +1083,29 @@ pub enum S3Error {
     UploadFailure(String),
     NoBucketFound,
     BucketNameError(String),
+    ObjectNotFound(String),
+    DeleteFailure(String),
+    DownloadFailure(String),
+    /// Unsatisfiable range of an object, along with the length of the object
+    InvalidRange(String, u64),
+    MultipartFailure(String),
+    InvalidMultipart(String),
+    LifecycleFailure(String),
+    InvalidConfiguration(String),
+    ListFailure(String),
 }
 
 #[allow(clippy::from_over_into)]
 impl Into<ApiError> for S3Error {
     fn into(self) -> ApiError {
-        ApiError::InternalServerError(self.to_string())
+        match self {
+            S3Error::ObjectNotFound(_) => ApiError::NotFound(self.to_string()),
+            S3Error::InvalidRange(_, length) => {
+                ApiError::RangeNotSatisfiable(self.to_string(), length)
+            }
+            S3Error::InvalidMultipart(_) => ApiError::BadRequest(self.to_string()),
+            _ => ApiError::InternalServerError(self.to_string()),
+        }
     }
 }
 
@@ -190,12 +1115,199 @@ impl Display for S3Error {
             S3Error::UploadFailure(e) => write!(f, "{}", e),
             S3Error::NoBucketFound => write!(f, "No bucket found"),
             S3Error::BucketNameError(e) => write!(f, "{}", e),
+            S3Error::ObjectNotFound(key) => write!(f, "Object not found: {}", key),
+            S3Error::DeleteFailure(e) => write!(f, "{}", e),
+            S3Error::DownloadFailure(e) => write!(f, "{}", e),
+            S3Error::InvalidRange(e, _) => write!(f, "{}", e),
+            S3Error::MultipartFailure(e) => write!(f, "{}", e),
+            S3Error::InvalidMultipart(e) => write!(f, "{}", e),
+            S3Error::LifecycleFailure(e) => write!(f, "{}", e),
+            S3Error::InvalidConfiguration(e) => write!(f, "{}", e),
+            S3Error::ListFailure(e) => write!(f, "{}", e),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct FileObject {
-    pub data: Vec<u8>,
+    pub data: Body,
+    pub content_type: String,
+    pub content_length: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ObjectStream {
+    pub data: Body,
     pub content_type: String,
+    /// Length of `data`, which is the length of the range when one was requested
+    pub content_length: u64,
+    /// Set when only part of the object was fetched, e.g. `bytes 0-99/1000`
+    pub content_range: Option<String>,
+    /// Quoted entity tag of the object, as returned by S3
+    pub e_tag: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// What is known of an object without downloading it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectMetadata {
+    pub content_type: String,
+    pub content_length: u64,
+    /// Quoted entity tag of the object, as returned by S3
+    pub e_tag: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Page of a listing, see `S3::list_objects`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListOptions {
+    /// Token returned along with the previous page
+    pub continuation_token: Option<String>,
+    /// Largest number of objects of the page, up to 1000. Skipped keys count
+    /// against it with S3, see `S3::list_objects`
+    pub max_keys: Option<u16>,
+    /// Key the listing starts after
+    pub start_after: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectList {
+    pub objects: Vec<ListedObject>,
+    /// Set when more objects are left, to request the next page with
+    pub next_continuation_token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct UploadedPart {
+    pub part_number: u16,
+    pub e_tag: String,
+}
+
+/// The SDK only accepts `Sync` bodies while axum's body is only `Send`.
+/// The body is exclusively polled through `&mut`, so wrapping it is enough.
+struct SyncBody(SyncWrapper<Body>);
+
+impl HttpBody for SyncBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(self.get_mut().0.get_mut()).poll_frame(cx)
+    }
+}
+
+/// Ids of the lifecycle rules set by the service, followed by the key prefix
+/// they expire
+const EXPIRATION_RULE_ID_PREFIX: &str = "expire-";
+
+/// Replaces the expiration rules of the service among the lifecycle rules of a
+/// bucket, keeping the rules set by anyone else in their order
+fn merge_expiration_rules(
+    current: Vec<LifecycleRule>,
+    rules: Vec<LifecycleRule>,
+) -> Vec<LifecycleRule> {
+    current
+        .into_iter()
+        .filter(|rule| {
+            !rule
+                .id()
+                .is_some_and(|id| id.starts_with(EXPIRATION_RULE_ID_PREFIX))
+        })
+        .chain(rules)
+        .collect()
+}
+
+/// Largest number of objects of a page of a listing, as with S3
+pub const MAX_KEYS: u16 = 1000;
+
+/// Picks the keys of a page of a listing out of every key of a bucket, the
+/// way S3 does, for the storages which can't list a range of keys. Their
+/// continuation token is the last key of the previous page.
+pub(crate) fn page_keys(
+    mut keys: Vec<String>,
+    prefix: &str,
+    options: &ListOptions,
+) -> (Vec<String>, Option<String>) {
+    let start_after = options
+        .continuation_token
+        .as_ref()
+        .or(options.start_after.as_ref());
+    keys.retain(|key| {
+        key.strip_prefix(prefix)
+            .is_some_and(|rest| !rest.contains('/'))
+            && start_after.is_none_or(|start_after| key > start_after)
+    });
+    keys.sort();
+
+    let max_keys = options.max_keys.unwrap_or(MAX_KEYS).min(MAX_KEYS) as usize;
+    if keys.len() <= max_keys {
+        return (keys, None);
+    }
+    keys.truncate(max_keys);
+    let next_continuation_token = keys.last().cloned();
+    (keys, next_continuation_token)
+}
+
+/// Hex digest shortened to the length of an S3 ETag
+pub(crate) fn short_digest(hasher: Sha256) -> String {
+    hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Identifier unique to this process, derived from `seed`
+pub(crate) fn unique_id(seed: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(seed.as_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(ID_COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    hasher.update(
+        Utc::now()
+            .timestamp_nanos_opt()
+            .unwrap_or_default()
+            .to_le_bytes(),
+    );
+    short_digest(hasher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(id: &str) -> LifecycleRule {
+        LifecycleRule::builder()
+            .id(id)
+            .status(ExpirationStatus::Enabled)
+            .build()
+            .expect("Invalid rule")
+    }
+
+    #[test]
+    fn test_merge_expiration_rules() {
+        let current = vec![
+            rule("archive-logs"),
+            rule("expire-message_attachment"),
+            rule("expire-former_prefix"),
+            rule("replicate"),
+        ];
+        let merged = merge_expiration_rules(current, vec![rule("expire-message_attachment")]);
+        let ids: Vec<&str> = merged.iter().filter_map(|rule| rule.id()).collect();
+        assert_eq!(
+            ids,
+            ["archive-logs", "replicate", "expire-message_attachment"]
+        );
+
+        assert!(merge_expiration_rules(vec![rule("expire-former_prefix")], vec![]).is_empty());
+    }
 }
@@ -0,0 +1,874 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use axum::body::{Body, Bytes};
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, stream};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tracing::info;
+
+use crate::{
+    range::ByteRange,
+    s3::{
+        FileObject, ListOptions, ListedObject, ObjectList, ObjectMetadata, ObjectStream, S3,
+        S3Error, UploadedPart, page_keys, short_digest, unique_id,
+    },
+};
+
+/// Characters kept as is in file names. `/` is encoded so that every object of
+/// a bucket is a file of the same directory, since a key can be the prefix of
+/// another one, e.g. an image and its variants.
+const FILE_NAME_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+/// Size of the chunks objects are read in
+const CHUNK_LENGTH: usize = 64 * 1024;
+
+/// Stores the objects on the local filesystem, for development and small
+/// deployments without an object store. Every bucket is a directory of `root`
+/// holding the objects, a JSON sidecar per object with its metadata, and the
+/// parts of the multipart uploads in progress.
+pub struct Filesystem {
+    root: PathBuf,
+}
+
+/// Metadata S3 keeps along with an object
+#[derive(Debug, Serialize, Deserialize)]
+struct Sidecar {
+    content_type: String,
+    e_tag: String,
+    /// Unix time in seconds, the precision of S3 dates
+    last_modified: i64,
+}
+
+/// Multipart upload in progress, the parts are stored next to it
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingUpload {
+    key: String,
+    content_type: String,
+}
+
+impl Filesystem {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn bucket_path(&self, bucket: &str) -> PathBuf {
+        self.root.join(file_name(bucket))
+    }
+
+    fn object_path(&self, bucket: &str, key: &str) -> PathBuf {
+        self.bucket_path(bucket)
+            .join("objects")
+            .join(file_name(key))
+    }
+
+    fn sidecar_path(&self, bucket: &str, key: &str) -> PathBuf {
+        self.bucket_path(bucket)
+            .join("metadata")
+            .join(format!("{}.json", file_name(key)))
+    }
+
+    fn upload_path(&self, bucket: &str, upload_id: &str) -> PathBuf {
+        self.bucket_path(bucket)
+            .join("uploads")
+            .join(file_name(upload_id))
+    }
+
+    async fn staging(&self, bucket: &str) -> std::io::Result<Staging> {
+        Staging::new(&self.bucket_path(bucket).join("staging")).await
+    }
+
+    /// Writes a body to a staging file, which must receive `content_length`
+    /// bytes as S3 would require
+    async fn stage_body(
+        &self,
+        bucket: &str,
+        data: Body,
+        content_length: u64,
+    ) -> Result<Staging, S3Error> {
+        let mut staging = self
+            .staging(bucket)
+            .await
+            .map_err(|e| S3Error::UploadFailure(e.to_string()))?;
+        let mut chunks = data.into_data_stream();
+        while let Some(chunk) = chunks.next().await {
+            let written = match chunk {
+                Ok(chunk) => staging.write(&chunk).await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = written {
+                staging.discard().await;
+                return Err(S3Error::UploadFailure(e));
+            }
+        }
+        if staging.length != content_length {
+            let length = staging.length;
+            staging.discard().await;
+            return Err(S3Error::UploadFailure(format!(
+                "Expected {} bytes, received {}",
+                content_length, length
+            )));
+        }
+        Ok(staging)
+    }
+
+    /// Moves a staged object in place along with its sidecar
+    async fn persist(
+        &self,
+        bucket: &str,
+        key: &str,
+        staging: Staging,
+        sidecar: Sidecar,
+    ) -> std::io::Result<PathBuf> {
+        let object_path = self.object_path(bucket, key);
+        let sidecar_path = self.sidecar_path(bucket, key);
+        for path in [&object_path, &sidecar_path] {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+        }
+
+        let mut sidecar_staging = self.staging(bucket).await?;
+        sidecar_staging
+            .write(&serde_json::to_vec(&sidecar)?)
+            .await?;
+        staging.persist(&object_path).await?;
+        sidecar_staging.persist(&sidecar_path).await?;
+        Ok(object_path)
+    }
+
+    /// Reads the sidecar of an object. Objects copied without one are served
+    /// with a generic content type.
+    async fn sidecar(&self, bucket: &str, key: &str, object: &Path) -> Sidecar {
+        let sidecar = fs::read(self.sidecar_path(bucket, key))
+            .await
+            .ok()
+            .and_then(|sidecar| serde_json::from_slice(&sidecar).ok());
+        match sidecar {
+            Some(sidecar) => sidecar,
+            None => Sidecar {
+                content_type: "application/octet-stream".to_string(),
+                e_tag: String::new(),
+                last_modified: fs::metadata(object)
+                    .await
+                    .and_then(|metadata| metadata.modified())
+                    .map(|modified| DateTime::<Utc>::from(modified).timestamp())
+                    .unwrap_or_default(),
+            },
+        }
+    }
+
+    async fn pending_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<PendingUpload, S3Error> {
+        let upload = fs::read(self.upload_path(bucket, upload_id).join("upload.json"))
+            .await
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => S3Error::ObjectNotFound(upload_id.to_string()),
+                _ => S3Error::MultipartFailure(e.to_string()),
+            })?;
+        let upload: PendingUpload = serde_json::from_slice(&upload)
+            .map_err(|e| S3Error::MultipartFailure(e.to_string()))?;
+        // Upload ids are only valid for the key they were created for
+        if upload.key != key {
+            return Err(S3Error::ObjectNotFound(upload_id.to_string()));
+        }
+        Ok(upload)
+    }
+}
+
+impl S3 for Filesystem {
+    /// Writes a body to a staging file, then moves it in place so that
+    /// readers never see a partial object
+    async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        file: FileObject,
+    ) -> Result<String, S3Error> {
+        let staging = self
+            .stage_body(bucket, file.data, file.content_length)
+            .await?;
+        let e_tag = format!("\"{}\"", staging.digest());
+        let sidecar = Sidecar {
+            content_type: file.content_type,
+            e_tag: e_tag.clone(),
+            last_modified: Utc::now().timestamp(),
+        };
+        self.persist(bucket, key, staging, sidecar)
+            .await
+            .map_err(|e| S3Error::UploadFailure(e.to_string()))?;
+
+        Ok(e_tag)
+    }
+
+    /// Lists the directories of the root, each one being a bucket
+    async fn show_buckets(&self) -> Result<Vec<String>, S3Error> {
+        let mut entries = fs::read_dir(&self.root)
+            .await
+            .map_err(|e| S3Error::BucketNameError(e.to_string()))?;
+        let mut buckets = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| S3Error::BucketNameError(e.to_string()))?
+        {
+            if entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+                let name = entry.file_name();
+                buckets.push(
+                    percent_decode_str(&name.to_string_lossy())
+                        .decode_utf8_lossy()
+                        .into_owned(),
+                );
+            }
+        }
+        buckets.sort();
+        Ok(buckets)
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, S3Error> {
+        let path = self.object_path(bucket, key);
+        let mut file = fs::File::open(&path).await.map_err(|e| match e.kind() {
+            ErrorKind::NotFound => S3Error::ObjectNotFound(key.to_string()),
+            _ => S3Error::DownloadFailure(e.to_string()),
+        })?;
+        let length = file
+            .metadata()
+            .await
+            .map_err(|e| S3Error::DownloadFailure(e.to_string()))?
+            .len();
+        let sidecar = self.sidecar(bucket, key, &path).await;
+
+        let (content_length, content_range) = match range {
+            Some(range) => {
+                let (start, end) = range.resolve(length).ok_or_else(|| {
+                    S3Error::InvalidRange(
+                        format!("{} of an object of {} bytes", range, length),
+                        length,
+                    )
+                })?;
+                file.seek(std::io::SeekFrom::Start(start))
+                    .await
+                    .map_err(|e| S3Error::DownloadFailure(e.to_string()))?;
+                (
+                    end - start + 1,
+                    Some(format!("bytes {}-{}/{}", start, end, length)),
+                )
+            }
+            None => (length, None),
+        };
+
+        Ok(ObjectStream {
+            data: file_body(file.take(content_length)),
+            content_type: sidecar.content_type,
+            content_length,
+            content_range,
+            e_tag: Some(sidecar.e_tag).filter(|e_tag| !e_tag.is_empty()),
+            last_modified: DateTime::from_timestamp(sidecar.last_modified, 0),
+        })
+    }
+
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, S3Error> {
+        let path = self.object_path(bucket, key);
+        let metadata = fs::metadata(&path).await.map_err(|e| match e.kind() {
+            ErrorKind::NotFound => S3Error::ObjectNotFound(key.to_string()),
+            _ => S3Error::DownloadFailure(e.to_string()),
+        })?;
+        let sidecar = self.sidecar(bucket, key, &path).await;
+
+        Ok(ObjectMetadata {
+            content_type: sidecar.content_type,
+            content_length: metadata.len(),
+            e_tag: Some(sidecar.e_tag).filter(|e_tag| !e_tag.is_empty()),
+            last_modified: DateTime::from_timestamp(sidecar.last_modified, 0),
+        })
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), S3Error> {
+        fs::remove_file(self.object_path(bucket, key))
+            .await
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => S3Error::ObjectNotFound(key.to_string()),
+                _ => S3Error::DeleteFailure(e.to_string()),
+            })?;
+        if let Err(e) = fs::remove_file(self.sidecar_path(bucket, key)).await
+            && e.kind() != ErrorKind::NotFound
+        {
+            return Err(S3Error::DeleteFailure(e.to_string()));
+        }
+
+        info!("Deleted object {} from bucket {}", key, bucket);
+
+        Ok(())
+    }
+
+    /// Streams the object to a staging file, moved in place as uploads are
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        key: &str,
+    ) -> Result<String, S3Error> {
+        let source = self.get_object(bucket, source_key, None).await?;
+        let staging = self
+            .stage_body(bucket, source.data, source.content_length)
+            .await?;
+        let e_tag = format!("\"{}\"", staging.digest());
+        let sidecar = Sidecar {
+            content_type: source.content_type,
+            e_tag: e_tag.clone(),
+            last_modified: Utc::now().timestamp(),
+        };
+        self.persist(bucket, key, staging, sidecar)
+            .await
+            .map_err(|e| S3Error::UploadFailure(e.to_string()))?;
+
+        Ok(e_tag)
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, S3Error> {
+        let multipart_failure = |e: std::io::Error| S3Error::MultipartFailure(e.to_string());
+        let upload_id = unique_id(key);
+        let upload_path = self.upload_path(bucket, &upload_id);
+        fs::create_dir_all(&upload_path)
+            .await
+            .map_err(multipart_failure)?;
+
+        let upload = PendingUpload {
+            key: key.to_string(),
+            content_type: content_type.to_string(),
+        };
+        let upload = serde_json::to_vec(&upload).map_err(|e| multipart_failure(e.into()))?;
+        fs::write(upload_path.join("upload.json"), upload)
+            .await
+            .map_err(multipart_failure)?;
+
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u16,
+        data: Body,
+        content_length: u64,
+    ) -> Result<String, S3Error> {
+        self.pending_upload(bucket, key, upload_id).await?;
+
+        let staging = self.stage_body(bucket, data, content_length).await?;
+
+        let e_tag = format!("\"{}\"", staging.digest());
+        staging
+            .persist(
+                &self
+                    .upload_path(bucket, upload_id)
+                    .join(part_number.to_string()),
+            )
+            .await
+            .map_err(|e| S3Error::UploadFailure(e.to_string()))?;
+
+        Ok(e_tag)
+    }
+
+    /// Concatenates the parts, whose ETags are checked as they are read
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<String, S3Error> {
+        let upload = self.pending_upload(bucket, key, upload_id).await?;
+        if parts.is_empty() {
+            return Err(S3Error::InvalidMultipart("No part to assemble".to_string()));
+        }
+        if parts
+            .windows(2)
+            .any(|pair| pair[0].part_number >= pair[1].part_number)
+        {
+            return Err(S3Error::InvalidMultipart(
+                "Parts must be listed in ascending order".to_string(),
+            ));
+        }
+
+        let upload_path = self.upload_path(bucket, upload_id);
+        let multipart_failure = |e: std::io::Error| S3Error::MultipartFailure(e.to_string());
+        let mut staging = self.staging(bucket).await.map_err(multipart_failure)?;
+        for part in &parts {
+            if let Err(e) = append_part(&mut staging, &upload_path, part).await {
+                staging.discard().await;
+                return Err(e);
+            }
+        }
+
+        let e_tag = format!("\"{}-{}\"", staging.digest(), parts.len());
+        let sidecar = Sidecar {
+            content_type: upload.content_type,
+            e_tag: e_tag.clone(),
+            last_modified: Utc::now().timestamp(),
+        };
+        self.persist(bucket, key, staging, sidecar)
+            .await
+            .map_err(multipart_failure)?;
+        fs::remove_dir_all(&upload_path)
+            .await
+            .map_err(multipart_failure)?;
+
+        Ok(e_tag)
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), S3Error> {
+        self.pending_upload(bucket, key, upload_id).await?;
+        fs::remove_dir_all(self.upload_path(bucket, upload_id))
+            .await
+            .map_err(|e| S3Error::MultipartFailure(e.to_string()))
+    }
+
+    /// Only succeeds without rules, since nothing would expire the objects
+    async fn put_expiration_rules(
+        &self,
+        _bucket: &str,
+        rules: Vec<(String, u32)>,
+    ) -> Result<(), S3Error> {
+        if rules.is_empty() {
+            return Ok(());
+        }
+        Err(S3Error::LifecycleFailure(
+            "Expiration rules are not supported by the filesystem storage".to_string(),
+        ))
+    }
+
+    /// Lists every object file of the bucket, since they are all stored in
+    /// the same directory
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        options: ListOptions,
+    ) -> Result<ObjectList, S3Error> {
+        let list_failure = |e: std::io::Error| S3Error::ListFailure(e.to_string());
+        let mut entries = match fs::read_dir(self.bucket_path(bucket).join("objects")).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ObjectList::default()),
+            Err(e) => return Err(list_failure(e)),
+        };
+        let mut keys = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(list_failure)? {
+            keys.push(
+                percent_decode_str(&entry.file_name().to_string_lossy())
+                    .decode_utf8_lossy()
+                    .into_owned(),
+            );
+        }
+        let (keys, next_continuation_token) = page_keys(keys, prefix, &options);
+
+        let mut objects = Vec::with_capacity(keys.len());
+        for key in keys {
+            let path = self.object_path(bucket, &key);
+            // Deleted since the directory was read
+            let Ok(metadata) = fs::metadata(&path).await else {
+                continue;
+            };
+            let sidecar = self.sidecar(bucket, &key, &path).await;
+            objects.push(ListedObject {
+                key,
+                size: metadata.len(),
+                last_modified: DateTime::from_timestamp(sidecar.last_modified, 0),
+            });
+        }
+
+        Ok(ObjectList {
+            objects,
+            next_continuation_token,
+        })
+    }
+}
+
+/// File an object is written to before being moved in place
+struct Staging {
+    path: PathBuf,
+    file: fs::File,
+    length: u64,
+    hasher: Sha256,
+}
+
+impl Staging {
+    async fn new(directory: &Path) -> std::io::Result<Self> {
+        fs::create_dir_all(directory).await?;
+        let path = directory.join(unique_id("staging"));
+        let file = fs::File::create(&path).await?;
+        Ok(Self {
+            path,
+            file,
+            length: 0,
+            hasher: Sha256::new(),
+        })
+    }
+
+    async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(chunk).await?;
+        self.hasher.update(chunk);
+        self.length += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Hex digest of the bytes written so far, shortened to the length of an S3 ETag
+    fn digest(&self) -> String {
+        short_digest(self.hasher.clone())
+    }
+
+    async fn persist(mut self, path: &Path) -> std::io::Result<()> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        fs::rename(&self.path, path).await
+    }
+
+    async fn discard(self) {
+        drop(self.file);
+        let _ = fs::remove_file(&self.path).await;
+    }
+}
+
+async fn append_part(
+    staging: &mut Staging,
+    upload_path: &Path,
+    part: &UploadedPart,
+) -> Result<(), S3Error> {
+    let multipart_failure = |e: std::io::Error| S3Error::MultipartFailure(e.to_string());
+    let mut file = fs::File::open(upload_path.join(part.part_number.to_string()))
+        .await
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => {
+                S3Error::InvalidMultipart(format!("Part {} was not uploaded", part.part_number))
+            }
+            _ => multipart_failure(e),
+        })?;
+
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; CHUNK_LENGTH];
+    loop {
+        let length = file.read(&mut chunk).await.map_err(multipart_failure)?;
+        if length == 0 {
+            break;
+        }
+        hasher.update(&chunk[..length]);
+        staging
+            .write(&chunk[..length])
+            .await
+            .map_err(multipart_failure)?;
+    }
+
+    if format!("\"{}\"", short_digest(hasher)) != part.e_tag {
+        return Err(S3Error::InvalidMultipart(format!(
+            "ETag of part {} doesn't match",
+            part.part_number
+        )));
+    }
+    Ok(())
+}
+
+/// Streams a reader chunk by chunk
+fn file_body<R>(reader: R) -> Body
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let chunks = stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut chunk = vec![0; CHUNK_LENGTH];
+        match reader.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(length) => {
+                chunk.truncate(length);
+                Some((Ok(Bytes::from(chunk)), Some(reader)))
+            }
+            // The stream ends after the error
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    Body::from_stream(chunks)
+}
+
+/// Encodes a bucket name, key or upload id as a single path segment
+fn file_name(name: &str) -> String {
+    let encoded = utf8_percent_encode(name, FILE_NAME_ENCODE_SET).to_string();
+    // `.` and `..` designate directories
+    match encoded.strip_prefix('.') {
+        Some(rest) => format!("%2E{}", rest),
+        None => encoded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+
+    /// A filesystem storage in a directory of its own, removed when dropped
+    struct TestFilesystem(Filesystem);
+
+    impl TestFilesystem {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("content-{}-{}", name, unique_id(name)));
+            Self(Filesystem::new(root))
+        }
+    }
+
+    impl Drop for TestFilesystem {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0.root);
+        }
+    }
+
+    fn file(data: &'static str, content_type: &str) -> FileObject {
+        FileObject {
+            data: Body::from(data),
+            content_type: content_type.to_string(),
+            content_length: data.len() as u64,
+        }
+    }
+
+    async fn read(object: ObjectStream) -> String {
+        let data = to_bytes(object.data, usize::MAX)
+            .await
+            .expect("Unreadable object");
+        String::from_utf8(data.to_vec()).expect("Invalid UTF-8")
+    }
+
+    #[tokio::test]
+    async fn test_put_get_delete() {
+        let storage = TestFilesystem::new("objects");
+        let s3 = &storage.0;
+
+        s3.put_object("beep", "profile_picture/me.png", file("hello", "image/png"))
+            .await
+            .expect("Upload failed");
+        // Keys can be the prefix of other keys, as with variants
+        s3.put_object(
+            "beep",
+            "profile_picture/me.png/variants/a.webp",
+            file("variant", "image/webp"),
+        )
+        .await
+        .expect("Upload failed");
+
+        let object = s3
+            .get_object("beep", "profile_picture/me.png", None)
+            .await
+            .expect("Download failed");
+        assert_eq!(object.content_type, "image/png");
+        assert_eq!(object.content_length, 5);
+        assert!(object.e_tag.is_some());
+        assert_eq!(read(object).await, "hello");
+
+        let metadata = s3
+            .head_object("beep", "profile_picture/me.png/variants/a.webp")
+            .await
+            .expect("Head failed");
+        assert_eq!(metadata.content_type, "image/webp");
+        assert_eq!(metadata.content_length, 7);
+
+        assert_eq!(
+            s3.show_buckets().await.expect("Listing failed"),
+            vec!["beep".to_string()]
+        );
+        let listing = s3
+            .list_objects("beep", "profile_picture/", ListOptions::default())
+            .await
+            .expect("Listing failed");
+        assert_eq!(listing.objects.len(), 1);
+        assert_eq!(listing.objects[0].key, "profile_picture/me.png");
+        assert_eq!(listing.objects[0].size, 5);
+
+        s3.delete_object("beep", "profile_picture/me.png")
+            .await
+            .expect("Delete failed");
+        assert!(matches!(
+            s3.get_object("beep", "profile_picture/me.png", None).await,
+            Err(S3Error::ObjectNotFound(_))
+        ));
+        assert!(matches!(
+            s3.delete_object("beep", "profile_picture/me.png").await,
+            Err(S3Error::ObjectNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_range() {
+        let storage = TestFilesystem::new("range");
+        let s3 = &storage.0;
+        s3.put_object(
+            "beep",
+            "message_attachment/a.txt",
+            file("0123456789", "text/plain"),
+        )
+        .await
+        .expect("Upload failed");
+
+        let object = s3
+            .get_object(
+                "beep",
+                "message_attachment/a.txt",
+                Some(ByteRange::Bounded(2, 4)),
+            )
+            .await
+            .expect("Download failed");
+        assert_eq!(object.content_range.as_deref(), Some("bytes 2-4/10"));
+        assert_eq!(object.content_length, 3);
+        assert_eq!(read(object).await, "234");
+
+        assert!(matches!(
+            s3.get_object(
+                "beep",
+                "message_attachment/a.txt",
+                Some(ByteRange::From(10))
+            )
+            .await,
+            Err(S3Error::InvalidRange(_, 10))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_copy() {
+        let storage = TestFilesystem::new("copy");
+        let s3 = &storage.0;
+        s3.put_object(
+            "beep",
+            "message_attachment/a.txt",
+            file("hello", "text/plain"),
+        )
+        .await
+        .expect("Upload failed");
+
+        let e_tag = s3
+            .copy_object(
+                "beep",
+                "message_attachment/a.txt",
+                "message_attachment/b.txt",
+            )
+            .await
+            .expect("Copy failed");
+        let object = s3
+            .get_object("beep", "message_attachment/b.txt", None)
+            .await
+            .expect("Download failed");
+        assert_eq!(object.content_type, "text/plain");
+        assert_eq!(object.e_tag, Some(e_tag));
+        assert_eq!(read(object).await, "hello");
+
+        assert!(matches!(
+            s3.copy_object(
+                "beep",
+                "message_attachment/c.txt",
+                "message_attachment/d.txt"
+            )
+            .await,
+            Err(S3Error::ObjectNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_put_wrong_length() {
+        let storage = TestFilesystem::new("length");
+        let s3 = &storage.0;
+        let mut wrong_length = file("hello", "text/plain");
+        wrong_length.content_length = 10;
+        assert!(matches!(
+            s3.put_object("beep", "message_attachment/a.txt", wrong_length)
+                .await,
+            Err(S3Error::UploadFailure(_))
+        ));
+        assert!(matches!(
+            s3.head_object("beep", "message_attachment/a.txt").await,
+            Err(S3Error::ObjectNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_multipart() {
+        let storage = TestFilesystem::new("multipart");
+        let s3 = &storage.0;
+        let key = "message_attachment/video.mp4";
+
+        let upload_id = s3
+            .create_multipart_upload("beep", key, "video/mp4")
+            .await
+            .expect("Creation failed");
+        let mut parts = Vec::new();
+        for (part_number, data) in [(1, "hello "), (2, "world")] {
+            let e_tag = s3
+                .upload_part(
+                    "beep",
+                    key,
+                    &upload_id,
+                    part_number,
+                    Body::from(data),
+                    data.len() as u64,
+                )
+                .await
+                .expect("Part upload failed");
+            parts.push(UploadedPart { part_number, e_tag });
+        }
+
+        let mut wrong_parts = parts.clone();
+        wrong_parts[1].e_tag = "\"wrong\"".to_string();
+        assert!(matches!(
+            s3.complete_multipart_upload("beep", key, &upload_id, wrong_parts)
+                .await,
+            Err(S3Error::InvalidMultipart(_))
+        ));
+        assert!(matches!(
+            s3.complete_multipart_upload(
+                "beep",
+                "message_attachment/other.mp4",
+                &upload_id,
+                vec![]
+            )
+            .await,
+            Err(S3Error::ObjectNotFound(_))
+        ));
+
+        s3.complete_multipart_upload("beep", key, &upload_id, parts)
+            .await
+            .expect("Completion failed");
+        let object = s3
+            .get_object("beep", key, None)
+            .await
+            .expect("Download failed");
+        assert_eq!(object.content_type, "video/mp4");
+        assert_eq!(read(object).await, "hello world");
+
+        assert!(matches!(
+            s3.abort_multipart_upload("beep", key, &upload_id).await,
+            Err(S3Error::ObjectNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,642 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::body::{Body, Bytes, to_bytes};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    range::ByteRange,
+    s3::{
+        FileObject, ListOptions, ListedObject, ObjectList, ObjectMetadata, ObjectStream, S3,
+        S3Error, UploadedPart, page_keys, short_digest,
+    },
+};
+
+/// Operations of the `S3` trait, whose failures can be injected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    PutObject,
+    ShowBuckets,
+    GetObject,
+    HeadObject,
+    DeleteObject,
+    CopyObject,
+    CreateMultipartUpload,
+    UploadPart,
+    CompleteMultipartUpload,
+    AbortMultipartUpload,
+    PutExpirationRules,
+    ListObjects,
+}
+
+impl Operation {
+    /// Error an injected failure of the operation is reported as, the one
+    /// Garage failures are reported as
+    fn failure(self) -> S3Error {
+        let message = format!("Injected failure of {:?}", self);
+        match self {
+            Operation::PutObject | Operation::CopyObject | Operation::UploadPart => {
+                S3Error::UploadFailure(message)
+            }
+            Operation::ShowBuckets => S3Error::BucketNameError(message),
+            Operation::GetObject | Operation::HeadObject => S3Error::DownloadFailure(message),
+            Operation::DeleteObject => S3Error::DeleteFailure(message),
+            Operation::CreateMultipartUpload
+            | Operation::CompleteMultipartUpload
+            | Operation::AbortMultipartUpload => S3Error::MultipartFailure(message),
+            Operation::PutExpirationRules => S3Error::LifecycleFailure(message),
+            Operation::ListObjects => S3Error::ListFailure(message),
+        }
+    }
+}
+
+/// Keeps the objects in memory, so that the service can be run end to end
+/// without any object store, e.g. by hermetic tests. Failures of each
+/// operation and latency can be injected while the service is running.
+#[derive(Default)]
+pub struct Memory {
+    objects: Mutex<BTreeMap<(String, String), StoredObject>>,
+    uploads: Mutex<HashMap<String, PendingUpload>>,
+    expiration_rules: Mutex<HashMap<String, Vec<(String, u32)>>>,
+    failures: Mutex<HashSet<Operation>>,
+    latency_ms: AtomicU64,
+    upload_ids: AtomicU64,
+}
+
+struct StoredObject {
+    data: Bytes,
+    content_type: String,
+    e_tag: String,
+    last_modified: DateTime<Utc>,
+}
+
+struct PendingUpload {
+    bucket: String,
+    key: String,
+    content_type: String,
+    parts: BTreeMap<u16, Bytes>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes every call of `operation` fail until `recover` is called
+    pub fn fail(&self, operation: Operation) {
+        self.failures
+            .lock()
+            .expect("Poisoned failures")
+            .insert(operation);
+    }
+
+    pub fn recover(&self, operation: Operation) {
+        self.failures
+            .lock()
+            .expect("Poisoned failures")
+            .remove(&operation);
+    }
+
+    /// Delays every operation by `latency`
+    pub fn set_latency(&self, latency: Duration) {
+        self.latency_ms
+            .store(latency.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Expiration rules last applied to `bucket`
+    pub fn expiration_rules(&self, bucket: &str) -> Vec<(String, u32)> {
+        self.expiration_rules
+            .lock()
+            .expect("Poisoned expiration rules")
+            .get(bucket)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Waits for the injected latency, then fails if a failure of `operation` is injected
+    async fn enter(&self, operation: Operation) -> Result<(), S3Error> {
+        let latency = self.latency_ms.load(Ordering::Relaxed);
+        if latency > 0 {
+            tokio::time::sleep(Duration::from_millis(latency)).await;
+        }
+        if self
+            .failures
+            .lock()
+            .expect("Poisoned failures")
+            .contains(&operation)
+        {
+            return Err(operation.failure());
+        }
+        Ok(())
+    }
+
+    fn store(&self, bucket: &str, key: &str, object: StoredObject) {
+        self.objects
+            .lock()
+            .expect("Poisoned objects")
+            .insert((bucket.to_string(), key.to_string()), object);
+    }
+}
+
+/// Reads a body, which must be `content_length` bytes long as S3 would require
+async fn read_body(data: Body, content_length: u64) -> Result<Bytes, S3Error> {
+    let data = to_bytes(data, usize::MAX)
+        .await
+        .map_err(|e| S3Error::UploadFailure(e.to_string()))?;
+    if data.len() as u64 != content_length {
+        return Err(S3Error::UploadFailure(format!(
+            "Expected {} bytes, received {}",
+            content_length,
+            data.len()
+        )));
+    }
+    Ok(data)
+}
+
+fn e_tag(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("\"{}\"", short_digest(hasher))
+}
+
+/// S3 dates have a precision of a second
+fn now() -> DateTime<Utc> {
+    DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap_or_default()
+}
+
+impl S3 for Memory {
+    async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        file: FileObject,
+    ) -> Result<String, S3Error> {
+        self.enter(Operation::PutObject).await?;
+        let data = read_body(file.data, file.content_length).await?;
+        let e_tag = e_tag(&data);
+        self.store(
+            bucket,
+            key,
+            StoredObject {
+                e_tag: e_tag.clone(),
+                data,
+                content_type: file.content_type,
+                last_modified: now(),
+            },
+        );
+        Ok(e_tag)
+    }
+
+    /// Lists the buckets holding objects
+    async fn show_buckets(&self) -> Result<Vec<String>, S3Error> {
+        self.enter(Operation::ShowBuckets).await?;
+        let mut buckets: Vec<String> = self
+            .objects
+            .lock()
+            .expect("Poisoned objects")
+            .keys()
+            .map(|(bucket, _)| bucket.clone())
+            .collect();
+        buckets.dedup();
+        Ok(buckets)
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, S3Error> {
+        self.enter(Operation::GetObject).await?;
+        let objects = self.objects.lock().expect("Poisoned objects");
+        let object = objects
+            .get(&(bucket.to_string(), key.to_string()))
+            .ok_or_else(|| S3Error::ObjectNotFound(key.to_string()))?;
+
+        let length = object.data.len() as u64;
+        let (data, content_range) = match range {
+            Some(range) => {
+                let (start, end) = range.resolve(length).ok_or_else(|| {
+                    S3Error::InvalidRange(
+                        format!("{} of an object of {} bytes", range, length),
+                        length,
+                    )
+                })?;
+                (
+                    object.data.slice(start as usize..=end as usize),
+                    Some(format!("bytes {}-{}/{}", start, end, length)),
+                )
+            }
+            None => (object.data.clone(), None),
+        };
+
+        Ok(ObjectStream {
+            content_length: data.len() as u64,
+            data: Body::from(data),
+            content_type: object.content_type.clone(),
+            content_range,
+            e_tag: Some(object.e_tag.clone()),
+            last_modified: Some(object.last_modified),
+        })
+    }
+
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, S3Error> {
+        self.enter(Operation::HeadObject).await?;
+        let objects = self.objects.lock().expect("Poisoned objects");
+        let object = objects
+            .get(&(bucket.to_string(), key.to_string()))
+            .ok_or_else(|| S3Error::ObjectNotFound(key.to_string()))?;
+
+        Ok(ObjectMetadata {
+            content_type: object.content_type.clone(),
+            content_length: object.data.len() as u64,
+            e_tag: Some(object.e_tag.clone()),
+            last_modified: Some(object.last_modified),
+        })
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), S3Error> {
+        self.enter(Operation::DeleteObject).await?;
+        self.objects
+            .lock()
+            .expect("Poisoned objects")
+            .remove(&(bucket.to_string(), key.to_string()))
+            .ok_or_else(|| S3Error::ObjectNotFound(key.to_string()))?;
+
+        info!("Deleted object {} from bucket {}", key, bucket);
+
+        Ok(())
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        key: &str,
+    ) -> Result<String, S3Error> {
+        self.enter(Operation::CopyObject).await?;
+        let source = self
+            .objects
+            .lock()
+            .expect("Poisoned objects")
+            .get(&(bucket.to_string(), source_key.to_string()))
+            .map(|source| (source.data.clone(), source.content_type.clone()))
+            .ok_or_else(|| S3Error::ObjectNotFound(source_key.to_string()))?;
+        let (data, content_type) = source;
+        let e_tag = e_tag(&data);
+        self.store(
+            bucket,
+            key,
+            StoredObject {
+                e_tag: e_tag.clone(),
+                data,
+                content_type,
+                last_modified: now(),
+            },
+        );
+        Ok(e_tag)
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, S3Error> {
+        self.enter(Operation::CreateMultipartUpload).await?;
+        let upload_id = format!("upload-{}", self.upload_ids.fetch_add(1, Ordering::Relaxed));
+        self.uploads.lock().expect("Poisoned uploads").insert(
+            upload_id.clone(),
+            PendingUpload {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+                content_type: content_type.to_string(),
+                parts: BTreeMap::new(),
+            },
+        );
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u16,
+        data: Body,
+        content_length: u64,
+    ) -> Result<String, S3Error> {
+        self.enter(Operation::UploadPart).await?;
+        let data = read_body(data, content_length).await?;
+        let e_tag = e_tag(&data);
+
+        let mut uploads = self.uploads.lock().expect("Poisoned uploads");
+        let upload = uploads
+            .get_mut(upload_id)
+            .filter(|upload| upload.bucket == bucket && upload.key == key)
+            .ok_or_else(|| S3Error::ObjectNotFound(upload_id.to_string()))?;
+        upload.parts.insert(part_number, data);
+        Ok(e_tag)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<String, S3Error> {
+        self.enter(Operation::CompleteMultipartUpload).await?;
+        let mut uploads = self.uploads.lock().expect("Poisoned uploads");
+        let upload = uploads
+            .get(upload_id)
+            .filter(|upload| upload.bucket == bucket && upload.key == key)
+            .ok_or_else(|| S3Error::ObjectNotFound(upload_id.to_string()))?;
+        if parts.is_empty() {
+            return Err(S3Error::InvalidMultipart("No part to assemble".to_string()));
+        }
+        if parts
+            .windows(2)
+            .any(|pair| pair[0].part_number >= pair[1].part_number)
+        {
+            return Err(S3Error::InvalidMultipart(
+                "Parts must be listed in ascending order".to_string(),
+            ));
+        }
+
+        let mut data = Vec::new();
+        for part in &parts {
+            let uploaded = upload
+                .parts
+                .get(&part.part_number)
+                .filter(|uploaded| e_tag(uploaded) == part.e_tag)
+                .ok_or_else(|| {
+                    S3Error::InvalidMultipart(format!(
+                        "Part {} was not uploaded with this ETag",
+                        part.part_number
+                    ))
+                })?;
+            data.extend_from_slice(uploaded);
+        }
+
+        let e_tag = format!("{}-{}\"", e_tag(&data).trim_end_matches('"'), parts.len());
+        let object = StoredObject {
+            e_tag: e_tag.clone(),
+            data: Bytes::from(data),
+            content_type: upload.content_type.clone(),
+            last_modified: now(),
+        };
+        uploads.remove(upload_id);
+        drop(uploads);
+        self.store(bucket, key, object);
+
+        Ok(e_tag)
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), S3Error> {
+        self.enter(Operation::AbortMultipartUpload).await?;
+        let mut uploads = self.uploads.lock().expect("Poisoned uploads");
+        if !uploads
+            .get(upload_id)
+            .is_some_and(|upload| upload.bucket == bucket && upload.key == key)
+        {
+            return Err(S3Error::ObjectNotFound(upload_id.to_string()));
+        }
+        uploads.remove(upload_id);
+        Ok(())
+    }
+
+    /// Records the rules without expiring anything
+    async fn put_expiration_rules(
+        &self,
+        bucket: &str,
+        rules: Vec<(String, u32)>,
+    ) -> Result<(), S3Error> {
+        self.enter(Operation::PutExpirationRules).await?;
+        self.expiration_rules
+            .lock()
+            .expect("Poisoned expiration rules")
+            .insert(bucket.to_string(), rules);
+        Ok(())
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        options: ListOptions,
+    ) -> Result<ObjectList, S3Error> {
+        self.enter(Operation::ListObjects).await?;
+        let objects = self.objects.lock().expect("Poisoned objects");
+        let keys = objects
+            .keys()
+            .filter(|(object_bucket, _)| object_bucket == bucket)
+            .map(|(_, key)| key.clone())
+            .collect();
+        let (keys, next_continuation_token) = page_keys(keys, prefix, &options);
+
+        Ok(ObjectList {
+            objects: keys
+                .into_iter()
+                .filter_map(|key| {
+                    let object = objects.get(&(bucket.to_string(), key.clone()))?;
+                    Some(ListedObject {
+                        size: object.data.len() as u64,
+                        last_modified: Some(object.last_modified),
+                        key,
+                    })
+                })
+                .collect(),
+            next_continuation_token,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn file(data: &'static str) -> FileObject {
+        FileObject {
+            data: Body::from(data),
+            content_type: "text/plain".to_string(),
+            content_length: data.len() as u64,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_objects() {
+        let s3 = Memory::new();
+        s3.put_object("beep", "message_attachment/a.txt", file("0123456789"))
+            .await
+            .expect("Upload failed");
+
+        let object = s3
+            .get_object(
+                "beep",
+                "message_attachment/a.txt",
+                Some(ByteRange::Suffix(3)),
+            )
+            .await
+            .expect("Download failed");
+        assert_eq!(object.content_range.as_deref(), Some("bytes 7-9/10"));
+        let data = to_bytes(object.data, usize::MAX)
+            .await
+            .expect("Unreadable object");
+        assert_eq!(&data[..], b"789");
+
+        assert_eq!(
+            s3.show_buckets().await.expect("Listing failed"),
+            vec!["beep".to_string()]
+        );
+        s3.delete_object("beep", "message_attachment/a.txt")
+            .await
+            .expect("Delete failed");
+        assert!(matches!(
+            s3.head_object("beep", "message_attachment/a.txt").await,
+            Err(S3Error::ObjectNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_objects() {
+        let s3 = Memory::new();
+        for key in [
+            "profile_picture/b.png",
+            "profile_picture/a.png",
+            "profile_picture/a.png/variants/small.webp",
+            "profile_picture/c.png",
+            "server_banner/a.png",
+        ] {
+            s3.put_object("beep", key, file("image"))
+                .await
+                .expect("Upload failed");
+        }
+
+        let options = ListOptions {
+            max_keys: Some(2),
+            ..Default::default()
+        };
+        let page = s3
+            .list_objects("beep", "profile_picture/", options.clone())
+            .await
+            .expect("Listing failed");
+        let keys: Vec<&str> = page.objects.iter().map(|o| o.key.as_str()).collect();
+        assert_eq!(keys, ["profile_picture/a.png", "profile_picture/b.png"]);
+        assert_eq!(page.objects[0].size, 5);
+
+        let page = s3
+            .list_objects(
+                "beep",
+                "profile_picture/",
+                ListOptions {
+                    continuation_token: page.next_continuation_token,
+                    ..options
+                },
+            )
+            .await
+            .expect("Listing failed");
+        let keys: Vec<&str> = page.objects.iter().map(|o| o.key.as_str()).collect();
+        assert_eq!(keys, ["profile_picture/c.png"]);
+        assert_eq!(page.next_continuation_token, None);
+
+        let page = s3
+            .list_objects(
+                "beep",
+                "profile_picture/",
+                ListOptions {
+                    start_after: Some("profile_picture/a.png".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("Listing failed");
+        assert_eq!(page.objects.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_multipart() {
+        let s3 = Memory::new();
+        let key = "message_attachment/video.mp4";
+        let upload_id = s3
+            .create_multipart_upload("beep", key, "video/mp4")
+            .await
+            .expect("Creation failed");
+        let e_tag = s3
+            .upload_part("beep", key, &upload_id, 1, Body::from("hello"), 5)
+            .await
+            .expect("Part upload failed");
+
+        assert!(matches!(
+            s3.complete_multipart_upload(
+                "beep",
+                key,
+                &upload_id,
+                vec![UploadedPart {
+                    part_number: 1,
+                    e_tag: "\"wrong\"".to_string(),
+                }],
+            )
+            .await,
+            Err(S3Error::InvalidMultipart(_))
+        ));
+        s3.complete_multipart_upload(
+            "beep",
+            key,
+            &upload_id,
+            vec![UploadedPart {
+                part_number: 1,
+                e_tag,
+            }],
+        )
+        .await
+        .expect("Completion failed");
+
+        let metadata = s3.head_object("beep", key).await.expect("Head failed");
+        assert_eq!(metadata.content_type, "video/mp4");
+        assert_eq!(metadata.content_length, 5);
+        assert!(matches!(
+            s3.abort_multipart_upload("beep", key, &upload_id).await,
+            Err(S3Error::ObjectNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures() {
+        let s3 = Memory::new();
+        s3.fail(Operation::PutObject);
+        assert!(matches!(
+            s3.put_object("beep", "message_attachment/a.txt", file("a"))
+                .await,
+            Err(S3Error::UploadFailure(_))
+        ));
+        s3.recover(Operation::PutObject);
+        assert!(
+            s3.put_object("beep", "message_attachment/a.txt", file("a"))
+                .await
+                .is_ok()
+        );
+
+        s3.set_latency(Duration::from_millis(50));
+        let start = Instant::now();
+        s3.head_object("beep", "message_attachment/a.txt")
+            .await
+            .expect("Head failed");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
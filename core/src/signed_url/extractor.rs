@@ -1,6 +1,6 @@
 use crate::{
     app::AppStateOperations,
-    signed_url::service::{AvailableActions, SignedUrlError},
+    signed_url::service::{AvailableActions, BoundParams, SignedUrlError},
 };
 use axum::extract::FromRequestParts;
 
@@ -9,6 +9,8 @@ pub struct Claims {
     pub action: AvailableActions,
     // We consider a signed url to be only valid if its made of a path and a file name
     pub path: (String, String),
+    pub expires: u64,
+    pub params: BoundParams,
 }
 
 #[derive(Debug, Clone)]
@@ -2,17 +2,19 @@ use std::path::Path;
 
 use axum::response::IntoResponse;
 use base64::{Engine as _, engine::general_purpose::URL_SAFE};
+use clap::Args;
 use http::{StatusCode, Uri, uri::Scheme};
 use mockall::automock;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
 use serde::{Deserialize, Serialize};
 use strum_macros::Display;
 use thiserror::Error;
 use utoipa::ToSchema;
 
 use crate::{
-    error::CoreError,
+    error::{ApiError, CoreError},
     signed_url::extractor::Claims,
-    signer::{HMACSigner, Signer},
+    signer::{KeyringSigner, Signer},
     utils::{RealTime, Time},
 };
 
@@ -22,6 +24,26 @@ pub enum AvailableActions {
     Put,
     Get,
     Delete,
+    CreateMultipartUpload,
+    UploadPart,
+    CompleteMultipartUpload,
+    AbortMultipartUpload,
+    /// Listing the objects of a prefix, which is authenticated by the bearer
+    /// token of the caller rather than signed
+    List,
+}
+
+impl AvailableActions {
+    /// Multipart actions are served under the `/{prefix}/{file_name}/multipart` sub-resource
+    pub fn is_multipart(&self) -> bool {
+        matches!(
+            self,
+            AvailableActions::CreateMultipartUpload
+                | AvailableActions::UploadPart
+                | AvailableActions::CompleteMultipartUpload
+                | AvailableActions::AbortMultipartUpload
+        )
+    }
 }
 
 impl From<AvailableActions> for http::Method {
@@ -30,6 +52,11 @@ impl From<AvailableActions> for http::Method {
             AvailableActions::Put => http::Method::PUT,
             AvailableActions::Get => http::Method::GET,
             AvailableActions::Delete => http::Method::DELETE,
+            AvailableActions::CreateMultipartUpload => http::Method::POST,
+            AvailableActions::UploadPart => http::Method::PUT,
+            AvailableActions::CompleteMultipartUpload => http::Method::POST,
+            AvailableActions::AbortMultipartUpload => http::Method::DELETE,
+            AvailableActions::List => http::Method::GET,
         }
     }
 }
@@ -38,10 +65,106 @@ impl From<AvailableActions> for http::Method {
 pub struct SignedURLParams {
     pub action: AvailableActions,
     pub expires: u64,
+    pub upload_id: Option<String>,
+    pub staging_id: Option<String>,
+    pub part_number: Option<u16>,
+    pub content_type: Option<String>,
+    pub max_content_length: Option<u64>,
+    pub kid: Option<String>,
     pub signature: String,
 }
 
-pub type HMACUrlService = SignedUrlServiceImpl<HMACSigner, RealTime>;
+/// A freshly signed url along with its expiration date, in Unix seconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuedUrl {
+    pub url: String,
+    pub expires_at: u64,
+}
+
+/// Longest lifetime of the Put urls when `MAX_PUT_TTL_SECS` is unset, in seconds
+pub const DEFAULT_MAX_PUT_TTL_SECS: u64 = 15 * 60;
+pub const DEFAULT_MAX_GET_TTL_SECS: u64 = 60 * 60;
+pub const DEFAULT_MAX_DELETE_TTL_SECS: u64 = 5 * 60;
+pub const DEFAULT_MAX_MULTIPART_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Longest lifetime, in seconds, of the urls signed for each action.
+/// The urls of an ongoing multipart upload expire along with the one starting it.
+#[derive(Args, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxTtls {
+    #[clap(
+        env = "MAX_PUT_TTL_SECS",
+        long = "max-put-ttl-secs",
+        value_name = "MAX_PUT_TTL_SECS",
+        default_value_t = DEFAULT_MAX_PUT_TTL_SECS,
+        help = "Longest lifetime of the Put urls, in seconds"
+    )]
+    pub put: u64,
+
+    #[clap(
+        env = "MAX_GET_TTL_SECS",
+        long = "max-get-ttl-secs",
+        value_name = "MAX_GET_TTL_SECS",
+        default_value_t = DEFAULT_MAX_GET_TTL_SECS,
+        help = "Longest lifetime of the Get urls, in seconds"
+    )]
+    pub get: u64,
+
+    #[clap(
+        env = "MAX_DELETE_TTL_SECS",
+        long = "max-delete-ttl-secs",
+        value_name = "MAX_DELETE_TTL_SECS",
+        default_value_t = DEFAULT_MAX_DELETE_TTL_SECS,
+        help = "Longest lifetime of the Delete urls, in seconds"
+    )]
+    pub delete: u64,
+
+    #[clap(
+        env = "MAX_MULTIPART_TTL_SECS",
+        long = "max-multipart-ttl-secs",
+        value_name = "MAX_MULTIPART_TTL_SECS",
+        default_value_t = DEFAULT_MAX_MULTIPART_TTL_SECS,
+        help = "Longest lifetime of the multipart upload urls, in seconds"
+    )]
+    pub multipart: u64,
+}
+
+impl Default for MaxTtls {
+    fn default() -> Self {
+        Self {
+            put: DEFAULT_MAX_PUT_TTL_SECS,
+            get: DEFAULT_MAX_GET_TTL_SECS,
+            delete: DEFAULT_MAX_DELETE_TTL_SECS,
+            multipart: DEFAULT_MAX_MULTIPART_TTL_SECS,
+        }
+    }
+}
+
+impl MaxTtls {
+    pub fn for_action(&self, action: AvailableActions) -> u64 {
+        match action {
+            AvailableActions::Put => self.put,
+            AvailableActions::Get => self.get,
+            AvailableActions::Delete => self.delete,
+            _ => self.multipart,
+        }
+    }
+}
+
+/// Optional query params covered by the signature along with the path,
+/// the action and the expiration date.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BoundParams {
+    pub upload_id: Option<String>,
+    /// Names the key a multipart upload is assembled under before being checked
+    pub staging_id: Option<String>,
+    pub part_number: Option<u16>,
+    /// Content type an upload must be sent with
+    pub content_type: Option<String>,
+    /// Maximum length in bytes of an upload
+    pub max_content_length: Option<u64>,
+}
+
+pub type HMACUrlService = SignedUrlServiceImpl<KeyringSigner, RealTime>;
 
 #[derive(Debug, Error)]
 pub enum SignedUrlError {
@@ -57,6 +180,14 @@ pub enum SignedUrlError {
     Expired,
     #[error("Invalid signature")]
     InvalidSignature,
+    #[error("Unsupported action: {0}")]
+    UnsupportedAction(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Expiration too far, {0} urls last at most {1} seconds")]
+    TtlTooLong(AvailableActions, u64),
+    #[error("Unknown prefix: {0}")]
+    UnknownPrefix(String),
 }
 
 impl IntoResponse for SignedUrlError {
@@ -68,11 +199,22 @@ impl IntoResponse for SignedUrlError {
             SignedUrlError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             SignedUrlError::Expired => StatusCode::UNAUTHORIZED,
             SignedUrlError::InvalidSignature => StatusCode::UNAUTHORIZED,
+            SignedUrlError::UnsupportedAction(_) => StatusCode::BAD_REQUEST,
+            SignedUrlError::Forbidden(_) => StatusCode::FORBIDDEN,
+            SignedUrlError::TtlTooLong(_, _) => StatusCode::BAD_REQUEST,
+            SignedUrlError::UnknownPrefix(_) => StatusCode::NOT_FOUND,
         };
         (status, self.to_string()).into_response()
     }
 }
 
+#[allow(clippy::from_over_into)]
+impl Into<ApiError> for SignedUrlError {
+    fn into(self) -> ApiError {
+        ApiError::InternalServerError(self.to_string())
+    }
+}
+
 impl From<SignedUrlError> for CoreError {
     fn from(e: SignedUrlError) -> Self {
         CoreError::SigningKeyError(e.to_string())
@@ -84,11 +226,22 @@ pub trait SignedUrlService<S>
 where
     S: Signer,
 {
+    /// Signs an url valid for `expires_in_secs` seconds, refusing lifetimes
+    /// longer than the maximum allowed for the action
     fn sign_url(
         &self,
         prefix: String,
         action: AvailableActions,
-        expires_in_ms: u64,
+        expires_in_secs: u64,
+        params: BoundParams,
+    ) -> Result<IssuedUrl, SignedUrlError>;
+    /// Signs an url valid until the absolute `expires` date, binding `params` to it
+    fn sign_url_until(
+        &self,
+        prefix: String,
+        action: AvailableActions,
+        expires: u64,
+        params: BoundParams,
     ) -> Result<String, SignedUrlError>;
     #[allow(dead_code)]
     fn verify_url(&self, url: &str) -> Result<Claims, SignedUrlError>;
@@ -103,6 +256,7 @@ where
     signer: S,
     time: T,
     base_url: Uri,
+    max_ttls: MaxTtls,
 }
 
 impl<S, T> SignedUrlServiceImpl<S, T>
@@ -118,14 +272,22 @@ where
             signer,
             time,
             base_url,
+            max_ttls: MaxTtls::default(),
         })
     }
 
+    pub fn with_max_ttls(mut self, max_ttls: MaxTtls) -> Self {
+        self.max_ttls = max_ttls;
+        self
+    }
+
     fn build_signable_url(
         &self,
         prefix: String,
         action: AvailableActions,
         duration: u64,
+        params: &BoundParams,
+        kid: Option<&str>,
     ) -> Result<String, SignedUrlError> {
         let path = self.base_url.path();
         let path = Path::new(path).join(prefix);
@@ -135,7 +297,34 @@ where
             ));
         };
 
-        let query = format!("?action={}&expires={}", action, duration);
+        let mut query = format!("?action={}&expires={}", action, duration);
+        if let Some(upload_id) = &params.upload_id {
+            query.push_str(&format!(
+                "&upload_id={}",
+                utf8_percent_encode(upload_id, NON_ALPHANUMERIC)
+            ));
+        }
+        if let Some(staging_id) = &params.staging_id {
+            query.push_str(&format!(
+                "&staging_id={}",
+                utf8_percent_encode(staging_id, NON_ALPHANUMERIC)
+            ));
+        }
+        if let Some(part_number) = params.part_number {
+            query.push_str(&format!("&part_number={}", part_number));
+        }
+        if let Some(content_type) = &params.content_type {
+            query.push_str(&format!(
+                "&content_type={}",
+                utf8_percent_encode(content_type, NON_ALPHANUMERIC)
+            ));
+        }
+        if let Some(max_content_length) = params.max_content_length {
+            query.push_str(&format!("&max_content_length={}", max_content_length));
+        }
+        if let Some(kid) = kid {
+            query.push_str(&format!("&kid={}", kid));
+        }
         let scheme = self.base_url.scheme().unwrap_or(&Scheme::HTTPS);
         let scheme = scheme.as_str();
         let Some(authority) = self.base_url.authority() else {
@@ -165,10 +354,27 @@ where
         &self,
         prefix: String,
         action: AvailableActions,
-        expires_in_ms: u64,
+        expires_in_secs: u64,
+        params: BoundParams,
+    ) -> Result<IssuedUrl, SignedUrlError> {
+        let max_ttl = self.max_ttls.for_action(action);
+        if expires_in_secs > max_ttl {
+            return Err(SignedUrlError::TtlTooLong(action, max_ttl));
+        }
+        let expires_at = self.time.now_secs() + expires_in_secs;
+        let url = self.sign_url_until(prefix, action, expires_at, params)?;
+        Ok(IssuedUrl { url, expires_at })
+    }
+
+    fn sign_url_until(
+        &self,
+        prefix: String,
+        action: AvailableActions,
+        expires: u64,
+        params: BoundParams,
     ) -> Result<String, SignedUrlError> {
-        let duration = self.time.now() + expires_in_ms;
-        let url = self.build_signable_url(prefix, action, duration)?;
+        let kid = self.signer.key_id();
+        let url = self.build_signable_url(prefix, action, expires, &params, kid.as_deref())?;
 
         let signature = self
             .signer
@@ -194,19 +400,28 @@ where
         let Ok(signature) = URL_SAFE.decode(parsed_params.signature) else {
             return Err(SignedUrlError::InvalidEncoding);
         };
+        let params = BoundParams {
+            upload_id: parsed_params.upload_id,
+            staging_id: parsed_params.staging_id,
+            part_number: parsed_params.part_number,
+            content_type: parsed_params.content_type,
+            max_content_length: parsed_params.max_content_length,
+        };
         let url = self.build_signable_url(
             prefix.to_string(),
             parsed_params.action,
             parsed_params.expires,
+            &params,
+            parsed_params.kid.as_deref(),
         )?;
         if !self
             .signer
-            .verify(url.as_bytes(), &signature)
+            .verify(parsed_params.kid.as_deref(), url.as_bytes(), &signature)
             .map_err(|e| SignedUrlError::InternalError(e.to_string()))?
         {
             return Err(SignedUrlError::InvalidSignature);
         };
-        let now = self.time.now();
+        let now = self.time.now_secs();
         if parsed_params.expires < now {
             return Err(SignedUrlError::Expired);
         }
@@ -221,6 +436,8 @@ where
         Ok(Claims {
             action,
             path: (path.0.to_string(), path.1.to_string()),
+            expires: parsed_params.expires,
+            params,
         })
     }
 
@@ -228,7 +445,10 @@ where
         let uri = parts.uri.to_string();
         let claims = self.verify_url(&uri)?;
         let action_as_method: http::Method = claims.action.into();
-        if parts.method != action_as_method {
+        // The url signed to download an object also fetches its metadata
+        let head_of_get =
+            claims.action == AvailableActions::Get && parts.method == http::Method::HEAD;
+        if parts.method != action_as_method && !head_of_get {
             return Err(SignedUrlError::InvalidSignature);
         }
         Ok(claims)
@@ -241,7 +461,10 @@ mod tests {
     use http::Request;
 
     use super::*;
-    use crate::{signer::HMACSigner, utils::tests::get_time};
+    use crate::{
+        signer::{HMACSigner, KeyringSigner},
+        utils::tests::get_time,
+    };
 
     pub fn sign_url(prefix: String, action: AvailableActions, expires: u64) -> String {
         let now: u64 = chrono::Utc::now()
@@ -249,30 +472,67 @@ mod tests {
             .try_into()
             .expect("Time shouldnt be negative");
         let duration = now + expires;
-        let signer = HMACSigner::new("test".to_string()).expect("Invalid key");
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
         let time = get_time();
         let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
             .expect("Invalid signer");
         service
-            .sign_url(prefix, action, duration)
+            .sign_url_until(prefix, action, duration, BoundParams::default())
             .expect("Invalid signature")
     }
 
     #[test]
     fn test_sign_url() {
-        let signer = HMACSigner::new("test".to_string()).expect("Invalid key");
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
         let time = get_time();
         let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
             .expect("Invalid signer");
         let url = service
-            .sign_url("test".to_string(), AvailableActions::Put, 100)
+            .sign_url(
+                "test".to_string(),
+                AvailableActions::Put,
+                100,
+                BoundParams::default(),
+            )
             .expect("Invalid signature");
-        insta::assert_snapshot!(url);
+        assert_eq!(url.expires_at, 200);
+        insta::assert_snapshot!(url.url);
+    }
+
+    #[test]
+    fn test_sign_url_max_ttl() {
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
+        let time = get_time();
+        let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
+            .expect("Invalid signer")
+            .with_max_ttls(MaxTtls {
+                put: 60,
+                ..Default::default()
+            });
+
+        let url = service.sign_url(
+            "test/test".to_string(),
+            AvailableActions::Put,
+            60,
+            BoundParams::default(),
+        );
+        assert_eq!(url.expect("Invalid signature").expires_at, 160);
+
+        let url = service.sign_url(
+            "test/test".to_string(),
+            AvailableActions::Put,
+            61,
+            BoundParams::default(),
+        );
+        assert!(matches!(
+            url,
+            Err(SignedUrlError::TtlTooLong(AvailableActions::Put, 60))
+        ));
     }
 
     #[test]
     fn test_verify_url() {
-        let signer = HMACSigner::new("test".to_string()).expect("Invalid key");
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
         let time = get_time();
         let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
             .expect("Invalid signer");
@@ -283,7 +543,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_verify_parts() {
-        let signer = HMACSigner::new("test".to_string()).expect("Invalid key");
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
         let time = get_time();
         let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
             .expect("Invalid signer");
@@ -304,9 +564,138 @@ mod tests {
         assert_eq!(params.action, AvailableActions::Put);
     }
 
+    #[test]
+    fn test_verify_url_with_bound_params() {
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
+        let time = get_time();
+        let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
+            .expect("Invalid signer");
+        let params = BoundParams {
+            // Upload ids of some providers hold base64 characters
+            upload_id: Some("up+load/id==".to_string()),
+            staging_id: Some("staging".to_string()),
+            part_number: Some(2),
+            ..Default::default()
+        };
+        let url = service
+            .sign_url_until(
+                "/bucket/test/multipart".to_string(),
+                AvailableActions::UploadPart,
+                200,
+                params.clone(),
+            )
+            .expect("Invalid signature");
+        let claims = service.verify_url(&url).expect("Invalid url");
+        assert_eq!(claims.params, params);
+        assert_eq!(claims.expires, 200);
+
+        let tampered = url.replace("part_number=2", "part_number=3");
+        assert!(matches!(
+            service.verify_url(&tampered),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_verify_url_with_content_constraints() {
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
+        let time = get_time();
+        let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
+            .expect("Invalid signer");
+        let params = BoundParams {
+            content_type: Some("text/plain; charset=utf-8".to_string()),
+            max_content_length: Some(1024),
+            ..Default::default()
+        };
+        let url = service
+            .sign_url_until(
+                "/bucket/test".to_string(),
+                AvailableActions::Put,
+                200,
+                params.clone(),
+            )
+            .expect("Invalid signature");
+        let claims = service.verify_url(&url).expect("Invalid url");
+        assert_eq!(claims.params, params);
+
+        let tampered = url.replace("max_content_length=1024", "max_content_length=4096");
+        assert!(matches!(
+            service.verify_url(&tampered),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+        let stripped = url.replace("&max_content_length=1024", "");
+        assert!(matches!(
+            service.verify_url(&stripped),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_verify_url_after_rotation() {
+        let keyring = |active: (&str, &[u8]), retired: Vec<(&str, &[u8])>| {
+            let keyring = KeyringSigner::new(
+                (active.0.to_string(), active.1.to_vec()),
+                retired
+                    .into_iter()
+                    .map(|(id, key)| (id.to_string(), key.to_vec()))
+                    .collect(),
+            )
+            .expect("Invalid keyring");
+            SignedUrlServiceImpl::new(keyring, get_time(), "https://beep.com".to_string())
+                .expect("Invalid signer")
+        };
+
+        let before = keyring(("v1", b"first"), vec![]);
+        let url = before
+            .sign_url(
+                "/bucket/test".to_string(),
+                AvailableActions::Get,
+                100,
+                BoundParams::default(),
+            )
+            .expect("Invalid signature")
+            .url;
+        assert!(url.contains("&kid=v1&"));
+
+        let during = keyring(("v2", b"second"), vec![("v1", b"first")]);
+        assert!(during.verify_url(&url).is_ok());
+        let url = during
+            .sign_url(
+                "/bucket/test".to_string(),
+                AvailableActions::Get,
+                100,
+                BoundParams::default(),
+            )
+            .expect("Invalid signature")
+            .url;
+        assert!(url.contains("&kid=v2&"));
+        assert!(during.verify_url(&url).is_ok());
+
+        let tampered = url.replace("kid=v2", "kid=v1");
+        assert!(matches!(
+            during.verify_url(&tampered),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+
+        let after = keyring(("v2", b"second"), vec![]);
+        let old_url = before
+            .sign_url(
+                "/bucket/test".to_string(),
+                AvailableActions::Get,
+                100,
+                BoundParams::default(),
+            )
+            .expect("Invalid signature")
+            .url;
+        assert!(matches!(
+            after.verify_url(&old_url),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+    }
+
     #[tokio::test]
     async fn test_verify_parts_invalid_method() {
-        let signer = HMACSigner::new("test".to_string()).expect("Invalid key");
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
         let time = get_time();
         let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
             .expect("Invalid signer");
@@ -323,4 +712,29 @@ mod tests {
         let params = service.verify_parts(parts);
         assert!(params.is_err());
     }
+
+    #[tokio::test]
+    async fn test_verify_parts_head() {
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
+        let time = get_time();
+        let service = SignedUrlServiceImpl::new(signer, time, "https://beep.com".to_string())
+            .expect("Invalid signer");
+
+        for (action, allowed) in [
+            (AvailableActions::Get, true),
+            (AvailableActions::Put, false),
+        ] {
+            let url = sign_url("/bucket/test".to_string(), action, 100);
+            let request = Request::builder()
+                .uri(url)
+                .method(http::Method::HEAD)
+                .body(axum::body::Body::empty())
+                .expect("Invalid request");
+
+            let parts = http::request::Parts::from_request(request, &())
+                .await
+                .expect("Invalid request");
+            assert_eq!(service.verify_parts(parts).is_ok(), allowed);
+        }
+    }
 }
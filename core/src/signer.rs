@@ -1,33 +1,43 @@
-use std::fmt::{Display, Formatter};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
 
 use hmac::{Hmac, Mac};
 use mockall::automock;
 
-use crate::error::ApiError;
+use crate::{config::Config, error::ApiError};
 
 #[automock]
 pub trait Signer: Send + Sync {
     fn sign(&self, data: &[u8]) -> Result<Vec<u8>, SignerError>;
-    #[allow(dead_code)]
-    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, SignerError>;
+    /// Identifier of the key used by `sign`, if the signer has several keys
+    fn key_id(&self) -> Option<String>;
+    // mockall can't elide the lifetime of `key_id`
+    #[allow(dead_code, clippy::needless_lifetimes)]
+    fn verify<'a>(
+        &self,
+        key_id: Option<&'a str>,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<bool, SignerError>;
 }
 
 pub struct HMACSigner {
-    key: String,
+    key: Vec<u8>,
 }
 
 type HmacSha256 = Hmac<sha2::Sha256>;
 
 impl HMACSigner {
-    pub fn new(key: String) -> Result<Self, SignerError> {
+    pub fn new(key: Vec<u8>) -> Result<Self, SignerError> {
         if key.is_empty() {
             return Err(SignerError::InvalidKey("Key cannot be empty".to_string()));
         }
         // I don't want this structure to be mutable but at the same time I want to
         // detect as soon as possible if the key is invalid. So I do it at three different
         // places. At construction time and at each call to sign or verify.
-        HmacSha256::new_from_slice(key.as_bytes())
-            .map_err(|e| SignerError::InvalidKey(e.to_string()))?;
+        HmacSha256::new_from_slice(&key).map_err(|e| SignerError::InvalidKey(e.to_string()))?;
 
         Ok(Self { key })
     }
@@ -35,21 +45,110 @@ impl HMACSigner {
 
 impl Signer for HMACSigner {
     fn sign(&self, data: &[u8]) -> Result<Vec<u8>, SignerError> {
-        let mut mac = HmacSha256::new_from_slice(self.key.as_bytes())
+        let mut mac = HmacSha256::new_from_slice(&self.key)
             .map_err(|e| SignerError::InvalidKey(e.to_string()))?;
 
         mac.update(data);
         Ok(mac.finalize().into_bytes().to_vec())
     }
 
-    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, SignerError> {
-        let mut mac = HmacSha256::new_from_slice(self.key.as_bytes())
+    fn key_id(&self) -> Option<String> {
+        None
+    }
+
+    fn verify(
+        &self,
+        _key_id: Option<&str>,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<bool, SignerError> {
+        let mut mac = HmacSha256::new_from_slice(&self.key)
             .map_err(|e| SignerError::InvalidKey(e.to_string()))?;
         mac.update(data);
         Ok(mac.verify_slice(signature).is_ok())
     }
 }
 
+/// Signs with its active key and verifies with any key of the keyring,
+/// so urls signed before a rotation stay valid while their key is kept.
+pub struct KeyringSigner {
+    active: String,
+    keys: HashMap<String, HMACSigner>,
+}
+
+impl KeyringSigner {
+    pub fn new(
+        active: (String, Vec<u8>),
+        retired: Vec<(String, Vec<u8>)>,
+    ) -> Result<Self, SignerError> {
+        let active_id = active.0.clone();
+        let mut keys = HashMap::new();
+        for (id, key) in std::iter::once(active).chain(retired) {
+            if !is_valid_key_id(&id) {
+                return Err(SignerError::InvalidKey(format!("Invalid key id: {:?}", id)));
+            }
+            if keys.insert(id.clone(), HMACSigner::new(key)?).is_some() {
+                return Err(SignerError::InvalidKey(format!("Duplicate key id: {}", id)));
+            }
+        }
+
+        Ok(Self {
+            active: active_id,
+            keys,
+        })
+    }
+
+    /// Signs with the `SIGNING_KEY` of the config, and also verifies with its
+    /// `VERIFICATION_KEYS`
+    pub fn from_config(config: &Config) -> Result<Self, SignerError> {
+        Self::new(
+            (
+                config.signing_key_id.clone(),
+                config.signing_key.as_bytes().to_vec(),
+            ),
+            config
+                .verification_keys
+                .iter()
+                .map(|k| (k.id.clone(), k.key.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+}
+
+/// Key ids end up in the query string, they are kept url safe
+pub fn is_valid_key_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+impl Signer for KeyringSigner {
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, SignerError> {
+        let Some(signer) = self.keys.get(&self.active) else {
+            return Err(SignerError::InvalidKey("Active key is missing".to_string()));
+        };
+        signer.sign(data)
+    }
+
+    fn key_id(&self) -> Option<String> {
+        Some(self.active.clone())
+    }
+
+    fn verify(
+        &self,
+        key_id: Option<&str>,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<bool, SignerError> {
+        // Unknown or missing key ids belong to keys that were retired
+        let Some(signer) = key_id.and_then(|id| self.keys.get(id)) else {
+            return Ok(false);
+        };
+        signer.verify(key_id, data, signature)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum SignerError {
     InvalidKey(String),
@@ -73,27 +172,117 @@ impl Display for SignerError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{SigningKey, VerificationKey};
 
     #[test]
     fn test_signature_without_alteration() {
-        let signer = HMACSigner::new("test".to_string()).expect("Invalid key");
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
         let data = b"test";
         let signature = signer.sign(data).expect("Invalid signature");
-        assert!(signer.verify(data, &signature).expect("Invalid signature"));
+        assert!(
+            signer
+                .verify(None, data, &signature)
+                .expect("Invalid signature")
+        );
     }
 
     #[test]
     fn test_signature_with_alteration() {
-        let signer = HMACSigner::new("test".to_string()).expect("Invalid key");
+        let signer = HMACSigner::new(b"test".to_vec()).expect("Invalid key");
         let data = b"test";
         let signature = signer.sign(data).expect("Invalid signature");
         let signature = signature[1..].to_vec();
-        assert!(!signer.verify(data, &signature).expect("Invalid signature"));
+        assert!(
+            !signer
+                .verify(None, data, &signature)
+                .expect("Invalid signature")
+        );
     }
 
     #[test]
     fn test_empty_key() {
-        let signer = HMACSigner::new("".to_string());
+        let signer = HMACSigner::new(Vec::new());
         assert!(signer.is_err());
     }
+
+    #[test]
+    fn test_keyring_rotation() {
+        let old = KeyringSigner::new(("v1".to_string(), b"first".to_vec()), vec![])
+            .expect("Invalid keyring");
+        let data = b"test";
+        let signature = old.sign(data).expect("Invalid signature");
+        assert_eq!(old.key_id(), Some("v1".to_string()));
+
+        let rotated = KeyringSigner::new(
+            ("v2".to_string(), b"second".to_vec()),
+            vec![("v1".to_string(), b"first".to_vec())],
+        )
+        .expect("Invalid keyring");
+        assert_eq!(rotated.key_id(), Some("v2".to_string()));
+        assert!(
+            rotated
+                .verify(Some("v1"), data, &signature)
+                .expect("Invalid signature")
+        );
+        assert!(
+            !rotated
+                .verify(Some("v2"), data, &signature)
+                .expect("Invalid signature")
+        );
+        assert!(
+            !rotated
+                .verify(None, data, &signature)
+                .expect("Invalid signature")
+        );
+
+        let retired = KeyringSigner::new(("v2".to_string(), b"second".to_vec()), vec![])
+            .expect("Invalid keyring");
+        assert!(
+            !retired
+                .verify(Some("v1"), data, &signature)
+                .expect("Invalid signature")
+        );
+    }
+
+    #[test]
+    fn test_keyring_from_config() {
+        let config = Config {
+            signing_key_id: "v2".to_string(),
+            signing_key: SigningKey::parse("dGVzdF9zaWduaW5nX2tleV9iZWVw").expect("Invalid key"),
+            verification_keys: vec![
+                VerificationKey::parse("v1:b2xkX3NpZ25pbmdfa2V5X2JlZXA=").expect("Invalid key"),
+            ],
+            ..Default::default()
+        };
+        let keyring = KeyringSigner::from_config(&config).expect("Invalid keyring");
+        assert_eq!(keyring.key_id(), Some("v2".to_string()));
+
+        let old = KeyringSigner::new(
+            (
+                "v1".to_string(),
+                config.verification_keys[0].key.as_bytes().to_vec(),
+            ),
+            vec![],
+        )
+        .expect("Invalid keyring");
+        let signature = old.sign(b"test").expect("Invalid signature");
+        assert!(
+            keyring
+                .verify(Some("v1"), b"test", &signature)
+                .expect("Invalid signature")
+        );
+    }
+
+    #[test]
+    fn test_keyring_invalid_ids() {
+        let duplicate = KeyringSigner::new(
+            ("v1".to_string(), b"first".to_vec()),
+            vec![("v1".to_string(), b"second".to_vec())],
+        );
+        assert!(duplicate.is_err());
+
+        let unsafe_id =
+            KeyringSigner::new(("v1&action=Get".to_string(), b"first".to_vec()), vec![]);
+        assert!(unsafe_id.is_err());
+    }
 }
@@ -0,0 +1,66 @@
+use axum::body::{Body, Bytes, HttpBody};
+use futures_util::{StreamExt, stream};
+
+use crate::error::ApiError;
+
+/// Returns the exact length of a request body.
+/// Bodies are streamed to S3 which needs their length upfront, so requests
+/// without a `Content-Length` are refused.
+pub fn content_length(body: &Body) -> Result<u64, ApiError> {
+    body.size_hint().exact().ok_or(ApiError::LengthRequired(
+        "Content-Length header is required".to_string(),
+    ))
+}
+
+/// Buffers the first `length` bytes of a body without consuming it.
+/// Returns the buffered bytes along with a body yielding the whole content,
+/// so callers can inspect the beginning of an upload and still stream it.
+pub async fn peek(body: Body, length: usize) -> Result<(Bytes, Body), ApiError> {
+    let mut stream = body.into_data_stream();
+    let mut head = Vec::with_capacity(length);
+
+    while head.len() < length {
+        let Some(chunk) = stream.next().await else {
+            break;
+        };
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+        head.extend_from_slice(&chunk);
+    }
+
+    let head = Bytes::from(head);
+    let body = Body::from_stream(stream::once(std::future::ready(Ok(head.clone()))).chain(stream));
+
+    Ok((head, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_peek_keeps_whole_body() {
+        let chunks: Vec<Result<Bytes, std::io::Error>> = vec![
+            Ok(Bytes::from_static(b"hello ")),
+            Ok(Bytes::from_static(b"streaming ")),
+            Ok(Bytes::from_static(b"world")),
+        ];
+        let body = Body::from_stream(stream::iter(chunks));
+
+        let (head, body) = peek(body, 8).await.expect("Peek failed");
+        assert_eq!(head, Bytes::from_static(b"hello streaming "));
+
+        let body = to_bytes(body, usize::MAX).await.expect("Body read failed");
+        assert_eq!(body, Bytes::from_static(b"hello streaming world"));
+    }
+
+    #[tokio::test]
+    async fn test_peek_short_body() {
+        let (head, body) = peek(Body::from("abc"), 8).await.expect("Peek failed");
+        assert_eq!(head, Bytes::from_static(b"abc"));
+
+        let body = to_bytes(body, usize::MAX).await.expect("Body read failed");
+        assert_eq!(body, Bytes::from_static(b"abc"));
+    }
+}
@@ -0,0 +1,275 @@
+use axum::extract::State;
+use http::StatusCode;
+
+#[cfg(test)]
+use crate::app::tests::TestAppState;
+use crate::{
+    app::{AppState, AppStateOperations},
+    error::ApiError,
+    signed_url::extractor::SignedUrl,
+    storage::variants::delete_variants,
+};
+
+#[utoipa::path(
+    delete,
+    path = "/{prefix}/{file_name}",
+    tag = "storage",
+    params(
+        ("prefix" = String, Path, description = "Bucket prefix"),
+        ("file_name" = String, Path, description = "File name"),
+    ),
+    responses(
+        (status = 204, description = "Deletion successful"),
+        (status = 401, description = "Invalid or expired signature", body = String),
+        (status = 404, description = "Object not found", body = String),
+        (status = 500, description = "Internal server error", body = String),
+    ),
+)]
+pub async fn delete_object_handler(
+    State(state): State<AppState>,
+    SignedUrl(claims): SignedUrl,
+) -> Result<StatusCode, ApiError> {
+    let (prefix, file_name) = claims.path;
+    delete_object(prefix, file_name, state).await
+}
+
+#[cfg(test)]
+pub async fn delete_object_test(
+    State(state): State<TestAppState>,
+    SignedUrl(claims): SignedUrl,
+) -> Result<StatusCode, ApiError> {
+    let (prefix, file_name) = claims.path;
+    delete_object(prefix, file_name, state).await
+}
+
+/// Deletes an object from S3, along with the variants cached for it.
+/// The claims of the signed url already guarantee that the request was
+/// made with the `DELETE` method, so the path can be trusted as is.
+async fn delete_object<S>(
+    prefix: String,
+    file_name: String,
+    state: S,
+) -> Result<StatusCode, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let location = state
+        .guards()
+        .location(&state.config().s3_bucket, &prefix, &file_name);
+    let deleted = state.delete_object(&location.bucket, &location.key).await;
+    // Even once the object is gone, so that retrying removes the variants left
+    delete_variants(&state, &location.bucket, &location.key).await?;
+    deleted.map_err(|e| e.into())?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{Router, routing::delete};
+    use axum_test::TestServer;
+
+    use crate::{
+        app::{MockAppStateOperations, tests::TestAppState},
+        config::Config,
+        guards::{FileType, Guard, GuardsBuilder},
+        s3::{ListedObject, ObjectList, S3Error},
+        signed_url::{extractor::Claims, service::AvailableActions},
+    };
+
+    use super::*;
+
+    pub fn fake_router(app_state: TestAppState) -> Router {
+        Router::new()
+            .route("/{prefix}/{file_name}", delete(delete_object_test))
+            .with_state(app_state)
+    }
+
+    fn mock_guards(operations: &mut MockAppStateOperations) {
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add("message_attachment", Guard::new(vec![FileType::Any]))
+                    .build(),
+            )
+        });
+    }
+
+    #[tokio::test]
+    async fn test_delete_object() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        mock_guards(&mut operations);
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/index.html")
+            .returning(|_, _| Ok(()));
+        operations
+            .expect_list_objects()
+            .withf(|_, prefix, _| prefix == "message_attachment/index.html/variants/")
+            .returning(|_, _, _| Ok(ObjectList::default()));
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "index.html".to_string()),
+                action: AvailableActions::Delete,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .delete("/message_attachment/index.html")
+            .await;
+        insta::assert_debug_snapshot!(response);
+    }
+
+    #[tokio::test]
+    async fn test_delete_object_mapped_prefix() {
+        let mut operations = MockAppStateOperations::new();
+        operations.expect_config().returning(|| {
+            Arc::new(Config {
+                s3_bucket: "beep".to_string(),
+                ..Default::default()
+            })
+        });
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::Any])
+                            .with_bucket("beep-attachments")
+                            .with_key_prefix("uploads"),
+                    )
+                    .build(),
+            )
+        });
+        operations
+            .expect_delete_object()
+            .withf(|bucket, key| {
+                bucket == "beep-attachments" && key == "uploads/message_attachment/index.html"
+            })
+            .returning(|_, _| Ok(()));
+        operations
+            .expect_list_objects()
+            .withf(|bucket, _, _| bucket == "beep-attachments")
+            .returning(|_, _, _| Ok(ObjectList::default()));
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "index.html".to_string()),
+                action: AvailableActions::Delete,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .delete("/message_attachment/index.html")
+            .await;
+        response.assert_status(StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_delete_object_not_found() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        mock_guards(&mut operations);
+        operations
+            .expect_delete_object()
+            .returning(|_, key| Err(S3Error::ObjectNotFound(key.to_string())));
+        operations
+            .expect_list_objects()
+            .returning(|_, _, _| Ok(ObjectList::default()));
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "index.html".to_string()),
+                action: AvailableActions::Delete,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .delete("/message_attachment/index.html")
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_object_variants() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        mock_guards(&mut operations);
+        operations
+            .expect_list_objects()
+            .withf(|_, _, options| options.continuation_token.is_none())
+            .times(1)
+            .returning(|_, prefix, _| {
+                Ok(ObjectList {
+                    objects: vec![ListedObject {
+                        key: format!("{}abc-w32.png", prefix),
+                        size: 3,
+                        last_modified: None,
+                    }],
+                    next_continuation_token: Some("next".to_string()),
+                })
+            });
+        operations
+            .expect_list_objects()
+            .withf(|_, _, options| options.continuation_token.as_deref() == Some("next"))
+            .times(1)
+            .returning(|_, prefix, _| {
+                Ok(ObjectList {
+                    objects: vec![ListedObject {
+                        key: format!("{}def-w32.png", prefix),
+                        size: 3,
+                        last_modified: None,
+                    }],
+                    next_continuation_token: None,
+                })
+            });
+        for key in [
+            "message_attachment/me.png",
+            "message_attachment/me.png/variants/abc-w32.png",
+            "message_attachment/me.png/variants/def-w32.png",
+        ] {
+            operations
+                .expect_delete_object()
+                .withf(move |_, deleted| deleted == key)
+                .times(1)
+                .returning(|_, _| Ok(()));
+        }
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "me.png".to_string()),
+                action: AvailableActions::Delete,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .delete("/message_attachment/me.png")
+            .await;
+        response.assert_status(StatusCode::NO_CONTENT);
+    }
+}
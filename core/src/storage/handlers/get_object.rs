@@ -1,5 +1,5 @@
 use axum::{body::Body, extract::State};
-use http::Response;
+use http::{HeaderMap, Response};
 
 #[cfg(test)]
 use crate::app::tests::TestAppState;
@@ -7,6 +7,7 @@ use crate::{
     app::{AppState, AppStateOperations},
     error::ApiError,
     signed_url::extractor::SignedUrl,
+    storage::response::{object_response, requested_range},
 };
 
 #[utoipa::path(
@@ -19,42 +20,50 @@ use crate::{
     ),
     responses(
         (status = 200, description = "Upload successful", body = String),
+        (status = 206, description = "Requested range of the object", body = String),
+        (status = 304, description = "Cached copy of the client is still fresh"),
         (status = 400, description = "Invalid request", body = String),
+        (status = 404, description = "Object not found", body = String),
+        (status = 416, description = "Requested range not satisfiable", body = String),
         (status = 500, description = "Internal server error", body = String),
     ),
 )]
 pub async fn get_object_handler(
     State(state): State<AppState>,
     SignedUrl(claims): SignedUrl,
+    headers: HeaderMap,
 ) -> Result<Response<Body>, ApiError> {
     let (prefix, file_name) = claims.path;
-    get_object(format!("{}/{}", prefix, file_name), state).await
+    get_object(prefix, file_name, headers, state).await
 }
 
-async fn get_object<S>(path: String, state: S) -> Result<Response<Body>, ApiError>
+async fn get_object<S>(
+    prefix: String,
+    file_name: String,
+    headers: HeaderMap,
+    state: S,
+) -> Result<Response<Body>, ApiError>
 where
     S: AppStateOperations + Send + Sync + 'static,
 {
-    let bucket = state.config().s3_bucket.clone();
-    let (object, mime_type) = state
-        .get_object(&bucket, &path)
+    let guards = state.guards();
+    let location = guards.location(&state.config().s3_bucket, &prefix, &file_name);
+    let object = state
+        .get_object(&location.bucket, &location.key, requested_range(&headers))
         .await
         .map_err(|e| e.into())?;
-    let body = Body::from(object);
-    Response::builder()
-        .status(200)
-        .header("Content-Type", mime_type)
-        .body(body)
-        .map_err(|e| ApiError::InternalServerError(e.to_string()))
+    let cache_control = guards.guard(&prefix).ok().and_then(|g| g.cache_control());
+    object_response(object, &headers, cache_control)
 }
 
 #[cfg(test)]
 pub async fn get_object_test(
     SignedUrl(claims): SignedUrl,
     State(state): State<TestAppState>,
+    headers: HeaderMap,
 ) -> Result<Response<Body>, ApiError> {
     let (prefix, file_name) = claims.path;
-    get_object(format!("{}/{}", prefix, file_name), state).await
+    get_object(prefix, file_name, headers, state).await
 }
 
 #[cfg(test)]
@@ -63,10 +72,18 @@ mod tests {
 
     use axum::{Router, routing::get};
     use axum_test::TestServer;
+    use chrono::DateTime;
+    use http::{
+        StatusCode,
+        header::{CACHE_CONTROL, CONTENT_RANGE, ETAG, IF_NONE_MATCH},
+    };
 
     use crate::{
         app::{MockAppStateOperations, tests::TestAppState},
         config::Config,
+        guards::{FileType, Guard, Guards, GuardsBuilder},
+        range::ByteRange,
+        s3::{ObjectStream, S3Error},
         signed_url::{extractor::Claims, service::AvailableActions},
     };
 
@@ -78,19 +95,38 @@ mod tests {
             .with_state(app_state)
     }
 
+    fn fake_guards() -> Arc<Guards> {
+        Arc::new(
+            GuardsBuilder::new()
+                .add(
+                    "message_attachment",
+                    Guard::new(vec![FileType::Any]).with_cache_control("private, max-age=3600"),
+                )
+                .build(),
+        )
+    }
+
     #[tokio::test]
     async fn test_get_object() {
         let mut operations = MockAppStateOperations::new();
         operations
             .expect_config()
             .returning(|| Arc::new(Config::default()));
-        operations
-            .expect_get_object()
-            .returning(|_, _| Ok((vec![1, 2, 3], "text/plain".to_string())));
+        operations.expect_guards().returning(fake_guards);
+        operations.expect_get_object().returning(|_, _, _| {
+            Ok(ObjectStream {
+                data: Body::from(vec![1, 2, 3]),
+                content_type: "text/plain".to_string(),
+                content_length: 3,
+                content_range: None,
+                ..Default::default()
+            })
+        });
         operations.expect_verify_parts().returning(|_| {
             Ok(Claims {
                 path: ("test-bucket".to_string(), "index.html".to_string()),
                 action: AvailableActions::Put,
+                ..Default::default()
             })
         });
 
@@ -103,4 +139,118 @@ mod tests {
             .await;
         insta::assert_debug_snapshot!(response);
     }
+
+    #[tokio::test]
+    async fn test_get_object_range() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(fake_guards);
+        operations
+            .expect_get_object()
+            .withf(|_, _, range| *range == Some(ByteRange::Bounded(1, 2)))
+            .returning(|_, _, _| {
+                Ok(ObjectStream {
+                    data: Body::from(vec![2, 3]),
+                    content_type: "video/mp4".to_string(),
+                    content_length: 2,
+                    content_range: Some("bytes 1-2/3".to_string()),
+                    ..Default::default()
+                })
+            });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "video.mp4".to_string()),
+                action: AvailableActions::Get,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .get("/message_attachment/video.mp4")
+            .add_header(http::header::RANGE, "bytes=1-2")
+            .await;
+        insta::assert_debug_snapshot!(response);
+    }
+
+    #[tokio::test]
+    async fn test_get_object_range_not_satisfiable() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(fake_guards);
+        operations.expect_get_object().returning(|_, _, _| {
+            Err(S3Error::InvalidRange(
+                "bytes=5- of an object of 3 bytes".to_string(),
+                3,
+            ))
+        });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "video.mp4".to_string()),
+                action: AvailableActions::Get,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .get("/message_attachment/video.mp4")
+            .add_header(http::header::RANGE, "bytes=5-")
+            .await;
+        response.assert_status(StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.header(CONTENT_RANGE), "bytes */3");
+    }
+
+    #[tokio::test]
+    async fn test_get_object_not_modified() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(fake_guards);
+        operations.expect_get_object().returning(|_, _, _| {
+            Ok(ObjectStream {
+                data: Body::from(vec![1, 2, 3]),
+                content_type: "text/plain".to_string(),
+                content_length: 3,
+                e_tag: Some("\"abc\"".to_string()),
+                last_modified: DateTime::from_timestamp(784111777, 0),
+                ..Default::default()
+            })
+        });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "notes.txt".to_string()),
+                action: AvailableActions::Get,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let server =
+            TestServer::new(fake_router(app_state)).expect("Axum test server creation failed");
+
+        let response = server.get("/message_attachment/notes.txt").await;
+        response.assert_status_ok();
+        response.assert_header(ETAG, "\"abc\"");
+        response.assert_header(CACHE_CONTROL, "private, max-age=3600");
+
+        let response = server
+            .get("/message_attachment/notes.txt")
+            .add_header(IF_NONE_MATCH, "\"abc\"")
+            .await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+        response.assert_header(CACHE_CONTROL, "private, max-age=3600");
+        assert!(response.as_bytes().is_empty());
+    }
 }
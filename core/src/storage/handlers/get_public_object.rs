@@ -1,15 +1,20 @@
 use axum::{
     body::Body,
-    extract::{Path, State},
+    extract::{Path, Query, State},
 };
-use http::Response;
+use http::{HeaderMap, Response};
 
 #[cfg(test)]
 use crate::app::tests::TestAppState;
 use crate::{
     app::{AppState, AppStateOperations},
     error::ApiError,
-    prefixes::Prefix,
+    guards::{Guard, Guards},
+    images::variants::VariantParams,
+    storage::{
+        response::{object_response, requested_range},
+        variants::variant_object,
+    },
 };
 
 #[utoipa::path(
@@ -19,61 +24,103 @@ use crate::{
     params(
         ("prefix" = String, Path, description = "Bucket prefix"),
         ("file_name" = String, Path, description = "File name"),
+        ("w" = Option<u32>, Query, description = "Width of the variant, a power of two"),
+        ("h" = Option<u32>, Query, description = "Height of the variant, a power of two"),
+        ("fit" = Option<String>, Query, description = "cover, contain or fill"),
+        ("format" = Option<String>, Query, description = "png, jpeg, gif or webp"),
     ),
     responses(
         (status = 200, description = "Upload successful", body = String),
+        (status = 206, description = "Requested range of the object", body = String),
+        (status = 304, description = "Cached copy of the client is still fresh"),
         (status = 400, description = "Invalid request", body = String),
+        (status = 404, description = "Object not found or prefix not public", body = String),
+        (status = 416, description = "Requested range not satisfiable", body = String),
         (status = 500, description = "Internal server error", body = String),
     ),
 )]
 pub async fn get_public_object_handler(
     State(state): State<AppState>,
     Path((prefix, file_name)): Path<(String, String)>,
+    Query(variant): Query<VariantParams>,
+    headers: HeaderMap,
 ) -> Result<Response<Body>, ApiError> {
-    if prefix != Prefix::ProfilePicture.as_str() {
-        return Err(ApiError::NotFound(format!(
-            "The prefix is not public : {}",
-            prefix
-        )));
-    }
-    get_public_object(format!("{}/{}", prefix, file_name), state).await
+    get_public_object(prefix, file_name, variant, headers, state).await
+}
+
+/// Guard of a prefix declared `public` in the policy, the other prefixes
+/// are reported as not found rather than forbidden.
+pub fn public_guard<'a>(guards: &'a Guards, prefix: &str) -> Result<&'a Guard, ApiError> {
+    guards
+        .guard(prefix)
+        .ok()
+        .filter(|guard| guard.is_public())
+        .ok_or_else(|| ApiError::NotFound(format!("The prefix is not public : {}", prefix)))
 }
 
-pub async fn get_public_object<S>(path: String, state: S) -> Result<Response<Body>, ApiError>
+/// Serves the objects of the prefixes declared `public` in the policy,
+/// or a resized variant of them when one is requested for an image.
+pub async fn get_public_object<S>(
+    prefix: String,
+    file_name: String,
+    variant: VariantParams,
+    headers: HeaderMap,
+    state: S,
+) -> Result<Response<Body>, ApiError>
 where
     S: AppStateOperations + Send + Sync + 'static,
 {
-    let bucket = state.config().s3_bucket.clone();
-    let (object, mime_type) = state
-        .get_object(&bucket, &path)
-        .await
-        .map_err(|e| e.into())?;
-    let body = Body::from(object);
-    Response::builder()
-        .status(200)
-        .header("Content-Type", mime_type)
-        .body(body)
-        .map_err(|e| ApiError::InternalServerError(e.to_string()))
+    let guards = state.guards();
+    let guard = public_guard(&guards, &prefix)?;
+
+    let location = guards.location(&state.config().s3_bucket, &prefix, &file_name);
+    let range = requested_range(&headers);
+    let object = if variant.is_empty() {
+        state
+            .get_object(&location.bucket, &location.key, range)
+            .await
+            .map_err(|e| e.into())?
+    } else {
+        variant_object(
+            &state,
+            guard,
+            &location.bucket,
+            &location.key,
+            variant,
+            range,
+        )
+        .await?
+    };
+    object_response(object, &headers, guard.cache_control())
 }
 
 #[cfg(test)]
 pub async fn get_public_object_test(
     Path((prefix, file_name)): Path<(String, String)>,
+    Query(variant): Query<VariantParams>,
     State(state): State<TestAppState>,
+    headers: HeaderMap,
 ) -> Result<Response<Body>, ApiError> {
-    get_public_object(format!("{}/{}", prefix, file_name), state).await
+    get_public_object(prefix, file_name, variant, headers, state).await
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
 
     use axum::{Router, routing::get};
     use axum_test::TestServer;
+    use http::{StatusCode, header::CONTENT_TYPE};
 
     use crate::{
         app::{MockAppStateOperations, tests::TestAppState},
         config::Config,
+        guards::{FileType, Guard, Guards, GuardsBuilder},
+        images::{limits::ImageLimits, variants::tests::fake_png},
+        s3::{ObjectMetadata, ObjectStream, S3Error},
         signed_url::{extractor::Claims, service::AvailableActions},
     };
 
@@ -85,19 +132,39 @@ mod tests {
             .with_state(app_state)
     }
 
+    fn fake_guards() -> Arc<Guards> {
+        Arc::new(
+            GuardsBuilder::new()
+                .add(
+                    "server_banner",
+                    Guard::new(vec![FileType::ImagePNG]).with_public(true),
+                )
+                .add("message_attachment", Guard::new(vec![FileType::Any]))
+                .build(),
+        )
+    }
+
     #[tokio::test]
     async fn test_get_public_object() {
         let mut operations = MockAppStateOperations::new();
         operations
             .expect_config()
             .returning(|| Arc::new(Config::default()));
-        operations
-            .expect_get_object()
-            .returning(|_, _| Ok((vec![1, 2, 3], "text/plain".to_string())));
+        operations.expect_guards().returning(fake_guards);
+        operations.expect_get_object().returning(|_, _, _| {
+            Ok(ObjectStream {
+                data: Body::from(vec![1, 2, 3]),
+                content_type: "text/plain".to_string(),
+                content_length: 3,
+                content_range: None,
+                ..Default::default()
+            })
+        });
         operations.expect_verify_parts().returning(|_| {
             Ok(Claims {
                 path: ("test-bucket".to_string(), "index.html".to_string()),
                 action: AvailableActions::Put,
+                ..Default::default()
             })
         });
 
@@ -106,8 +173,186 @@ mod tests {
 
         let response = TestServer::new(router)
             .expect("Axum test server creation failed")
-            .get("/public/test-bucket/index.html")
+            .get("/public/server_banner/index.html")
             .await;
         insta::assert_debug_snapshot!(response);
     }
+
+    #[tokio::test]
+    async fn test_get_private_object() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(fake_guards);
+        operations.expect_get_object().never();
+
+        let app_state = TestAppState::new(operations);
+        let server =
+            TestServer::new(fake_router(app_state)).expect("Axum test server creation failed");
+
+        for path in [
+            "/public/message_attachment/index.html",
+            "/public/emoji/index.html",
+        ] {
+            server.get(path).await.assert_status(StatusCode::NOT_FOUND);
+        }
+    }
+
+    fn fake_variant_operations() -> MockAppStateOperations {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(fake_guards);
+        operations.expect_head_object().returning(|_, _| {
+            Ok(ObjectMetadata {
+                content_type: "image/png".to_string(),
+                e_tag: Some("\"abc\"".to_string()),
+                ..Default::default()
+            })
+        });
+        operations
+    }
+
+    #[tokio::test]
+    async fn test_get_cached_variant() {
+        let mut operations = fake_variant_operations();
+        operations
+            .expect_get_object()
+            .withf(|_, key, _| key == "server_banner/banner.png/variants/abc-w64-cover.webp")
+            .times(1)
+            .returning(|_, _, _| {
+                Ok(ObjectStream {
+                    data: Body::from(vec![1, 2, 3]),
+                    content_type: "image/webp".to_string(),
+                    content_length: 3,
+                    ..Default::default()
+                })
+            });
+        operations.expect_upload().never();
+
+        let app_state = TestAppState::new(operations);
+        let response = TestServer::new(fake_router(app_state))
+            .expect("Axum test server creation failed")
+            .get("/public/server_banner/banner.png?w=64&fit=cover&format=webp")
+            .await;
+        response.assert_status_ok();
+        response.assert_header(CONTENT_TYPE, "image/webp");
+    }
+
+    #[tokio::test]
+    async fn test_get_rendered_variant() {
+        let uploaded = Arc::new(AtomicBool::new(false));
+        let mut operations = fake_variant_operations();
+        let cached = uploaded.clone();
+        operations
+            .expect_get_object()
+            .returning(move |_, key, _| match key {
+                "server_banner/banner.png" => Ok(ObjectStream {
+                    data: Body::from(fake_png(400, 100)),
+                    content_type: "image/png".to_string(),
+                    e_tag: Some("\"abc\"".to_string()),
+                    ..Default::default()
+                }),
+                "server_banner/banner.png/variants/abc-w64-h64-contain.png"
+                    if cached.load(Ordering::SeqCst) =>
+                {
+                    Ok(ObjectStream {
+                        data: Body::from(vec![1, 2, 3]),
+                        content_type: "image/png".to_string(),
+                        content_length: 3,
+                        ..Default::default()
+                    })
+                }
+                _ => Err(S3Error::ObjectNotFound(key.to_string())),
+            });
+        let upload = uploaded.clone();
+        operations
+            .expect_upload()
+            .withf(|_, key, file| {
+                key == "server_banner/banner.png/variants/abc-w64-h64-contain.png"
+                    && file.content_type == "image/png"
+            })
+            .times(1)
+            .returning(move |_, _, _| {
+                upload.store(true, Ordering::SeqCst);
+                Ok("url".to_string())
+            });
+
+        let app_state = TestAppState::new(operations);
+        let response = TestServer::new(fake_router(app_state))
+            .expect("Axum test server creation failed")
+            .get("/public/server_banner/banner.png?w=64&h=64")
+            .await;
+        response.assert_status_ok();
+        assert!(uploaded.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_get_invalid_variant() {
+        let mut operations = fake_variant_operations();
+        operations.expect_get_object().never();
+
+        let app_state = TestAppState::new(operations);
+        let server =
+            TestServer::new(fake_router(app_state)).expect("Axum test server creation failed");
+
+        for query in ["w=100", "fit=cover", "format=tiff"] {
+            server
+                .get(&format!("/public/server_banner/banner.png?{}", query))
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_variant_over_image_limits() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "server_banner",
+                        Guard::new(vec![FileType::ImagePNG])
+                            .with_public(true)
+                            .with_image_limits(ImageLimits {
+                                max_width: Some(200),
+                                ..Default::default()
+                            }),
+                    )
+                    .build(),
+            )
+        });
+        operations.expect_head_object().returning(|_, _| {
+            Ok(ObjectMetadata {
+                content_type: "image/png".to_string(),
+                e_tag: Some("\"abc\"".to_string()),
+                ..Default::default()
+            })
+        });
+        operations
+            .expect_get_object()
+            .returning(|_, key, _| match key {
+                "server_banner/banner.png" => Ok(ObjectStream {
+                    data: Body::from(fake_png(400, 100)),
+                    content_type: "image/png".to_string(),
+                    e_tag: Some("\"abc\"".to_string()),
+                    ..Default::default()
+                }),
+                _ => Err(S3Error::ObjectNotFound(key.to_string())),
+            });
+        // Never decoded, nor cached
+        operations.expect_upload().never();
+
+        let app_state = TestAppState::new(operations);
+        let response = TestServer::new(fake_router(app_state))
+            .expect("Axum test server creation failed")
+            .get("/public/server_banner/banner.png?w=64&h=64")
+            .await;
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    }
 }
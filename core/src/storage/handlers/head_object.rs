@@ -0,0 +1,243 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+};
+use http::{HeaderMap, Response};
+
+#[cfg(test)]
+use crate::app::tests::TestAppState;
+use crate::{
+    app::{AppState, AppStateOperations},
+    error::ApiError,
+    signed_url::extractor::SignedUrl,
+    storage::{handlers::get_public_object::public_guard, response::metadata_response},
+};
+
+#[utoipa::path(
+    head,
+    path = "/{prefix}/{file_name}",
+    tag = "storage",
+    params(
+        ("prefix" = String, Path, description = "Bucket prefix"),
+        ("file_name" = String, Path, description = "File name"),
+    ),
+    responses(
+        (status = 200, description = "Metadata of the object, without its body"),
+        (status = 304, description = "Cached copy of the client is still fresh"),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 404, description = "Object not found", body = String),
+        (status = 500, description = "Internal server error", body = String),
+    ),
+)]
+pub async fn head_object_handler(
+    State(state): State<AppState>,
+    SignedUrl(claims): SignedUrl,
+    headers: HeaderMap,
+) -> Result<Response<Body>, ApiError> {
+    let (prefix, file_name) = claims.path;
+    head_object(prefix, file_name, headers, state).await
+}
+
+/// Answers a `HEAD` on a signed url, which is the url signed for a `GET`
+async fn head_object<S>(
+    prefix: String,
+    file_name: String,
+    headers: HeaderMap,
+    state: S,
+) -> Result<Response<Body>, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let guards = state.guards();
+    let location = guards.location(&state.config().s3_bucket, &prefix, &file_name);
+    let metadata = state
+        .head_object(&location.bucket, &location.key)
+        .await
+        .map_err(|e| e.into())?;
+    let cache_control = guards.guard(&prefix).ok().and_then(|g| g.cache_control());
+    metadata_response(metadata, &headers, cache_control)
+}
+
+pub async fn head_public_object_handler(
+    State(state): State<AppState>,
+    Path((prefix, file_name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, ApiError> {
+    head_public_object(prefix, file_name, headers, state).await
+}
+
+async fn head_public_object<S>(
+    prefix: String,
+    file_name: String,
+    headers: HeaderMap,
+    state: S,
+) -> Result<Response<Body>, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let guards = state.guards();
+    let guard = public_guard(&guards, &prefix)?;
+
+    let location = guards.location(&state.config().s3_bucket, &prefix, &file_name);
+    let metadata = state
+        .head_object(&location.bucket, &location.key)
+        .await
+        .map_err(|e| e.into())?;
+    metadata_response(metadata, &headers, guard.cache_control())
+}
+
+#[cfg(test)]
+pub async fn head_object_test(
+    SignedUrl(claims): SignedUrl,
+    State(state): State<TestAppState>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, ApiError> {
+    let (prefix, file_name) = claims.path;
+    head_object(prefix, file_name, headers, state).await
+}
+
+#[cfg(test)]
+pub async fn head_public_object_test(
+    Path((prefix, file_name)): Path<(String, String)>,
+    State(state): State<TestAppState>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, ApiError> {
+    head_public_object(prefix, file_name, headers, state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{Router, routing::head};
+    use axum_test::TestServer;
+    use chrono::DateTime;
+    use http::{
+        StatusCode,
+        header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH, LAST_MODIFIED},
+    };
+
+    use crate::{
+        app::{MockAppStateOperations, tests::TestAppState},
+        config::Config,
+        guards::{FileType, Guard, Guards, GuardsBuilder},
+        s3::{ObjectMetadata, S3Error},
+        signed_url::{extractor::Claims, service::AvailableActions},
+    };
+
+    use super::*;
+
+    pub fn fake_router(app_state: TestAppState) -> Router {
+        Router::new()
+            .route("/{prefix}/{file_name}", head(head_object_test))
+            .route(
+                "/public/{prefix}/{file_name}",
+                head(head_public_object_test),
+            )
+            .with_state(app_state)
+    }
+
+    fn fake_guards() -> Arc<Guards> {
+        Arc::new(
+            GuardsBuilder::new()
+                .add(
+                    "server_banner",
+                    Guard::new(vec![FileType::ImagePNG]).with_public(true),
+                )
+                .add("message_attachment", Guard::new(vec![FileType::Any]))
+                .build(),
+        )
+    }
+
+    fn fake_operations() -> MockAppStateOperations {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(fake_guards);
+        operations
+            .expect_head_object()
+            .returning(|_, key| match key {
+                "message_attachment/report.pdf" | "server_banner/banner.png" => {
+                    Ok(ObjectMetadata {
+                        content_type: "application/pdf".to_string(),
+                        content_length: 1234,
+                        e_tag: Some("\"abc\"".to_string()),
+                        last_modified: DateTime::from_timestamp(784111777, 0),
+                    })
+                }
+                _ => Err(S3Error::ObjectNotFound(key.to_string())),
+            });
+        operations
+    }
+
+    #[tokio::test]
+    async fn test_head_object() {
+        let mut operations = fake_operations();
+        operations.expect_get_object().never();
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "report.pdf".to_string()),
+                action: AvailableActions::Get,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let server =
+            TestServer::new(fake_router(app_state)).expect("Axum test server creation failed");
+
+        let response = server
+            .method(http::Method::HEAD, "/message_attachment/report.pdf")
+            .await;
+        response.assert_status_ok();
+        response.assert_header(CONTENT_TYPE, "application/pdf");
+        response.assert_header(CONTENT_LENGTH, "1234");
+        response.assert_header(ETAG, "\"abc\"");
+        response.assert_header(LAST_MODIFIED, "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(response.as_bytes().is_empty());
+
+        let response = server
+            .method(http::Method::HEAD, "/message_attachment/report.pdf")
+            .add_header(IF_NONE_MATCH, "\"abc\"")
+            .await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn test_head_missing_object() {
+        let mut operations = fake_operations();
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "missing.pdf".to_string()),
+                action: AvailableActions::Get,
+                ..Default::default()
+            })
+        });
+
+        let app_state = TestAppState::new(operations);
+        let response = TestServer::new(fake_router(app_state))
+            .expect("Axum test server creation failed")
+            .method(http::Method::HEAD, "/message_attachment/missing.pdf")
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_head_public_object() {
+        let app_state = TestAppState::new(fake_operations());
+        let server =
+            TestServer::new(fake_router(app_state)).expect("Axum test server creation failed");
+
+        let response = server
+            .method(http::Method::HEAD, "/public/server_banner/banner.png")
+            .await;
+        response.assert_status_ok();
+        response.assert_header(CONTENT_LENGTH, "1234");
+
+        let response = server
+            .method(http::Method::HEAD, "/public/message_attachment/report.pdf")
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,367 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use chrono::SecondsFormat;
+use futures_util::{StreamExt, TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[cfg(test)]
+use crate::app::tests::TestAppState;
+use crate::{
+    app::{AppState, AppStateOperations},
+    auth::{Caller, extractor::AuthenticatedCaller},
+    error::ApiError,
+    s3::{ListOptions, ListedObject, S3Error},
+    signed_url::service::AvailableActions,
+};
+
+/// Objects listed when the request doesn't set `max_keys`
+const DEFAULT_MAX_KEYS: u16 = 100;
+
+/// Largest page listed, the content type of every object costing a request
+const MAX_LISTED_KEYS: u16 = 100;
+
+/// Objects whose content type is fetched at once
+const CONCURRENT_HEADS: usize = 16;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListObjectsQuery {
+    /// Start of the file names to list
+    pub prefix: Option<String>,
+    pub continuation_token: Option<String>,
+    pub max_keys: Option<u16>,
+    /// File name the listing starts after
+    pub start_after: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ListedFile {
+    /// Key of the object in its bucket
+    pub key: String,
+    pub file_name: String,
+    /// Length in bytes of the object
+    pub size: u64,
+    pub content_type: String,
+    /// RFC 3339 date of the last upload of the object
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ListObjectsResponse {
+    /// Objects of the page, in key order
+    pub objects: Vec<ListedFile>,
+    /// Token of the next page, absent on the last one. Pages can hold fewer
+    /// objects than asked, or none, before the last one
+    pub next_continuation_token: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/list/{prefix}",
+    tag = "storage",
+    params(
+        ("prefix" = String, Path, description = "Bucket prefix"),
+        ("prefix" = Option<String>, Query, description = "Start of the file names to list"),
+        ("continuation_token" = Option<String>, Query, description = "Token of the page, as returned with the previous one"),
+        ("max_keys" = Option<u16>, Query, description = "Largest number of objects of the page, between 1 and 100, 100 by default"),
+        ("start_after" = Option<String>, Query, description = "File name the listing starts after"),
+    ),
+    responses(
+        (status = 200, description = "Page of the objects of the prefix", body = ListObjectsResponse),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Missing or invalid bearer token", body = String),
+        (status = 403, description = "Listing the prefix is not allowed for the caller", body = String),
+        (status = 404, description = "Unknown prefix", body = String),
+        (status = 500, description = "Internal server error", body = String),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list_objects_handler(
+    Path(prefix): Path<String>,
+    State(state): State<AppState>,
+    AuthenticatedCaller(caller): AuthenticatedCaller,
+    Query(query): Query<ListObjectsQuery>,
+) -> Result<Json<ListObjectsResponse>, ApiError> {
+    Ok(Json(list_objects(prefix, query, caller, state).await?))
+}
+
+#[cfg(test)]
+pub async fn list_objects_test(
+    Path(prefix): Path<String>,
+    State(state): State<TestAppState>,
+    AuthenticatedCaller(caller): AuthenticatedCaller,
+    Query(query): Query<ListObjectsQuery>,
+) -> Result<Json<ListObjectsResponse>, ApiError> {
+    Ok(Json(list_objects(prefix, query, caller, state).await?))
+}
+
+/// Lists a page of the objects of `prefix` for the moderation tooling.
+/// Listings give access to every file name of a prefix, so they are only
+/// allowed to the callers granted the `List` action on it.
+/// The cached variants and the multipart uploads being assembled are left out
+/// of the page, but still count against `max_keys` with S3, so a page can be
+/// short or empty while the continuation token tells more objects are left.
+async fn list_objects<S>(
+    prefix: String,
+    query: ListObjectsQuery,
+    caller: Caller,
+    state: S,
+) -> Result<ListObjectsResponse, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    if !caller.allows(&prefix, AvailableActions::List) {
+        return Err(ApiError::Forbidden(format!(
+            "{} is not allowed to list {}",
+            caller.name, prefix
+        )));
+    }
+    let max_keys = query.max_keys.unwrap_or(DEFAULT_MAX_KEYS);
+    if !(1..=MAX_LISTED_KEYS).contains(&max_keys) {
+        return Err(ApiError::BadRequest(format!(
+            "max_keys must be between 1 and {}",
+            MAX_LISTED_KEYS
+        )));
+    }
+    let guards = state.guards();
+    guards
+        .guard(&prefix)
+        .map_err(|_| ApiError::NotFound(format!("Unknown prefix : {}", prefix)))?;
+
+    let default_bucket = state.config().s3_bucket.clone();
+    let location = guards.location(&default_bucket, &prefix, "");
+    let options = ListOptions {
+        continuation_token: query.continuation_token,
+        max_keys: Some(max_keys),
+        start_after: query
+            .start_after
+            .map(|file_name| format!("{}{}", location.key, file_name)),
+    };
+    let listing = state
+        .list_objects(
+            &location.bucket,
+            &format!("{}{}", location.key, query.prefix.unwrap_or_default()),
+            options,
+        )
+        .await
+        .map_err(|e| e.into())?;
+
+    // Listings don't hold the content types, which are fetched for every object
+    let state = &state;
+    let location = &location;
+    let objects: Vec<Option<ListedFile>> = stream::iter(listing.objects)
+        .map(|object: ListedObject| async move {
+            let content_type = match state.head_object(&location.bucket, &object.key).await {
+                Ok(metadata) => metadata.content_type,
+                // Deleted since it was listed
+                Err(S3Error::ObjectNotFound(_)) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            Ok(Some(ListedFile {
+                file_name: object.key[location.key.len()..].to_string(),
+                size: object.size,
+                content_type,
+                last_modified: object
+                    .last_modified
+                    .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                key: object.key,
+            }))
+        })
+        .buffered(CONCURRENT_HEADS)
+        .try_collect()
+        .await
+        .map_err(|e: S3Error| e.into())?;
+
+    Ok(ListObjectsResponse {
+        objects: objects.into_iter().flatten().collect(),
+        next_continuation_token: listing.next_continuation_token,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{Router, routing::get};
+    use axum_test::TestServer;
+    use chrono::DateTime;
+    use http::StatusCode;
+
+    use crate::{
+        app::MockAppStateOperations,
+        auth::{
+            Callers,
+            tests::{TEST_TOKEN, config_with_caller},
+        },
+        config::Config,
+        guards::{FileType, Guard, GuardsBuilder},
+        s3::{ListedObject, ObjectList, ObjectMetadata},
+    };
+
+    use super::*;
+
+    pub fn fake_router(app_state: TestAppState) -> Router {
+        Router::new()
+            .route("/list/{prefix}", get(list_objects_test))
+            .with_state(app_state)
+    }
+
+    /// A config letting the `TEST_TOKEN` bearer list `message_attachment`
+    fn config_with_moderator() -> Arc<Config> {
+        Arc::new(Config {
+            s3_bucket: "beep".to_string(),
+            callers: Callers::new(vec![Caller::new(
+                "moderation".to_string(),
+                TEST_TOKEN.to_string(),
+                vec!["message_attachment".to_string()],
+                vec![AvailableActions::List],
+            )])
+            .expect("Invalid callers"),
+            ..Default::default()
+        })
+    }
+
+    fn mock_guards(operations: &mut MockAppStateOperations) {
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::Any])
+                            .with_bucket("beep-attachments")
+                            .with_key_prefix("uploads"),
+                    )
+                    .build(),
+            )
+        });
+    }
+
+    fn listed(key: &str) -> ListedObject {
+        ListedObject {
+            key: key.to_string(),
+            size: 5,
+            last_modified: DateTime::from_timestamp(784111777, 0),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_objects() {
+        let mut operations = MockAppStateOperations::new();
+        operations.expect_config().returning(config_with_moderator);
+        mock_guards(&mut operations);
+        operations
+            .expect_list_objects()
+            .withf(|bucket, prefix, options| {
+                bucket == "beep-attachments"
+                    && prefix == "uploads/message_attachment/notes"
+                    && options.max_keys == Some(2)
+                    && options.start_after.as_deref()
+                        == Some("uploads/message_attachment/notes-0.txt")
+            })
+            .returning(|_, _, _| {
+                Ok(ObjectList {
+                    objects: vec![
+                        listed("uploads/message_attachment/notes-1.txt"),
+                        listed("uploads/message_attachment/notes-2.txt"),
+                    ],
+                    next_continuation_token: Some("next".to_string()),
+                })
+            });
+        operations
+            .expect_head_object()
+            .withf(|bucket, _| bucket == "beep-attachments")
+            .returning(|_, key| {
+                if key.ends_with("notes-2.txt") {
+                    return Err(S3Error::ObjectNotFound(key.to_string()));
+                }
+                Ok(ObjectMetadata {
+                    content_type: "text/plain".to_string(),
+                    content_length: 5,
+                    e_tag: None,
+                    last_modified: None,
+                })
+            });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .get("/list/message_attachment")
+            .add_query_param("prefix", "notes")
+            .add_query_param("max_keys", 2)
+            .add_query_param("start_after", "notes-0.txt")
+            .authorization_bearer(TEST_TOKEN)
+            .await;
+        insta::assert_debug_snapshot!(response);
+    }
+
+    #[tokio::test]
+    async fn test_list_objects_empty_page() {
+        let mut operations = MockAppStateOperations::new();
+        operations.expect_config().returning(config_with_moderator);
+        mock_guards(&mut operations);
+        // Only variants and uploads being assembled on this page
+        operations
+            .expect_list_objects()
+            .withf(|_, _, options| options.max_keys == Some(DEFAULT_MAX_KEYS))
+            .returning(|_, _, _| {
+                Ok(ObjectList {
+                    objects: vec![],
+                    next_continuation_token: Some("next".to_string()),
+                })
+            });
+        operations.expect_head_object().never();
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .get("/list/message_attachment")
+            .authorization_bearer(TEST_TOKEN)
+            .await;
+        let listing = response.json::<ListObjectsResponse>();
+        assert!(listing.objects.is_empty());
+        assert_eq!(listing.next_continuation_token.as_deref(), Some("next"));
+    }
+
+    #[tokio::test]
+    async fn test_list_objects_forbidden() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| config_with_caller("message_attachment"));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .get("/list/message_attachment")
+            .authorization_bearer(TEST_TOKEN)
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_list_objects_invalid_max_keys() {
+        let mut operations = MockAppStateOperations::new();
+        operations.expect_config().returning(config_with_moderator);
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let server = TestServer::new(router).expect("Axum test server creation failed");
+        for max_keys in [0, 101] {
+            server
+                .get("/list/message_attachment")
+                .add_query_param("max_keys", max_keys)
+                .authorization_bearer(TEST_TOKEN)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+    }
+}
@@ -1,4 +1,8 @@
+pub mod delete_object;
 pub mod get_object;
 pub mod get_public_object;
+pub mod head_object;
+pub mod list_objects;
+pub mod multipart;
 pub mod post_object;
 pub mod put_object;
@@ -0,0 +1,1423 @@
+use axum::{
+    Json,
+    body::{Body, Bytes, to_bytes},
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::warn;
+use utoipa::ToSchema;
+
+#[cfg(test)]
+use crate::app::tests::TestAppState;
+use crate::{
+    app::{AppState, AppStateOperations},
+    error::ApiError,
+    guards::{GuardError, Location, SNIFF_LENGTH, active_content, media_type},
+    images::{
+        metadata::{MetadataFormat, strip_metadata},
+        variants::VariantFormat,
+    },
+    range::{ByteRange, complete_length},
+    s3::{FileObject, ObjectMetadata, S3Error, UploadedPart, unique_id},
+    signed_url::{
+        extractor::{Claims, SignedUrl},
+        service::{AvailableActions, BoundParams},
+    },
+    storage::{
+        body::{content_length, peek},
+        handlers::put_object::{UploadResponse, upload_image_error},
+        variants::{Derivative, MAX_SOURCE_LENGTH, render_derivatives, store_derivatives},
+    },
+};
+
+/// S3 refuses multipart uploads made of more parts than this
+const MAX_PARTS: u16 = 10_000;
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct CreateMultipartRequest {
+    pub content_type: String,
+    /// Number of parts the client is going to upload
+    pub parts: u16,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct PartUrl {
+    pub part_number: u16,
+    pub url: String,
+}
+
+/// Every url expires along with the url used to start the upload
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct CreateMultipartResponse {
+    pub upload_id: String,
+    pub parts: Vec<PartUrl>,
+    pub complete_url: String,
+    pub abort_url: String,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct CompleteMultipartRequest {
+    pub parts: Vec<UploadedPart>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/{prefix}/{file_name}/multipart",
+    tag = "storage",
+    request_body(
+        content(
+            (CreateMultipartRequest = "application/json"),
+            (CompleteMultipartRequest = "application/json"),
+        ),
+        description = "`CreateMultipartRequest` for a `CreateMultipartUpload` url, \
+            `CompleteMultipartRequest` for a `CompleteMultipartUpload` one",
+    ),
+    params(
+        ("prefix" = String, Path, description = "Bucket prefix"),
+        ("file_name" = String, Path, description = "File name"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "`CreateMultipartResponse` once the upload is started, \
+                `UploadResponse` once it is completed",
+            content(
+                (CreateMultipartResponse = "application/json"),
+                (UploadResponse = "application/json"),
+            ),
+        ),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Invalid or expired signature", body = String),
+        (status = 403, description = "Content type not allowed by the signed url", body = String),
+        (status = 404, description = "Unknown prefix or upload", body = String),
+        (status = 413, description = "Upload longer than the signed url allows, or image too large to be checked", body = String),
+        (status = 422, description = "Image exceeds the image limits of the prefix", body = String),
+        (status = 500, description = "Internal server error", body = String),
+    ),
+)]
+pub async fn post_multipart_handler(
+    State(state): State<AppState>,
+    Path((prefix, file_name)): Path<(String, String)>,
+    SignedUrl(claims): SignedUrl,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    post_multipart(prefix, file_name, claims, body, state).await
+}
+
+#[cfg(test)]
+pub async fn post_multipart_test(
+    State(state): State<TestAppState>,
+    Path((prefix, file_name)): Path<(String, String)>,
+    SignedUrl(claims): SignedUrl,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    post_multipart(prefix, file_name, claims, body, state).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/{prefix}/{file_name}/multipart",
+    tag = "storage",
+    params(
+        ("prefix" = String, Path, description = "Bucket prefix"),
+        ("file_name" = String, Path, description = "File name"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Part uploaded", body = UploadedPart),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Invalid or expired signature", body = String),
+        (status = 404, description = "Unknown upload", body = String),
+        (status = 411, description = "Missing Content-Length", body = String),
+        (status = 413, description = "Part longer than the signed url allows", body = String),
+        (status = 500, description = "Internal server error", body = String),
+    ),
+)]
+pub async fn put_part_handler(
+    State(state): State<AppState>,
+    Path((prefix, file_name)): Path<(String, String)>,
+    SignedUrl(claims): SignedUrl,
+    body: Body,
+) -> Result<Json<UploadedPart>, ApiError> {
+    Ok(Json(
+        put_part(prefix, file_name, claims, body, state).await?,
+    ))
+}
+
+#[cfg(test)]
+pub async fn put_part_test(
+    State(state): State<TestAppState>,
+    Path((prefix, file_name)): Path<(String, String)>,
+    SignedUrl(claims): SignedUrl,
+    body: Body,
+) -> Result<Json<UploadedPart>, ApiError> {
+    Ok(Json(
+        put_part(prefix, file_name, claims, body, state).await?,
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{prefix}/{file_name}/multipart",
+    tag = "storage",
+    params(
+        ("prefix" = String, Path, description = "Bucket prefix"),
+        ("file_name" = String, Path, description = "File name"),
+    ),
+    responses(
+        (status = 204, description = "Upload aborted"),
+        (status = 401, description = "Invalid or expired signature", body = String),
+        (status = 404, description = "Unknown upload", body = String),
+        (status = 500, description = "Internal server error", body = String),
+    ),
+)]
+pub async fn delete_multipart_handler(
+    State(state): State<AppState>,
+    Path((prefix, file_name)): Path<(String, String)>,
+    SignedUrl(claims): SignedUrl,
+) -> Result<StatusCode, ApiError> {
+    abort_multipart(prefix, file_name, claims, state).await
+}
+
+#[cfg(test)]
+pub async fn delete_multipart_test(
+    State(state): State<TestAppState>,
+    Path((prefix, file_name)): Path<(String, String)>,
+    SignedUrl(claims): SignedUrl,
+) -> Result<StatusCode, ApiError> {
+    abort_multipart(prefix, file_name, claims, state).await
+}
+
+/// Starting and completing an upload are both `POST` requests on the same
+/// resource, the signed action tells them apart.
+async fn post_multipart<S>(
+    prefix: String,
+    file_name: String,
+    claims: Claims,
+    body: Bytes,
+    state: S,
+) -> Result<Response, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    match claims.action {
+        AvailableActions::CreateMultipartUpload => {
+            let request = parse_json(&body)?;
+            let response = create_multipart(prefix, file_name, claims, request, state).await?;
+            Ok(Json(response).into_response())
+        }
+        AvailableActions::CompleteMultipartUpload => {
+            let request = parse_json(&body)?;
+            let response = complete_multipart(prefix, file_name, claims, request, state).await?;
+            Ok(Json(response).into_response())
+        }
+        action => Err(wrong_action(action)),
+    }
+}
+
+/// Starts a multipart upload and signs every url needed to carry it out.
+async fn create_multipart<S>(
+    prefix: String,
+    file_name: String,
+    claims: Claims,
+    request: CreateMultipartRequest,
+    state: S,
+) -> Result<CreateMultipartResponse, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    if request.parts == 0 || request.parts > MAX_PARTS {
+        return Err(ApiError::BadRequest(format!(
+            "The number of parts must be between 1 and {}",
+            MAX_PARTS
+        )));
+    }
+
+    if let Some(allowed) = &claims.params.content_type
+        && media_type(allowed) != media_type(&request.content_type)
+    {
+        return Err(ApiError::Forbidden(format!(
+            "Content type {} is not allowed, expected {}",
+            request.content_type, allowed
+        )));
+    }
+
+    // Fail early on prefixes nothing could ever be uploaded to
+    let guards = state.guards();
+    guards.guard(&prefix).map_err(|e| e.into())?;
+
+    let Location { bucket, key } = guards.location(&state.config().s3_bucket, &prefix, &file_name);
+    let staging_id = unique_id(&key);
+    // The content can't be sniffed before it is uploaded, only the declared type
+    let content_type = active_content::neutralize(&[], request.content_type);
+    let upload_id = state
+        .create_multipart_upload(&bucket, &staging_key(&key, &staging_id), &content_type)
+        .await
+        .map_err(|e| e.into())?;
+
+    let path = format!("{}/{}/multipart", prefix, file_name);
+    let sign = |action: AvailableActions, part_number: Option<u16>| -> Result<String, ApiError> {
+        let params = BoundParams {
+            upload_id: Some(upload_id.clone()),
+            staging_id: Some(staging_id.clone()),
+            part_number,
+            max_content_length: claims.params.max_content_length,
+            ..Default::default()
+        };
+        state
+            .sign_url_until(path.clone(), action, claims.expires, params)
+            .map_err(|e| e.into())
+    };
+
+    let parts = (1..=request.parts)
+        .map(|part_number| {
+            Ok(PartUrl {
+                part_number,
+                url: sign(AvailableActions::UploadPart, Some(part_number))?,
+            })
+        })
+        .collect::<Result<Vec<PartUrl>, ApiError>>()?;
+    let complete_url = sign(AvailableActions::CompleteMultipartUpload, None)?;
+    let abort_url = sign(AvailableActions::AbortMultipartUpload, None)?;
+
+    Ok(CreateMultipartResponse {
+        upload_id,
+        parts,
+        complete_url,
+        abort_url,
+    })
+}
+
+async fn put_part<S>(
+    prefix: String,
+    file_name: String,
+    claims: Claims,
+    body: Body,
+    state: S,
+) -> Result<UploadedPart, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    if claims.action != AvailableActions::UploadPart {
+        return Err(wrong_action(claims.action));
+    }
+    let (Some(upload_id), Some(staging_id), Some(part_number)) = (
+        claims.params.upload_id,
+        claims.params.staging_id,
+        claims.params.part_number,
+    ) else {
+        return Err(ApiError::BadRequest(
+            "Missing upload id or part number".to_string(),
+        ));
+    };
+
+    let content_length = content_length(&body)?;
+    if let Some(max_content_length) = claims.params.max_content_length
+        && content_length > max_content_length
+    {
+        return Err(too_long(max_content_length));
+    }
+    let Location { bucket, key } =
+        state
+            .guards()
+            .location(&state.config().s3_bucket, &prefix, &file_name);
+
+    let e_tag = state
+        .upload_part(
+            &bucket,
+            &staging_key(&key, &staging_id),
+            &upload_id,
+            part_number,
+            body,
+            content_length,
+        )
+        .await
+        .map_err(|e| e.into())?;
+
+    Ok(UploadedPart { part_number, e_tag })
+}
+
+/// Assembles the parts under the staging key of the upload, then runs the
+/// prefix guard on the resulting object since no single part could be trusted
+/// to carry the file signature. Only objects passing it, no longer than the
+/// maximum length bound to the signed url, and whose image can be processed as
+/// single uploads are, are stored under their key. The staging object is
+/// deleted either way, so a refused upload never replaces the object at its key.
+async fn complete_multipart<S>(
+    prefix: String,
+    file_name: String,
+    claims: Claims,
+    request: CompleteMultipartRequest,
+    state: S,
+) -> Result<UploadResponse, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let (Some(upload_id), Some(staging_id)) = (claims.params.upload_id, claims.params.staging_id)
+    else {
+        return Err(ApiError::BadRequest("Missing upload id".to_string()));
+    };
+
+    let location = state
+        .guards()
+        .location(&state.config().s3_bucket, &prefix, &file_name);
+    let staging = staging_key(&location.key, &staging_id);
+
+    state
+        .complete_multipart_upload(&location.bucket, &staging, &upload_id, request.parts)
+        .await
+        .map_err(|e| e.into())?;
+
+    let path = format!("{}/{}", prefix, file_name);
+    let stored = store_object(
+        &state,
+        &location,
+        &prefix,
+        &path,
+        &staging,
+        claims.params.max_content_length,
+    )
+    .await;
+    if let Err(e) = state.delete_object(&location.bucket, &staging).await {
+        warn!("Failed to delete staging object {}: {}", staging, e);
+    }
+
+    Ok(UploadResponse {
+        key: path,
+        derivatives: stored?,
+    })
+}
+
+/// Checks the object assembled at `staging`, then stores it at `location`
+async fn store_object<S>(
+    state: &S,
+    location: &Location,
+    prefix: &str,
+    path: &str,
+    staging: &str,
+    max_content_length: Option<u64>,
+) -> Result<Vec<Derivative>, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let (bucket, key) = (location.bucket.as_str(), location.key.as_str());
+    let sniffed_range = ByteRange::Bounded(0, SNIFF_LENGTH as u64 - 1);
+    let (head, metadata) = match state.get_object(bucket, staging, Some(sniffed_range)).await {
+        Ok(object) => {
+            let metadata = ObjectMetadata {
+                content_length: object
+                    .content_range
+                    .as_deref()
+                    .and_then(complete_length)
+                    .unwrap_or(object.content_length),
+                content_type: object.content_type,
+                e_tag: object.e_tag,
+                last_modified: object.last_modified,
+            };
+            (peek(object.data, SNIFF_LENGTH).await?.0, metadata)
+        }
+        // Empty objects have no satisfiable range
+        Err(S3Error::InvalidRange(..)) => (
+            Bytes::new(),
+            ObjectMetadata {
+                content_type: "application/octet-stream".to_string(),
+                ..Default::default()
+            },
+        ),
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(max_content_length) = max_content_length
+        && metadata.content_length > max_content_length
+    {
+        return Err(too_long(max_content_length));
+    }
+    let image = check_object(state, bucket, prefix, staging, key, &head, &metadata).await?;
+    process_object(state, location, prefix, path, staging, image, &metadata).await
+}
+
+/// Runs the prefix guard on an assembled object. The image limits need the
+/// whole image, which is only fetched for the prefixes enforcing them, and
+/// returned to spare fetching it again.
+async fn check_object<S>(
+    state: &S,
+    bucket: &str,
+    prefix: &str,
+    staging: &str,
+    key: &str,
+    head: &[u8],
+    metadata: &ObjectMetadata,
+) -> Result<Option<Bytes>, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let (content_length, content_type) = (metadata.content_length, metadata.content_type.as_str());
+    let guards = state.guards();
+    let checked_content_type = guards
+        .check(prefix, key, head, content_length, content_type)
+        .map_err(|e| e.into())?;
+    // The object can't be stored again with the content type of active content
+    if checked_content_type != content_type {
+        return Err(GuardError::ActiveContent.into());
+    }
+    let guard = guards.guard(prefix).map_err(|e| e.into())?;
+    let media_type = media_type(content_type);
+    if !guard.limits_images(&media_type) {
+        return Ok(None);
+    }
+
+    if content_length > MAX_SOURCE_LENGTH as u64 {
+        return Err(image_too_large());
+    }
+    let data = fetch_object(state, bucket, staging).await?;
+    guard
+        .check_image(&data, &media_type)
+        .map_err(|e| e.into())?;
+    Ok(Some(data))
+}
+
+/// Stores an assembled object at `location`. Images have their metadata stripped
+/// and their derivatives rendered beforehand, as `put_object` does, the
+/// stripped image being uploaded in place of the assembled one. `image` is the
+/// image when the guard already fetched it. Images too large to be buffered
+/// are refused if they must be stripped, and get no derivatives otherwise.
+async fn process_object<S>(
+    state: &S,
+    location: &Location,
+    prefix: &str,
+    path: &str,
+    staging: &str,
+    image: Option<Bytes>,
+    metadata: &ObjectMetadata,
+) -> Result<Vec<Derivative>, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let (bucket, key) = (location.bucket.as_str(), location.key.as_str());
+    let (content_length, content_type) = (metadata.content_length, metadata.content_type.as_str());
+    let guards = state.guards();
+    let guard = guards.guard(prefix).map_err(|e| e.into())?;
+    let media_type = media_type(content_type);
+    let strip_format =
+        MetadataFormat::from_content_type(&media_type).filter(|_| guard.strips_metadata());
+    let source_format = VariantFormat::from_source(&media_type)
+        .filter(|_| !guard.derivatives().is_empty())
+        .filter(|_| content_length <= MAX_SOURCE_LENGTH as u64);
+    if strip_format.is_some() && content_length > MAX_SOURCE_LENGTH as u64 {
+        return Err(image_too_large());
+    }
+    if strip_format.is_none() && source_format.is_none() {
+        state
+            .copy_object(bucket, staging, key)
+            .await
+            .map_err(|e| e.into())?;
+        return Ok(Vec::new());
+    }
+
+    let mut image = match image {
+        Some(image) => image,
+        None => fetch_object(state, bucket, staging).await?,
+    };
+    if let Some(strip_format) = strip_format {
+        let source = image.clone();
+        image = tokio::task::spawn_blocking(move || strip_metadata(&source, strip_format))
+            .await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+            .map(Bytes::from)
+            .map_err(upload_image_error)?;
+    }
+    let rendered = match source_format {
+        Some(source_format) => {
+            render_derivatives(image.clone(), source_format, guard.derivatives().to_vec())
+                .await
+                .map_err(upload_image_error)?
+        }
+        None => Vec::new(),
+    };
+
+    let e_tag = match strip_format {
+        Some(_) => {
+            let file = FileObject {
+                content_length: image.len() as u64,
+                data: Body::from(image),
+                content_type: content_type.to_string(),
+            };
+            state.upload(bucket, key, file).await
+        }
+        None => state.copy_object(bucket, staging, key).await,
+    }
+    .map_err(|e| e.into())?;
+    match source_format {
+        Some(source_format) if !rendered.is_empty() => {
+            store_derivatives(state, location, path, &e_tag, source_format, rendered).await
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Fetches a whole assembled object, up to `MAX_SOURCE_LENGTH` bytes
+async fn fetch_object<S>(state: &S, bucket: &str, key: &str) -> Result<Bytes, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let object = state
+        .get_object(bucket, key, None)
+        .await
+        .map_err(|e| e.into())?;
+    to_bytes(object.data, MAX_SOURCE_LENGTH)
+        .await
+        .map_err(|_| image_too_large())
+}
+
+fn image_too_large() -> ApiError {
+    ApiError::PayloadTooLarge("Image too large to be checked".to_string())
+}
+
+async fn abort_multipart<S>(
+    prefix: String,
+    file_name: String,
+    claims: Claims,
+    state: S,
+) -> Result<StatusCode, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    if claims.action != AvailableActions::AbortMultipartUpload {
+        return Err(wrong_action(claims.action));
+    }
+    let (Some(upload_id), Some(staging_id)) = (claims.params.upload_id, claims.params.staging_id)
+    else {
+        return Err(ApiError::BadRequest("Missing upload id".to_string()));
+    };
+
+    let Location { bucket, key } =
+        state
+            .guards()
+            .location(&state.config().s3_bucket, &prefix, &file_name);
+
+    state
+        .abort_multipart_upload(&bucket, &staging_key(&key, &staging_id), &upload_id)
+        .await
+        .map_err(|e| e.into())?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Key the parts of an upload are assembled under, next to the object. Its id
+/// is only handed out in the upload urls, and it is deleted once completed.
+fn staging_key(key: &str, staging_id: &str) -> String {
+    format!("{}.multipart/{}", key, staging_id)
+}
+
+fn parse_json<T: DeserializeOwned>(body: &Bytes) -> Result<T, ApiError> {
+    serde_json::from_slice(body).map_err(|e| ApiError::BadRequest(e.to_string()))
+}
+
+fn too_long(max_content_length: u64) -> ApiError {
+    ApiError::PayloadTooLarge(format!(
+        "File too large, the maximum size is {} bytes",
+        max_content_length
+    ))
+}
+
+fn wrong_action(action: AvailableActions) -> ApiError {
+    ApiError::Forbidden(format!("Action not allowed on this resource: {}", action))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        Router,
+        routing::{delete, post, put},
+    };
+    use axum_test::TestServer;
+
+    use crate::{
+        app::MockAppStateOperations,
+        config::Config,
+        guards::{FileType, Guard, GuardsBuilder},
+        images::{
+            limits::ImageLimits,
+            metadata::tests::fake_jpeg,
+            variants::{Fit, VariantParams, tests::fake_png},
+        },
+        s3::ObjectStream,
+    };
+
+    use super::*;
+
+    pub fn fake_router(app_state: TestAppState) -> Router {
+        Router::new()
+            .route("/{prefix}/{file_name}/multipart", post(post_multipart_test))
+            .route("/{prefix}/{file_name}/multipart", put(put_part_test))
+            .route(
+                "/{prefix}/{file_name}/multipart",
+                delete(delete_multipart_test),
+            )
+            .with_state(app_state)
+    }
+
+    fn claims(action: AvailableActions, params: BoundParams) -> Claims {
+        Claims {
+            path: (
+                "message_attachment".to_string(),
+                "video.mp4/multipart".to_string(),
+            ),
+            action,
+            expires: 200,
+            params,
+        }
+    }
+
+    fn mock_guards(operations: &mut MockAppStateOperations) {
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add("message_attachment", Guard::new(vec![FileType::VideoMP4]))
+                    .build(),
+            )
+        });
+    }
+
+    #[tokio::test]
+    async fn test_create_multipart() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        mock_guards(&mut operations);
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CreateMultipartUpload,
+                BoundParams::default(),
+            ))
+        });
+        operations
+            .expect_create_multipart_upload()
+            .withf(|_, key, content_type| {
+                key.starts_with("message_attachment/video.mp4.multipart/")
+                    && content_type == "video/mp4"
+            })
+            .returning(|_, _, _| Ok("upload".to_string()));
+        operations
+            .expect_sign_url_until()
+            .withf(|_, _, expires, params| *expires == 200 && params.staging_id.is_some())
+            .returning(|prefix, action, _, params| {
+                Ok(format!(
+                    "https://beep.com/{}?action={}&upload_id={}&part_number={:?}",
+                    prefix,
+                    action,
+                    params.upload_id.unwrap_or_default(),
+                    params.part_number
+                ))
+            });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CreateMultipartRequest {
+                content_type: "video/mp4".to_string(),
+                parts: 2,
+            })
+            .await;
+        insta::assert_debug_snapshot!(response);
+    }
+
+    #[tokio::test]
+    async fn test_create_multipart_bound_content_type() {
+        let mut operations = MockAppStateOperations::new();
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CreateMultipartUpload,
+                BoundParams {
+                    content_type: Some("video/mp4".to_string()),
+                    ..Default::default()
+                },
+            ))
+        });
+        operations.expect_create_multipart_upload().never();
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CreateMultipartRequest {
+                content_type: "text/html".to_string(),
+                parts: 2,
+            })
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_create_multipart_too_many_parts() {
+        let mut operations = MockAppStateOperations::new();
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CreateMultipartUpload,
+                BoundParams::default(),
+            ))
+        });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CreateMultipartRequest {
+                content_type: "video/mp4".to_string(),
+                parts: MAX_PARTS + 1,
+            })
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_upload_part() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        mock_guards(&mut operations);
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::UploadPart,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    part_number: Some(2),
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_upload_part()
+            .withf(|_, key, upload_id, part_number, _, content_length| {
+                key == "message_attachment/video.mp4.multipart/staging"
+                    && upload_id == "upload"
+                    && *part_number == 2
+                    && *content_length == 4
+            })
+            .returning(|_, _, _, _, _, _| Ok("\"etag\"".to_string()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .put("/message_attachment/video.mp4/multipart")
+            .bytes(vec![1, 2, 3, 4].into())
+            .await;
+        insta::assert_debug_snapshot!(response);
+    }
+
+    #[tokio::test]
+    async fn test_upload_part_with_put_claims() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_verify_parts()
+            .returning(|_| Ok(claims(AvailableActions::Put, BoundParams::default())));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .put("/message_attachment/video.mp4/multipart")
+            .bytes(vec![1, 2, 3, 4].into())
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_complete_multipart() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        mock_guards(&mut operations);
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CompleteMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    part_number: None,
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_complete_multipart_upload()
+            .withf(|_, key, upload_id, parts| {
+                key == "message_attachment/video.mp4.multipart/staging"
+                    && upload_id == "upload"
+                    && parts.len() == 2
+            })
+            .returning(|_, _, _, _| Ok("\"abc-2\"".to_string()));
+        operations
+            .expect_get_object()
+            .withf(|_, key, _| key == "message_attachment/video.mp4.multipart/staging")
+            .returning(|_, _, _| {
+                // ftyp box of an mp4 file
+                let head = [
+                    0, 0, 0, 0x18, b'f', b't', b'y', b'p', b'm', b'p', b'4', b'2',
+                ];
+                Ok(ObjectStream {
+                    data: Body::from(head.to_vec()),
+                    content_type: "video/mp4".to_string(),
+                    content_length: head.len() as u64,
+                    content_range: Some("bytes 0-11/12".to_string()),
+                    ..Default::default()
+                })
+            });
+        operations
+            .expect_copy_object()
+            .withf(|_, source_key, key| {
+                source_key == "message_attachment/video.mp4.multipart/staging"
+                    && key == "message_attachment/video.mp4"
+            })
+            .times(1)
+            .returning(|_, _, _| Ok("\"abc\"".to_string()));
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/video.mp4.multipart/staging")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CompleteMultipartRequest {
+                parts: vec![
+                    UploadedPart {
+                        part_number: 1,
+                        e_tag: "\"first\"".to_string(),
+                    },
+                    UploadedPart {
+                        part_number: 2,
+                        e_tag: "\"second\"".to_string(),
+                    },
+                ],
+            })
+            .await;
+        insta::assert_debug_snapshot!(response);
+    }
+
+    #[tokio::test]
+    async fn test_complete_multipart_refused_by_guard() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        mock_guards(&mut operations);
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CompleteMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    part_number: None,
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_complete_multipart_upload()
+            .returning(|_, _, _, _| Ok("url".to_string()));
+        operations.expect_get_object().returning(|_, _, _| {
+            Ok(ObjectStream {
+                data: Body::from("<html></html>"),
+                content_type: "video/mp4".to_string(),
+                content_length: 13,
+                content_range: Some("bytes 0-12/13".to_string()),
+                ..Default::default()
+            })
+        });
+        // The object at the key of the upload is left untouched
+        operations.expect_copy_object().never();
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/video.mp4.multipart/staging")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CompleteMultipartRequest { parts: vec![] })
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_complete_multipart_too_long() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        mock_guards(&mut operations);
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CompleteMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    max_content_length: Some(1024),
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_complete_multipart_upload()
+            .returning(|_, _, _, _| Ok("url".to_string()));
+        operations.expect_get_object().returning(|_, _, _| {
+            let head = [
+                0, 0, 0, 0x18, b'f', b't', b'y', b'p', b'm', b'p', b'4', b'2',
+            ];
+            Ok(ObjectStream {
+                data: Body::from(head.to_vec()),
+                content_type: "video/mp4".to_string(),
+                content_length: head.len() as u64,
+                content_range: Some("bytes 0-11/2048".to_string()),
+                ..Default::default()
+            })
+        });
+        // The object at the key of the upload is left untouched
+        operations.expect_copy_object().never();
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/video.mp4.multipart/staging")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CompleteMultipartRequest { parts: vec![] })
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_complete_multipart_active_content() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add("message_attachment", Guard::new(vec![FileType::Any]))
+                    .build(),
+            )
+        });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CompleteMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_complete_multipart_upload()
+            .returning(|_, _, _, _| Ok("url".to_string()));
+        operations.expect_get_object().returning(|_, _, _| {
+            Ok(ObjectStream {
+                data: Body::from("<html><script></script></html>"),
+                content_type: "text/plain".to_string(),
+                content_length: 30,
+                content_range: Some("bytes 0-29/30".to_string()),
+                ..Default::default()
+            })
+        });
+        // The object at the key of the upload is left untouched
+        operations.expect_copy_object().never();
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/video.mp4.multipart/staging")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CompleteMultipartRequest { parts: vec![] })
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_complete_multipart_image_too_large() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::ImagePNG]).with_image_limits(ImageLimits {
+                            max_width: Some(32),
+                            ..Default::default()
+                        }),
+                    )
+                    .build(),
+            )
+        });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CompleteMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_complete_multipart_upload()
+            .returning(|_, _, _, _| Ok("url".to_string()));
+        // Fetched once for the guard, then whole for the image limits
+        operations
+            .expect_get_object()
+            .times(2)
+            .returning(|_, _, _| {
+                let png = fake_png(64, 64);
+                Ok(ObjectStream {
+                    content_type: "image/png".to_string(),
+                    content_length: png.len() as u64,
+                    data: Body::from(png),
+                    ..Default::default()
+                })
+            });
+        // The object at the key of the upload is left untouched
+        operations.expect_copy_object().never();
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/video.mp4.multipart/staging")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CompleteMultipartRequest { parts: vec![] })
+            .await;
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_complete_multipart_with_derivatives() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::ImagePNG]).with_derivatives(vec![
+                            VariantParams {
+                                w: Some(32),
+                                h: Some(32),
+                                fit: Some(Fit::Cover),
+                                ..Default::default()
+                            },
+                        ]),
+                    )
+                    .build(),
+            )
+        });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CompleteMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_complete_multipart_upload()
+            .returning(|_, _, _, _| Ok("url".to_string()));
+        // Fetched once for the guard, then whole for the derivatives
+        operations
+            .expect_get_object()
+            .times(2)
+            .returning(|_, _, _| {
+                let png = fake_png(64, 64);
+                Ok(ObjectStream {
+                    content_type: "image/png".to_string(),
+                    content_length: png.len() as u64,
+                    data: Body::from(png),
+                    e_tag: Some("\"abc-1\"".to_string()),
+                    ..Default::default()
+                })
+            });
+        // The derivatives are named after the ETag of the copy
+        operations
+            .expect_copy_object()
+            .times(1)
+            .returning(|_, _, _| Ok("\"abc\"".to_string()));
+        operations.expect_head_object().never();
+        operations
+            .expect_upload()
+            .withf(|_, key, _| key == "message_attachment/video.mp4/variants/abc-w32-h32-cover.png")
+            .times(1)
+            .returning(|_, _, _| Ok("\"def\"".to_string()));
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/video.mp4.multipart/staging")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CompleteMultipartRequest { parts: vec![] })
+            .await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.json::<UploadResponse>().derivatives,
+            vec![Derivative {
+                key: "message_attachment/video.mp4/variants/abc-w32-h32-cover.png".to_string(),
+                query: "w=32&h=32&fit=cover".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_complete_multipart_strips_metadata() {
+        let jpeg = fake_jpeg(8, 8, Some(1));
+        assert!(jpeg.windows(4).any(|window| window == b"Exif"));
+
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::Any]).with_strip_metadata(true),
+                    )
+                    .build(),
+            )
+        });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CompleteMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_complete_multipart_upload()
+            .returning(|_, _, _, _| Ok("url".to_string()));
+        // Fetched once for the guard, then whole to be stripped
+        operations
+            .expect_get_object()
+            .times(2)
+            .returning(move |_, _, _| {
+                Ok(ObjectStream {
+                    content_type: "image/jpeg".to_string(),
+                    content_length: jpeg.len() as u64,
+                    data: Body::from(jpeg.clone()),
+                    ..Default::default()
+                })
+            });
+        let stored = Arc::new(Mutex::new(None));
+        let uploaded = stored.clone();
+        operations
+            .expect_upload()
+            .withf(|_, key, file| {
+                key == "message_attachment/video.mp4" && file.content_type == "image/jpeg"
+            })
+            .times(1)
+            .returning(move |_, _, file| {
+                *uploaded.lock().expect("Poisoned upload") = Some(file.data);
+                Ok("\"abc\"".to_string())
+            });
+        // The stripped image is uploaded in place of a copy of the assembled one
+        operations.expect_copy_object().never();
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/video.mp4.multipart/staging")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CompleteMultipartRequest { parts: vec![] })
+            .await;
+        response.assert_status_ok();
+
+        let stored = stored
+            .lock()
+            .expect("Poisoned upload")
+            .take()
+            .expect("Nothing stored");
+        let stored = to_bytes(stored, usize::MAX).await.expect("Invalid body");
+        assert!(!stored.windows(4).any(|window| window == b"Exif"));
+        image::load_from_memory(&stored).expect("Invalid JPEG");
+    }
+
+    #[tokio::test]
+    async fn test_complete_multipart_strip_too_large() {
+        let jpeg = fake_jpeg(8, 8, None);
+
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::Any]).with_strip_metadata(true),
+                    )
+                    .build(),
+            )
+        });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::CompleteMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_complete_multipart_upload()
+            .returning(|_, _, _, _| Ok("url".to_string()));
+        // Only the sniffed range is fetched, the image is too large to be stripped
+        operations
+            .expect_get_object()
+            .times(1)
+            .returning(move |_, _, _| {
+                Ok(ObjectStream {
+                    content_type: "image/jpeg".to_string(),
+                    content_length: jpeg.len() as u64,
+                    content_range: Some(format!(
+                        "bytes 0-{}/{}",
+                        jpeg.len() - 1,
+                        MAX_SOURCE_LENGTH + 1
+                    )),
+                    data: Body::from(jpeg.clone()),
+                    ..Default::default()
+                })
+            });
+        // The assembled image is never published with its metadata
+        operations.expect_upload().never();
+        operations.expect_copy_object().never();
+        operations
+            .expect_delete_object()
+            .withf(|_, key| key == "message_attachment/video.mp4.multipart/staging")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .post("/message_attachment/video.mp4/multipart")
+            .json(&CompleteMultipartRequest { parts: vec![] })
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_abort_multipart() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::VideoMP4])
+                            .with_bucket("beep-attachments")
+                            .with_key_prefix("uploads"),
+                    )
+                    .build(),
+            )
+        });
+        operations.expect_verify_parts().returning(|_| {
+            Ok(claims(
+                AvailableActions::AbortMultipartUpload,
+                BoundParams {
+                    upload_id: Some("upload".to_string()),
+                    staging_id: Some("staging".to_string()),
+                    part_number: None,
+                    ..Default::default()
+                },
+            ))
+        });
+        operations
+            .expect_abort_multipart_upload()
+            .withf(|bucket, key, upload_id| {
+                bucket == "beep-attachments"
+                    && key == "uploads/message_attachment/video.mp4.multipart/staging"
+                    && upload_id == "upload"
+            })
+            .returning(|_, _, _| Ok(()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .delete("/message_attachment/video.mp4/multipart")
+            .await;
+        response.assert_status(StatusCode::NO_CONTENT);
+    }
+}
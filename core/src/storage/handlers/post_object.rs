@@ -9,31 +9,87 @@ use utoipa::ToSchema;
 use crate::app::tests::TestAppState;
 use crate::{
     app::{AppState, AppStateOperations},
-    signed_url::service::{AvailableActions, SignedUrlError},
+    auth::{Caller, extractor::AuthenticatedCaller},
+    signed_url::service::{AvailableActions, BoundParams, SignedUrlError},
 };
 
 #[derive(Deserialize, Serialize, ToSchema)]
 pub struct SignUrlRequest {
     pub action: AvailableActions,
-    pub expires_in_ms: u64,
+    /// Lifetime of the url, in seconds
+    pub expires_in_secs: u64,
+    /// Content type the upload must be sent with, only for `Put` and
+    /// `CreateMultipartUpload`
+    pub content_type: Option<String>,
+    /// Maximum length in bytes of the upload, only for `Put` and
+    /// `CreateMultipartUpload`
+    pub max_content_length: Option<u64>,
 }
 
 #[derive(Deserialize, Serialize, ToSchema)]
 pub struct SignUrlResponse {
     pub url: String,
+    /// Expiration date of the url, in Unix seconds
+    pub expires_at: u64,
 }
 
 fn post_sign_url<S>(
-    path: String,
+    prefix: String,
+    file_name: String,
     request: SignUrlRequest,
+    caller: Caller,
     state: S,
 ) -> Result<SignUrlResponse, SignedUrlError>
 where
     S: AppStateOperations + Send + Sync + 'static,
 {
-    let url = state.sign_url(path, request.action, request.expires_in_ms)?;
+    let path = format!("{}/{}", prefix, file_name);
+    let path = match request.action {
+        AvailableActions::CreateMultipartUpload => format!("{}/multipart", path),
+        // The other multipart urls are signed when the upload is created, and
+        // listings are requested with the bearer token
+        action if action.is_multipart() || action == AvailableActions::List => {
+            return Err(SignedUrlError::UnsupportedAction(action.to_string()));
+        }
+        _ => path,
+    };
+    let constrained = request.content_type.is_some() || request.max_content_length.is_some();
+    let constrainable = matches!(
+        request.action,
+        AvailableActions::Put | AvailableActions::CreateMultipartUpload
+    );
+    if constrained && !constrainable {
+        return Err(SignedUrlError::UnsupportedAction(format!(
+            "{} with content constraints",
+            request.action
+        )));
+    }
+    if !caller.allows(&prefix, request.action) {
+        return Err(SignedUrlError::Forbidden(format!(
+            "{} is not allowed to sign {} urls for {}",
+            caller.name, request.action, prefix
+        )));
+    }
+    let guards = state.guards();
+    let guard = guards
+        .guard(&prefix)
+        .map_err(|_| SignedUrlError::UnknownPrefix(prefix.clone()))?;
+    if let Some(max_ttl) = guard.max_ttl()
+        && request.expires_in_secs > max_ttl
+    {
+        return Err(SignedUrlError::TtlTooLong(request.action, max_ttl));
+    }
+    let params = BoundParams {
+        content_type: request.content_type,
+        max_content_length: request.max_content_length,
+        ..Default::default()
+    };
+    let url = state.sign_url(path, request.action, request.expires_in_secs, params)?;
 
-    Ok(SignUrlResponse { url })
+    Ok(SignUrlResponse {
+        url: url.url,
+        expires_at: url.expires_at,
+    })
 }
 
 #[utoipa::path(
@@ -48,18 +104,21 @@ where
     responses(
         (status = 200, description = "Upload successful", body = SignUrlResponse),
         (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Missing or invalid bearer token", body = String),
+        (status = 403, description = "Prefix or action not allowed for the caller", body = String),
+        (status = 404, description = "Unknown prefix", body = String),
         (status = 500, description = "Internal server error", body = String),
     ),
+    security(("bearer" = [])),
 )]
 pub async fn post_sign_url_handler(
     Path((prefix, file_name)): Path<(String, String)>,
     State(state): State<AppState>,
+    AuthenticatedCaller(caller): AuthenticatedCaller,
     Json(request): Json<SignUrlRequest>,
 ) -> Result<Json<SignUrlResponse>, SignedUrlError> {
     Ok(Json(post_sign_url(
-        format!("{}/{}", prefix, file_name),
-        request,
-        state,
+        prefix, file_name, request, caller, state,
     )?))
 }
 
@@ -67,23 +126,29 @@ pub async fn post_sign_url_handler(
 pub async fn post_sign_url_test(
     Path((prefix, file_name)): Path<(String, String)>,
     State(state): State<TestAppState>,
+    AuthenticatedCaller(caller): AuthenticatedCaller,
     Json(request): Json<SignUrlRequest>,
 ) -> Result<Json<SignUrlResponse>, SignedUrlError> {
     Ok(Json(post_sign_url(
-        format!("{}/{}", prefix, file_name),
-        request,
-        state,
+        prefix, file_name, request, caller, state,
     )?))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use axum::{Router, routing::post};
     use axum_test::TestServer;
 
     use crate::{
         app::MockAppStateOperations,
-        signed_url::{extractor::Claims, service::AvailableActions},
+        auth::tests::{TEST_TOKEN, config_with_caller},
+        guards::{FileType, Guard, GuardsBuilder},
+        signed_url::{
+            extractor::Claims,
+            service::{AvailableActions, IssuedUrl},
+        },
     };
 
     use super::*;
@@ -94,12 +159,33 @@ mod tests {
             .with_state(app_state)
     }
 
+    /// Guards `message_attachment` with urls valid for an hour at most
+    fn mock_guards(operations: &mut MockAppStateOperations) {
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::Any]).with_max_ttl(3600),
+                    )
+                    .build(),
+            )
+        });
+    }
+
     #[tokio::test]
     async fn test_post_sign_url() {
         let mut operations = MockAppStateOperations::new();
         operations
-            .expect_sign_url()
-            .returning(|_, _, _| Ok("https://beep.com/prefix/file_name".to_string()));
+            .expect_config()
+            .returning(|| config_with_caller("message_attachment"));
+        mock_guards(&mut operations);
+        operations.expect_sign_url().returning(|_, _, _, _| {
+            Ok(IssuedUrl {
+                url: "https://beep.com/prefix/file_name".to_string(),
+                expires_at: 200,
+            })
+        });
         operations
             .expect_verify_parts()
             .returning(|_| Ok(Claims::default()));
@@ -110,9 +196,187 @@ mod tests {
         let client = TestServer::new(router).expect("Axum test server creation failed");
         let payload = SignUrlRequest {
             action: AvailableActions::Put,
-            expires_in_ms: 100,
+            expires_in_secs: 100,
+            content_type: None,
+            max_content_length: None,
         };
-        let response = client.post("/prefix/file_name").json(&payload).await;
+        let response = client
+            .post("/message_attachment/file_name")
+            .authorization_bearer(TEST_TOKEN)
+            .json(&payload)
+            .await;
         insta::assert_debug_snapshot!(response);
     }
+
+    #[tokio::test]
+    async fn test_post_sign_url_multipart() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| config_with_caller("message_attachment"));
+        mock_guards(&mut operations);
+        operations
+            .expect_sign_url()
+            .withf(|path, _, _, _| path == "message_attachment/file_name/multipart")
+            .returning(|_, _, _, _| {
+                Ok(IssuedUrl {
+                    url: "https://beep.com/prefix/file_name/multipart".to_string(),
+                    expires_at: 200,
+                })
+            });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let client = TestServer::new(router).expect("Axum test server creation failed");
+        let payload = SignUrlRequest {
+            action: AvailableActions::CreateMultipartUpload,
+            expires_in_secs: 100,
+            content_type: None,
+            max_content_length: None,
+        };
+        let response = client
+            .post("/message_attachment/file_name")
+            .authorization_bearer(TEST_TOKEN)
+            .json(&payload)
+            .await;
+        response.assert_status_ok();
+
+        let payload = SignUrlRequest {
+            action: AvailableActions::UploadPart,
+            expires_in_secs: 100,
+            content_type: None,
+            max_content_length: None,
+        };
+        let response = client
+            .post("/message_attachment/file_name")
+            .authorization_bearer(TEST_TOKEN)
+            .json(&payload)
+            .await;
+        response.assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_post_sign_url_with_content_constraints() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| config_with_caller("message_attachment"));
+        mock_guards(&mut operations);
+        operations
+            .expect_sign_url()
+            .withf(|_, _, _, params| {
+                params.content_type.as_deref() == Some("image/png")
+                    && params.max_content_length == Some(1024)
+            })
+            .returning(|_, _, _, _| {
+                Ok(IssuedUrl {
+                    url: "https://beep.com/prefix/file_name".to_string(),
+                    expires_at: 200,
+                })
+            });
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let client = TestServer::new(router).expect("Axum test server creation failed");
+        let payload = SignUrlRequest {
+            action: AvailableActions::Put,
+            expires_in_secs: 100,
+            content_type: Some("image/png".to_string()),
+            max_content_length: Some(1024),
+        };
+        let response = client
+            .post("/message_attachment/file_name")
+            .authorization_bearer(TEST_TOKEN)
+            .json(&payload)
+            .await;
+        response.assert_status_ok();
+
+        let payload = SignUrlRequest {
+            action: AvailableActions::CreateMultipartUpload,
+            expires_in_secs: 100,
+            content_type: Some("image/png".to_string()),
+            max_content_length: Some(1024),
+        };
+        let response = client
+            .post("/message_attachment/file_name")
+            .authorization_bearer(TEST_TOKEN)
+            .json(&payload)
+            .await;
+        response.assert_status_ok();
+
+        let payload = SignUrlRequest {
+            action: AvailableActions::Get,
+            expires_in_secs: 100,
+            content_type: Some("image/png".to_string()),
+            max_content_length: None,
+        };
+        let response = client
+            .post("/message_attachment/file_name")
+            .authorization_bearer(TEST_TOKEN)
+            .json(&payload)
+            .await;
+        response.assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_post_sign_url_unauthorized() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| config_with_caller("message_attachment"));
+        mock_guards(&mut operations);
+        operations.expect_sign_url().never();
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let client = TestServer::new(router).expect("Axum test server creation failed");
+        let payload = SignUrlRequest {
+            action: AvailableActions::Put,
+            expires_in_secs: 100,
+            content_type: None,
+            max_content_length: None,
+        };
+        let response = client
+            .post("/message_attachment/file_name")
+            .json(&payload)
+            .await;
+        response.assert_status_unauthorized();
+
+        let response = client
+            .post("/other_prefix/file_name")
+            .authorization_bearer(TEST_TOKEN)
+            .json(&payload)
+            .await;
+        response.assert_status_forbidden();
+    }
+
+    #[tokio::test]
+    async fn test_post_sign_url_prefix_policy() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| config_with_caller("message_attachment"));
+        mock_guards(&mut operations);
+        operations.expect_sign_url().never();
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let client = TestServer::new(router).expect("Axum test server creation failed");
+        let payload = SignUrlRequest {
+            action: AvailableActions::Get,
+            expires_in_secs: 3601,
+            content_type: None,
+            max_content_length: None,
+        };
+        let response = client
+            .post("/message_attachment/file_name")
+            .authorization_bearer(TEST_TOKEN)
+            .json(&payload)
+            .await;
+        response.assert_status_bad_request();
+    }
 }
@@ -1,4 +1,11 @@
-use axum::{body::Bytes, extract::State, http::HeaderMap, http::header::CONTENT_TYPE};
+use axum::{
+    Json,
+    body::{Body, Bytes, to_bytes},
+    extract::State,
+    http::HeaderMap,
+    http::header::CONTENT_TYPE,
+};
+use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 #[cfg(test)]
@@ -6,7 +13,18 @@ use crate::app::tests::TestAppState;
 use crate::{
     app::{AppState, AppStateOperations},
     error::ApiError,
-    signed_url::extractor::SignedUrl,
+    guards::{SNIFF_LENGTH, media_type},
+    images::{
+        ImageError,
+        metadata::{MetadataFormat, strip_metadata},
+        variants::VariantFormat,
+    },
+    s3::FileObject,
+    signed_url::{extractor::SignedUrl, service::BoundParams},
+    storage::{
+        body::{content_length, peek},
+        variants::{Derivative, MAX_SOURCE_LENGTH, render_derivatives, store_derivatives},
+    },
 };
 
 #[derive(ToSchema)]
@@ -16,6 +34,15 @@ struct UploadRequest {
     pub file: Vec<u8>,
 }
 
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct UploadResponse {
+    /// Path of the upload, `<prefix>/<file_name>`, whatever bucket and key the
+    /// policy of its prefix stores it under
+    pub key: String,
+    /// Variants rendered from an uploaded image, as configured for its prefix
+    pub derivatives: Vec<Derivative>,
+}
+
 #[utoipa::path(
     put,
     path = "/{prefix}/{file_name}",
@@ -26,8 +53,12 @@ struct UploadRequest {
     ),
     request_body(content = UploadRequest, content_type = "application/octet-stream"),
     responses(
-        (status = 200, description = "Upload successful", body = String),
+        (status = 200, description = "Upload successful", body = UploadResponse),
         (status = 400, description = "Invalid request", body = String),
+        (status = 403, description = "Content type not allowed by the signed url", body = String),
+        (status = 411, description = "Missing Content-Length", body = String),
+        (status = 413, description = "File too large for the prefix", body = String),
+        (status = 422, description = "Image can't be decoded or exceeds the image limits", body = String),
         (status = 500, description = "Internal server error", body = String),
     ),
 )]
@@ -35,10 +66,12 @@ pub async fn put_object_handler(
     State(state): State<AppState>,
     SignedUrl(claims): SignedUrl,
     headers: HeaderMap,
-    body: Bytes,
-) -> Result<String, ApiError> {
+    body: Body,
+) -> Result<Json<UploadResponse>, ApiError> {
     let (prefix, file_name) = claims.path;
-    put_object(body, headers, state, prefix, file_name).await
+    Ok(Json(
+        put_object(body, headers, state, prefix, file_name, claims.params).await?,
+    ))
 }
 
 #[cfg(test)]
@@ -46,15 +79,24 @@ pub async fn put_object_test(
     State(state): State<TestAppState>,
     SignedUrl(claims): SignedUrl,
     headers: HeaderMap,
-    body: Bytes,
-) -> Result<String, ApiError> {
+    body: Body,
+) -> Result<Json<UploadResponse>, ApiError> {
     let (prefix, file_name) = claims.path;
-    put_object(body, headers, state, prefix, file_name).await
+    Ok(Json(
+        put_object(body, headers, state, prefix, file_name, claims.params).await?,
+    ))
 }
 
 /// Uploads a file from a raw binary request to S3.
-/// The output of this method when successful is just a string "Uploaded"
-/// confirming that the file was uploaded successfully.
+/// Only the first bytes of the body are buffered for the guards, the rest is
+/// streamed to S3, which is why the request must carry its length.
+/// The content type and maximum length bound to the signed url, if any, are
+/// enforced before the guards.
+/// Images whose metadata is stripped, or uploaded to a prefix with derivatives,
+/// are buffered instead, so that they are processed before the upload. Their
+/// derivatives are rendered from the stripped image and stored after it.
+/// The output of this method when successful is the key of the upload along
+/// with the keys of its derivatives.
 ///
 /// # Examples
 ///
@@ -65,12 +107,13 @@ pub async fn put_object_test(
 ///     .with_state(app_state);
 /// ```
 async fn put_object<S>(
-    body: Bytes,
+    body: Body,
     headers: HeaderMap,
     state: S,
     prefix: String,
     file_name: String,
-) -> Result<String, ApiError>
+    params: BoundParams,
+) -> Result<UploadResponse, ApiError>
 where
     S: AppStateOperations + Send + Sync + 'static,
 {
@@ -79,21 +122,114 @@ where
         .and_then(|v| v.to_str().ok())
         .unwrap_or("application/octet-stream");
 
-    let bucket = state.config().s3_bucket.clone();
+    if let Some(allowed) = &params.content_type
+        && !same_media_type(allowed, content_type)
+    {
+        return Err(ApiError::Forbidden(format!(
+            "Content type {} is not allowed, expected {}",
+            content_type, allowed
+        )));
+    }
+
+    let content_length = content_length(&body)?;
+
+    if let Some(max_content_length) = params.max_content_length
+        && content_length > max_content_length
+    {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "File too large, the maximum size is {} bytes",
+            max_content_length
+        )));
+    }
+
+    let guards = state.guards();
+    let location = guards.location(&state.config().s3_bucket, &prefix, &file_name);
+
+    let (head, data) = peek(body, SNIFF_LENGTH).await?;
+
+    let content_type = guards
+        .check(&prefix, &location.key, &head, content_length, content_type)
+        .map_err(|e| e.into())?;
+    let guard = guards.guard(&prefix).map_err(|e| e.into())?;
+    let media_type = media_type(&content_type);
+    let strip_format =
+        MetadataFormat::from_content_type(&media_type).filter(|_| guard.strips_metadata());
+    let source_format =
+        VariantFormat::from_source(&media_type).filter(|_| !guard.derivatives().is_empty());
 
-    let key = format!("{}/{}", prefix, file_name);
+    if strip_format.is_none() && source_format.is_none() && !guard.limits_images(&media_type) {
+        let file = FileObject {
+            data,
+            content_type,
+            content_length,
+        };
+        state
+            .upload(&location.bucket, &location.key, file)
+            .await
+            .map_err(|e| e.into())?;
+        return Ok(UploadResponse {
+            key: format!("{}/{}", prefix, file_name),
+            derivatives: Vec::new(),
+        });
+    }
 
-    let file = state
-        .guards()
-        .check(&prefix, &key, body.to_vec(), content_type)
+    let mut data = to_bytes(data, MAX_SOURCE_LENGTH)
+        .await
+        .map_err(|_| ApiError::PayloadTooLarge("Image too large to be processed".to_string()))?;
+    guard
+        .check_image(&data, &media_type)
         .map_err(|e| e.into())?;
+    if let Some(strip_format) = strip_format {
+        let image = data.clone();
+        data = tokio::task::spawn_blocking(move || strip_metadata(&image, strip_format))
+            .await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+            .map(Bytes::from)
+            .map_err(upload_image_error)?;
+    }
+    let rendered = match source_format {
+        Some(source_format) => {
+            render_derivatives(data.clone(), source_format, guard.derivatives().to_vec())
+                .await
+                .map_err(upload_image_error)?
+        }
+        None => Vec::new(),
+    };
 
-    state
-        .upload(&bucket, &key, file)
+    let file = FileObject {
+        content_length: data.len() as u64,
+        data: Body::from(data),
+        content_type,
+    };
+    let e_tag = state
+        .upload(&location.bucket, &location.key, file)
         .await
         .map_err(|e| e.into())?;
+    let path = format!("{}/{}", prefix, file_name);
+    let derivatives = match source_format {
+        Some(source_format) if !rendered.is_empty() => {
+            store_derivatives(&state, &location, &path, &e_tag, source_format, rendered).await?
+        }
+        _ => Vec::new(),
+    };
 
-    Ok("Uploaded".to_string())
+    Ok(UploadResponse {
+        key: path,
+        derivatives,
+    })
+}
+
+/// Images that can't be decoded are the uploader's fault rather than ours
+pub fn upload_image_error(error: ImageError) -> ApiError {
+    match error {
+        ImageError::Decode(_) => ApiError::UnProcessableEntity(error.to_string()),
+        error => error.into(),
+    }
+}
+
+/// Compares two content types, ignoring their parameters and case
+fn same_media_type(a: &str, b: &str) -> bool {
+    media_type(a) == media_type(b)
 }
 
 #[cfg(test)]
@@ -103,8 +239,12 @@ pub mod tests {
     use crate::{
         app::MockAppStateOperations,
         config::Config,
-        guards::{FileType, Guard, GuardsBuilder},
-        prefixes::Prefix,
+        guards::{FileType, Guard, Guards, GuardsBuilder},
+        images::{
+            limits::ImageLimits,
+            metadata::tests::fake_jpeg,
+            variants::{Fit, VariantParams, tests::fake_png},
+        },
         signed_url::{extractor::Claims, service::AvailableActions},
     };
     use axum::{Router, routing::put};
@@ -128,18 +268,16 @@ pub mod tests {
 
         operations.expect_verify_parts().returning(|_| {
             Ok(Claims {
-                path: (
-                    Prefix::ServerBanner.as_str().to_string(),
-                    "index.html".to_string(),
-                ),
+                path: ("server_banner".to_string(), "index.html".to_string()),
                 action: AvailableActions::Put,
+                ..Default::default()
             })
         });
 
         operations.expect_guards().returning(|| {
             Arc::new(
                 GuardsBuilder::new()
-                    .add(Prefix::ServerBanner, Guard::new(vec![FileType::Any]))
+                    .add("server_banner", Guard::new(vec![FileType::Any]))
                     .build(),
             )
         });
@@ -164,6 +302,57 @@ pub mod tests {
         insta::assert_debug_snapshot!(response);
     }
 
+    #[tokio::test]
+    async fn test_put_object_mapped_prefix() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_upload()
+            .withf(|bucket, key, _| {
+                bucket == "beep-attachments" && key == "uploads/message_attachment/notes.txt"
+            })
+            .returning(|_, _, _| Ok("Uploaded".to_string()));
+
+        operations.expect_verify_parts().returning(|_| {
+            Ok(Claims {
+                path: ("message_attachment".to_string(), "notes.txt".to_string()),
+                action: AvailableActions::Put,
+                ..Default::default()
+            })
+        });
+
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::Any])
+                            .with_bucket("beep-attachments")
+                            .with_key_prefix("uploads"),
+                    )
+                    .build(),
+            )
+        });
+
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+
+        let app_state = TestAppState::new(operations);
+        let router = fake_router(app_state);
+
+        let response = TestServer::new(router)
+            .expect("Axum test server creation failed")
+            .put("/message_attachment/notes.txt?action=Put&expires=1684969600&signature=test")
+            .content_type("text/plain")
+            .bytes("hello".into())
+            .await;
+
+        response.assert_status_ok();
+        let upload = response.json::<UploadResponse>();
+        // The path of the upload rather than where its prefix is stored
+        assert_eq!(upload.key, "message_attachment/notes.txt");
+    }
+
     #[tokio::test]
     async fn test_put_object_empty_body() {
         let mut operations = MockAppStateOperations::new();
@@ -173,8 +362,9 @@ pub mod tests {
 
         operations.expect_verify_parts().returning(|_| {
             Ok(Claims {
-                path: (Prefix::ServerBanner.as_str().to_string(), "index.html".to_string()),
+                path: ("server_banner".to_string(), "index.html".to_string()),
                 action: AvailableActions::Put,
+                ..Default::default()
             })
         });
 
@@ -185,7 +375,7 @@ pub mod tests {
         operations.expect_guards().returning(|| {
             Arc::new(
                 GuardsBuilder::new()
-                    .add(Prefix::ServerBanner, Guard::new(vec![FileType::Any]))
+                    .add("server_banner", Guard::new(vec![FileType::Any]))
                     .build(),
             )
         });
@@ -204,4 +394,329 @@ pub mod tests {
 
         response.assert_status(StatusCode::OK);
     }
+
+    #[tokio::test]
+    async fn test_put_object_without_length() {
+        let operations = MockAppStateOperations::new();
+        let app_state = TestAppState::new(operations);
+
+        let chunks: Vec<Result<&'static str, std::io::Error>> = vec![Ok("Hello "), Ok("World")];
+        let body = Body::from_stream(futures_util::stream::iter(chunks));
+
+        let response = put_object(
+            body,
+            HeaderMap::new(),
+            app_state,
+            "server_banner".to_string(),
+            "index.html".to_string(),
+            BoundParams::default(),
+        )
+        .await;
+
+        assert!(matches!(response, Err(ApiError::LengthRequired(_))));
+    }
+
+    #[tokio::test]
+    async fn test_put_object_content_type_mismatch() {
+        let operations = MockAppStateOperations::new();
+        let app_state = TestAppState::new(operations);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "text/html".parse().expect("Invalid header"));
+
+        let response = put_object(
+            Body::from("<html></html>"),
+            headers,
+            app_state,
+            "message_attachment".to_string(),
+            "index.html".to_string(),
+            BoundParams {
+                content_type: Some("image/png".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert!(matches!(response, Err(ApiError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn test_put_object_over_max_content_length() {
+        let operations = MockAppStateOperations::new();
+        let app_state = TestAppState::new(operations);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            "text/plain; charset=utf-8".parse().expect("Invalid header"),
+        );
+
+        let response = put_object(
+            Body::from("Hello World"),
+            headers,
+            app_state,
+            "message_attachment".to_string(),
+            "hello.txt".to_string(),
+            BoundParams {
+                content_type: Some("Text/Plain".to_string()),
+                max_content_length: Some(5),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert!(matches!(response, Err(ApiError::PayloadTooLarge(_))));
+    }
+
+    fn guards_with_derivatives() -> Arc<Guards> {
+        Arc::new(
+            GuardsBuilder::new()
+                .add(
+                    "profile_picture",
+                    Guard::new(vec![FileType::ImagePNG])
+                        .with_key_prefix("uploads")
+                        .with_derivatives(vec![VariantParams {
+                            w: Some(32),
+                            h: Some(32),
+                            fit: Some(Fit::Cover),
+                            ..Default::default()
+                        }]),
+                )
+                .build(),
+        )
+    }
+
+    fn png_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "image/png".parse().expect("Invalid header"));
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_put_object_with_derivatives() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations
+            .expect_guards()
+            .returning(guards_with_derivatives);
+        operations
+            .expect_upload()
+            .withf(|_, key, file| {
+                key == "uploads/profile_picture/me.png" && file.content_type == "image/png"
+            })
+            .times(1)
+            .returning(|_, _, _| Ok("\"abc\"".to_string()));
+        operations
+            .expect_upload()
+            .withf(|_, key, _| {
+                key == "uploads/profile_picture/me.png/variants/abc-w32-h32-cover.png"
+            })
+            .times(1)
+            .returning(|_, _, _| Ok("\"def\"".to_string()));
+        // The ETag of the upload names the derivatives, whatever is stored now
+        operations.expect_head_object().never();
+        let app_state = TestAppState::new(operations);
+
+        let response = put_object(
+            Body::from(fake_png(64, 64)),
+            png_headers(),
+            app_state,
+            "profile_picture".to_string(),
+            "me.png".to_string(),
+            BoundParams::default(),
+        )
+        .await
+        .expect("Upload failed");
+
+        // Answered with their paths rather than where they are stored
+        assert_eq!(response.key, "profile_picture/me.png");
+        assert_eq!(
+            response.derivatives,
+            vec![Derivative {
+                key: "profile_picture/me.png/variants/abc-w32-h32-cover.png".to_string(),
+                query: "w=32&h=32&fit=cover".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_object_undecodable_image() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations
+            .expect_guards()
+            .returning(guards_with_derivatives);
+        operations.expect_upload().never();
+        let app_state = TestAppState::new(operations);
+
+        // A PNG signature followed by garbage passes the guards
+        let mut png = fake_png(64, 64);
+        png.truncate(16);
+        let response = put_object(
+            Body::from(png),
+            png_headers(),
+            app_state,
+            "profile_picture".to_string(),
+            "me.png".to_string(),
+            BoundParams::default(),
+        )
+        .await;
+
+        assert!(matches!(response, Err(ApiError::UnProcessableEntity(_))));
+    }
+
+    #[tokio::test]
+    async fn test_put_object_image_too_large() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "profile_picture",
+                        Guard::new(vec![FileType::ImagePNG]).with_image_limits(ImageLimits {
+                            max_pixels: Some(1024),
+                            ..Default::default()
+                        }),
+                    )
+                    .build(),
+            )
+        });
+        operations.expect_upload().never();
+        let app_state = TestAppState::new(operations);
+
+        let response = put_object(
+            Body::from(fake_png(64, 64)),
+            png_headers(),
+            app_state,
+            "profile_picture".to_string(),
+            "me.png".to_string(),
+            BoundParams::default(),
+        )
+        .await;
+
+        assert!(matches!(response, Err(ApiError::UnProcessableEntity(_))));
+    }
+
+    fn guards_stripping_metadata() -> Arc<Guards> {
+        Arc::new(
+            GuardsBuilder::new()
+                .add(
+                    "message_attachment",
+                    Guard::new(vec![FileType::Any]).with_strip_metadata(true),
+                )
+                .build(),
+        )
+    }
+
+    /// A JPEG padded with trailing bytes up to `length`
+    fn padded_jpeg(length: usize) -> Vec<u8> {
+        let mut jpeg = fake_jpeg(8, 8, None);
+        jpeg.resize(length, 0);
+        jpeg
+    }
+
+    fn jpeg_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "image/jpeg".parse().expect("Invalid header"));
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_put_object_strips_metadata() {
+        let jpeg = fake_jpeg(8, 8, None);
+        let length = jpeg.len() as u64;
+
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations
+            .expect_guards()
+            .returning(guards_stripping_metadata);
+        operations
+            .expect_upload()
+            .withf(move |_, _, file| file.content_length < length)
+            .times(1)
+            .returning(|_, _, _| Ok("url".to_string()));
+        let app_state = TestAppState::new(operations);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            "Image/JPEG; name=photo".parse().expect("Invalid header"),
+        );
+        let response = put_object(
+            Body::from(jpeg),
+            headers,
+            app_state,
+            "message_attachment".to_string(),
+            "photo.jpg".to_string(),
+            BoundParams::default(),
+        )
+        .await
+        .expect("Upload failed");
+
+        assert!(response.derivatives.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_put_object_strips_metadata_up_to_source_length() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations
+            .expect_guards()
+            .returning(guards_stripping_metadata);
+        operations
+            .expect_upload()
+            .withf(|_, _, file| file.content_length <= MAX_SOURCE_LENGTH as u64)
+            .times(1)
+            .returning(|_, _, _| Ok("\"abc\"".to_string()));
+        let app_state = TestAppState::new(operations);
+
+        put_object(
+            Body::from(padded_jpeg(MAX_SOURCE_LENGTH)),
+            jpeg_headers(),
+            app_state,
+            "message_attachment".to_string(),
+            "photo.jpg".to_string(),
+            BoundParams::default(),
+        )
+        .await
+        .expect("Upload failed");
+    }
+
+    #[tokio::test]
+    async fn test_put_object_strip_too_large() {
+        let mut operations = MockAppStateOperations::new();
+        operations
+            .expect_config()
+            .returning(|| Arc::new(Config::default()));
+        operations
+            .expect_guards()
+            .returning(guards_stripping_metadata);
+        // Never stored with its metadata
+        operations.expect_upload().never();
+        let app_state = TestAppState::new(operations);
+
+        let response = put_object(
+            Body::from(padded_jpeg(MAX_SOURCE_LENGTH + 1)),
+            jpeg_headers(),
+            app_state,
+            "message_attachment".to_string(),
+            "photo.jpg".to_string(),
+            BoundParams::default(),
+        )
+        .await;
+
+        assert!(matches!(response, Err(ApiError::PayloadTooLarge(_))));
+    }
 }
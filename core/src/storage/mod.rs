@@ -1,2 +1,5 @@
+pub mod body;
 pub mod handlers;
+pub mod response;
 pub mod router;
+pub mod variants;
@@ -0,0 +1,252 @@
+use axum::body::Body;
+use chrono::{DateTime, Utc};
+use http::{
+    HeaderMap, Response, StatusCode,
+    header::{
+        ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE,
+        CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+        X_CONTENT_TYPE_OPTIONS,
+    },
+    response::Builder,
+};
+
+use crate::{
+    error::ApiError,
+    guards::active_content::is_inline_safe,
+    range::ByteRange,
+    s3::{ObjectMetadata, ObjectStream},
+};
+
+/// Format of the dates of the HTTP headers, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Extracts the byte range requested by the client, if any.
+/// Malformed or multi-range headers are ignored and the whole object is served.
+pub fn requested_range(headers: &HeaderMap) -> Option<ByteRange> {
+    headers
+        .get(RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(ByteRange::parse)
+}
+
+/// Builds the response streaming an object to the client, with a
+/// `206 Partial Content` status when only a range of it was fetched, or a
+/// bodyless `304 Not Modified` when the copy cached by the client is still fresh.
+pub fn object_response(
+    object: ObjectStream,
+    headers: &HeaderMap,
+    cache_control: Option<&str>,
+) -> Result<Response<Body>, ApiError> {
+    let e_tag = object.e_tag.as_deref();
+    let mut response = validator_headers(e_tag, object.last_modified, cache_control);
+    if is_not_modified(headers, e_tag, object.last_modified) {
+        return not_modified(response);
+    }
+
+    response = content_headers(response, object.content_type, object.content_length);
+    response = match object.content_range {
+        Some(content_range) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(CONTENT_RANGE, content_range),
+        None => response.status(StatusCode::OK),
+    };
+
+    response
+        .body(object.data)
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))
+}
+
+/// Builds the bodyless response to a `HEAD` request, carrying the headers
+/// a `GET` of the whole object would have.
+pub fn metadata_response(
+    metadata: ObjectMetadata,
+    headers: &HeaderMap,
+    cache_control: Option<&str>,
+) -> Result<Response<Body>, ApiError> {
+    let e_tag = metadata.e_tag.as_deref();
+    let response = validator_headers(e_tag, metadata.last_modified, cache_control);
+    if is_not_modified(headers, e_tag, metadata.last_modified) {
+        return not_modified(response);
+    }
+
+    content_headers(response, metadata.content_type, metadata.content_length)
+        .status(StatusCode::OK)
+        .body(Body::empty())
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))
+}
+
+/// Headers letting clients cache the object and revalidate their copy
+fn validator_headers(
+    e_tag: Option<&str>,
+    last_modified: Option<DateTime<Utc>>,
+    cache_control: Option<&str>,
+) -> Builder {
+    let mut response = Response::builder();
+    if let Some(e_tag) = e_tag {
+        response = response.header(ETAG, e_tag);
+    }
+    if let Some(last_modified) = last_modified {
+        response = response.header(
+            LAST_MODIFIED,
+            last_modified.format(HTTP_DATE_FORMAT).to_string(),
+        );
+    }
+    if let Some(cache_control) = cache_control {
+        response = response.header(CACHE_CONTROL, cache_control);
+    }
+    response
+}
+
+/// Headers describing the content of the object. Browsers are told not to
+/// sniff it, and to download rather than display what they could execute.
+fn content_headers(response: Builder, content_type: String, content_length: u64) -> Builder {
+    let inline_safe = is_inline_safe(&content_type);
+    let mut response = response
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_LENGTH, content_length)
+        .header(ACCEPT_RANGES, "bytes")
+        .header(X_CONTENT_TYPE_OPTIONS, "nosniff");
+    if !inline_safe {
+        response = response.header(CONTENT_DISPOSITION, "attachment");
+    }
+    response
+}
+
+fn not_modified(response: Builder) -> Result<Response<Body>, ApiError> {
+    response
+        .status(StatusCode::NOT_MODIFIED)
+        .body(Body::empty())
+        .map_err(|e| ApiError::InternalServerError(e.to_string()))
+}
+
+/// Evaluates the conditional headers of the request against the object.
+/// As in RFC 9110, `If-Modified-Since` is ignored when `If-None-Match` is present.
+fn is_not_modified(
+    headers: &HeaderMap,
+    e_tag: Option<&str>,
+    last_modified: Option<DateTime<Utc>>,
+) -> bool {
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        let Some(e_tag) = e_tag else {
+            return false;
+        };
+        return if_none_match.to_str().is_ok_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || weak_tag(tag) == weak_tag(e_tag))
+        });
+    }
+
+    let if_modified_since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+    match (if_modified_since, last_modified) {
+        (Some(since), Some(last_modified)) => last_modified <= since.with_timezone(&Utc),
+        _ => false,
+    }
+}
+
+/// Entity tags are compared weakly, ignoring the `W/` prefix
+fn weak_tag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_object() -> ObjectStream {
+        ObjectStream {
+            e_tag: Some("\"abc\"".to_string()),
+            last_modified: DateTime::from_timestamp(784111777, 0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        for (if_none_match, not_modified) in [
+            ("\"abc\"", true),
+            ("W/\"abc\"", true),
+            ("\"xyz\", \"abc\"", true),
+            ("*", true),
+            ("\"xyz\"", false),
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_NONE_MATCH, if_none_match.parse().unwrap());
+            // If-None-Match takes precedence over a matching If-Modified-Since
+            headers.insert(
+                IF_MODIFIED_SINCE,
+                "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap(),
+            );
+            assert_eq!(
+                is_not_modified(&headers, Some("\"abc\""), fake_object().last_modified),
+                not_modified,
+                "{}",
+                if_none_match
+            );
+        }
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        for (if_modified_since, not_modified) in [
+            ("Sun, 06 Nov 1994 08:49:37 GMT", true),
+            ("Mon, 07 Nov 1994 08:49:37 GMT", true),
+            ("Sat, 05 Nov 1994 08:49:37 GMT", false),
+            ("yesterday", false),
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_MODIFIED_SINCE, if_modified_since.parse().unwrap());
+            assert_eq!(
+                is_not_modified(&headers, Some("\"abc\""), fake_object().last_modified),
+                not_modified,
+                "{}",
+                if_modified_since
+            );
+        }
+        assert!(!is_not_modified(
+            &HeaderMap::new(),
+            Some("\"abc\""),
+            fake_object().last_modified
+        ));
+    }
+
+    #[test]
+    fn test_validator_headers() {
+        let response =
+            object_response(fake_object(), &HeaderMap::new(), Some("public, max-age=60")).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ETAG], "\"abc\"");
+        assert_eq!(
+            response.headers()[LAST_MODIFIED],
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(response.headers()[CACHE_CONTROL], "public, max-age=60");
+    }
+
+    #[test]
+    fn test_content_headers() {
+        for (content_type, attachment) in [
+            ("image/png", false),
+            ("text/plain; charset=utf-8", false),
+            ("text/html", true),
+            ("image/svg+xml", true),
+            ("application/octet-stream", true),
+        ] {
+            let object = ObjectStream {
+                content_type: content_type.to_string(),
+                ..fake_object()
+            };
+            let response = object_response(object, &HeaderMap::new(), None).unwrap();
+            assert_eq!(response.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+            assert_eq!(
+                response.headers().get(CONTENT_DISPOSITION).is_some(),
+                attachment,
+                "{}",
+                content_type
+            );
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{
     Router,
-    routing::{get, post, put},
+    routing::{delete, get, head, post, put},
 };
 
 #[cfg(test)]
@@ -8,33 +8,70 @@ use crate::app::tests::TestAppState;
 use crate::{
     app::AppState,
     storage::handlers::{
-        get_object::get_object_handler, get_public_object::get_public_object_handler,
-        post_object::post_sign_url_handler, put_object::put_object_handler,
+        delete_object::delete_object_handler,
+        get_object::get_object_handler,
+        get_public_object::get_public_object_handler,
+        head_object::{head_object_handler, head_public_object_handler},
+        list_objects::list_objects_handler,
+        multipart::{delete_multipart_handler, post_multipart_handler, put_part_handler},
+        post_object::post_sign_url_handler,
+        put_object::put_object_handler,
     },
 };
 
 pub fn storage_router(app_state: AppState) -> Router {
     Router::new()
+        .route("/list/{prefix}", get(list_objects_handler))
         .route("/{prefix}/{file_name}", put(put_object_handler))
         .route("/{prefix}/{file_name}", post(post_sign_url_handler))
         .route("/{prefix}/{file_name}", get(get_object_handler))
+        .route("/{prefix}/{file_name}", head(head_object_handler))
+        .route("/{prefix}/{file_name}", delete(delete_object_handler))
+        .route(
+            "/{prefix}/{file_name}/multipart",
+            post(post_multipart_handler),
+        )
+        .route("/{prefix}/{file_name}/multipart", put(put_part_handler))
+        .route(
+            "/{prefix}/{file_name}/multipart",
+            delete(delete_multipart_handler),
+        )
         .route(
             "/public/{prefix}/{file_name}",
             get(get_public_object_handler),
         )
+        .route(
+            "/public/{prefix}/{file_name}",
+            head(head_public_object_handler),
+        )
         .with_state(app_state)
 }
 
 #[cfg(test)]
 pub fn storage_router_test(app_state: TestAppState) -> Router {
     use crate::storage::handlers::{
-        get_object::get_object_test, post_object::post_sign_url_test, put_object::put_object_test,
+        delete_object::delete_object_test,
+        get_object::get_object_test,
+        head_object::head_object_test,
+        list_objects::list_objects_test,
+        multipart::{delete_multipart_test, post_multipart_test, put_part_test},
+        post_object::post_sign_url_test,
+        put_object::put_object_test,
     };
 
     Router::new()
+        .route("/list/{prefix}", get(list_objects_test))
         .route("/{prefix}/{file_name}", put(put_object_test))
         .route("/{prefix}/{file_name}", post(post_sign_url_test))
         .route("/{prefix}/{file_name}", get(get_object_test))
+        .route("/{prefix}/{file_name}", head(head_object_test))
+        .route("/{prefix}/{file_name}", delete(delete_object_test))
+        .route("/{prefix}/{file_name}/multipart", post(post_multipart_test))
+        .route("/{prefix}/{file_name}/multipart", put(put_part_test))
+        .route(
+            "/{prefix}/{file_name}/multipart",
+            delete(delete_multipart_test),
+        )
         .with_state(app_state)
 }
 
@@ -46,10 +83,13 @@ mod tests {
 
     use crate::{
         app::MockAppStateOperations,
+        auth::tests::{TEST_TOKEN, config_with_caller},
         config::Config,
         guards::{FileType, Guard, GuardsBuilder},
-        prefixes::Prefix,
-        signed_url::{extractor::Claims, service::AvailableActions},
+        signed_url::{
+            extractor::Claims,
+            service::{AvailableActions, IssuedUrl},
+        },
         storage::handlers::post_object::SignUrlRequest,
     };
 
@@ -67,18 +107,16 @@ mod tests {
 
         operations.expect_guards().returning(|| {
             let guards = GuardsBuilder::new()
-                .add(Prefix::ServerBanner, Guard::new(vec![FileType::Any]))
+                .add("server_banner", Guard::new(vec![FileType::Any]))
                 .build();
             Arc::new(guards)
         });
 
         operations.expect_verify_parts().returning(|_| {
             Ok(Claims {
-                path: (
-                    Prefix::ServerBanner.as_str().to_string(),
-                    "index.html".to_string(),
-                ),
+                path: ("server_banner".to_string(), "index.html".to_string()),
                 action: AvailableActions::Put,
+                ..Default::default()
             })
         });
         let app_state = TestAppState::new(operations);
@@ -101,8 +139,24 @@ mod tests {
     async fn test_post_sign_url() {
         let mut operations = MockAppStateOperations::new();
         operations
-            .expect_sign_url()
-            .returning(|_, _, _| Ok("https://beep.com/prefix/file_name".to_string()));
+            .expect_config()
+            .returning(|| config_with_caller("message_attachment"));
+        operations.expect_guards().returning(|| {
+            Arc::new(
+                GuardsBuilder::new()
+                    .add(
+                        "message_attachment",
+                        Guard::new(vec![FileType::Any]).with_max_ttl(3600),
+                    )
+                    .build(),
+            )
+        });
+        operations.expect_sign_url().returning(|_, _, _, _| {
+            Ok(IssuedUrl {
+                url: "https://beep.com/prefix/file_name".to_string(),
+                expires_at: 200,
+            })
+        });
         operations
             .expect_verify_parts()
             .returning(|_| Ok(Claims::default()));
@@ -111,11 +165,14 @@ mod tests {
 
         let payload = SignUrlRequest {
             action: AvailableActions::Put,
-            expires_in_ms: 100,
+            expires_in_secs: 100,
+            content_type: None,
+            max_content_length: None,
         };
         let response = TestServer::new(router)
             .expect("Axum test server creation failed")
-            .post("/prefix/file_name")
+            .post("/message_attachment/file_name")
+            .authorization_bearer(TEST_TOKEN)
             .json(&payload)
             .await;
 
@@ -0,0 +1,204 @@
+use axum::body::{Body, Bytes, to_bytes};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    app::AppStateOperations,
+    error::ApiError,
+    guards::{Guard, Location, media_type},
+    images::{
+        ImageError,
+        variants::{VariantFormat, VariantParams},
+    },
+    range::ByteRange,
+    s3::{FileObject, ListOptions, ObjectStream, S3Error},
+};
+
+/// Largest source image variants are rendered from, bigger ones are refused
+/// rather than buffered in memory.
+pub const MAX_SOURCE_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Fetches the variant of the image at `key`, rendering it and caching it in
+/// the bucket on the first request. Later requests are served from the cache.
+/// The image is held to the image limits of `guard` before being decoded.
+pub async fn variant_object<S>(
+    state: &S,
+    guard: &Guard,
+    bucket: &str,
+    key: &str,
+    params: VariantParams,
+    range: Option<ByteRange>,
+) -> Result<ObjectStream, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    params.validate().map_err(|e| e.into())?;
+
+    let metadata = state.head_object(bucket, key).await.map_err(|e| e.into())?;
+    let source_format = VariantFormat::from_source(&metadata.content_type)
+        .ok_or_else(|| ImageError::NotAnImage.into())?;
+    let variant_key = params.key(
+        key,
+        metadata.e_tag.as_deref().unwrap_or_default(),
+        source_format,
+    );
+    match state.get_object(bucket, &variant_key, range).await {
+        Ok(variant) => return Ok(variant),
+        Err(S3Error::ObjectNotFound(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let source = state
+        .get_object(bucket, key, None)
+        .await
+        .map_err(|e| e.into())?;
+    // The source may have been replaced since it was looked up
+    let variant_key = params.key(
+        key,
+        source.e_tag.as_deref().unwrap_or_default(),
+        source_format,
+    );
+    let data = to_bytes(source.data, MAX_SOURCE_LENGTH)
+        .await
+        .map_err(|_| ApiError::PayloadTooLarge("Image too large to be resized".to_string()))?;
+    guard
+        .check_image(&data, &media_type(&metadata.content_type))
+        .map_err(|e| e.into())?;
+
+    let (variant, format) =
+        tokio::task::spawn_blocking(move || params.render(&data, source_format))
+            .await
+            .map_err(|e| ApiError::InternalServerError(e.to_string()))?
+            .map_err(|e| e.into())?;
+    let content_length = variant.len() as u64;
+    state
+        .upload(
+            bucket,
+            &variant_key,
+            FileObject {
+                data: Body::from(variant),
+                content_type: format.content_type().to_string(),
+                content_length,
+            },
+        )
+        .await
+        .map_err(|e| e.into())?;
+
+    state
+        .get_object(bucket, &variant_key, range)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Deletes the variants cached for the image at `key`, whichever version of
+/// the image they were rendered from.
+pub async fn delete_variants<S>(state: &S, bucket: &str, key: &str) -> Result<(), ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let prefix = format!("{}/variants/", key);
+    let mut continuation_token = None;
+    loop {
+        let page = state
+            .list_objects(
+                bucket,
+                &prefix,
+                ListOptions {
+                    continuation_token,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| e.into())?;
+        for object in page.objects {
+            state
+                .delete_object(bucket, &object.key)
+                .await
+                .map_err(|e| e.into())?;
+        }
+        continuation_token = page.next_continuation_token;
+        if continuation_token.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+/// A variant rendered along with the upload of an image
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Derivative {
+    /// Path of the variant, `<prefix>/<file_name>/variants/<name>`
+    pub key: String,
+    /// Query string serving the variant from the public route
+    pub query: String,
+}
+
+pub struct RenderedDerivative {
+    params: VariantParams,
+    data: Vec<u8>,
+    format: VariantFormat,
+}
+
+/// Renders the derivatives of an uploaded image, which fails if it can't be decoded
+pub async fn render_derivatives(
+    data: Bytes,
+    source_format: VariantFormat,
+    derivatives: Vec<VariantParams>,
+) -> Result<Vec<RenderedDerivative>, ImageError> {
+    tokio::task::spawn_blocking(move || {
+        derivatives
+            .into_iter()
+            .map(|params| {
+                let (data, format) = params.render(&data, source_format)?;
+                Ok(RenderedDerivative {
+                    params,
+                    data,
+                    format,
+                })
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| ImageError::Encode(e.to_string()))?
+}
+
+/// Stores the derivatives of the image at `location` where its variants are
+/// cached, so that requesting them never renders them again. `path` is the
+/// `<prefix>/<file_name>` of the image, the derivatives are returned under it.
+/// `source_tag` is the ETag the image was stored with, the object at
+/// `location` may have been replaced since.
+pub async fn store_derivatives<S>(
+    state: &S,
+    location: &Location,
+    path: &str,
+    source_tag: &str,
+    source_format: VariantFormat,
+    derivatives: Vec<RenderedDerivative>,
+) -> Result<Vec<Derivative>, ApiError>
+where
+    S: AppStateOperations + Send + Sync + 'static,
+{
+    let mut stored = Vec::with_capacity(derivatives.len());
+    for derivative in derivatives {
+        let derivative_key = derivative
+            .params
+            .key(&location.key, source_tag, source_format);
+        let content_length = derivative.data.len() as u64;
+        state
+            .upload(
+                &location.bucket,
+                &derivative_key,
+                FileObject {
+                    data: Body::from(derivative.data),
+                    content_type: derivative.format.content_type().to_string(),
+                    content_length,
+                },
+            )
+            .await
+            .map_err(|e| e.into())?;
+        stored.push(Derivative {
+            key: derivative.params.key(path, source_tag, source_format),
+            query: derivative.params.query(),
+        });
+    }
+    Ok(stored)
+}
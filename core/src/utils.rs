@@ -4,10 +4,11 @@ pub struct RealTime {}
 
 #[automock]
 pub trait Time {
-    fn now(&self) -> u64;
+    /// Current Unix time, in seconds, the unit of every `expires` date
+    fn now_secs(&self) -> u64;
 }
 impl Time for RealTime {
-    fn now(&self) -> u64 {
+    fn now_secs(&self) -> u64 {
         chrono::Utc::now()
             .timestamp()
             .try_into()
@@ -25,7 +26,7 @@ pub mod tests {
 
     pub fn get_time() -> MockTime {
         let mut mock = MockTime::new();
-        mock.expect_now().returning(|| 100);
+        mock.expect_now_secs().returning(|| 100);
         mock
     }
 }
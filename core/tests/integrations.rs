@@ -1,8 +1,16 @@
 use std::sync::Arc;
 
-use content_core::{config::Config, error::CoreError, utils::RealTime};
+use clap::ValueEnum;
+use content_core::{
+    auth::Callers,
+    config::{Config, MaxTtls, S3Addressing, S3Credentials, SigningKey, StorageBackend},
+    error::CoreError,
+    utils::RealTime,
+};
 use dotenv::dotenv;
 
+const CALLER_TOKEN: &str = "integration_tests_caller_token";
+
 fn bootstrap_config() -> Config {
     dotenv().ok();
     Config {
@@ -15,11 +23,39 @@ fn bootstrap_config() -> Config {
             .split(',')
             .map(|s| s.to_string())
             .collect(),
+        storage_backend: std::env::var("STORAGE_BACKEND")
+            .ok()
+            .and_then(|backend| StorageBackend::from_str(&backend, true).ok())
+            .unwrap_or_default(),
+        storage_path: std::env::var("STORAGE_PATH")
+            .unwrap_or("data".to_string())
+            .into(),
         s3_endpoint: std::env::var("S3_ENDPOINT").unwrap_or("http://0.0.0.0:3900/".to_string()),
+        s3_region: std::env::var("S3_REGION").unwrap_or("garage".to_string()),
+        s3_addressing: S3Addressing::Path,
+        s3_ca_bundle: std::env::var("S3_CA_BUNDLE").ok().map(Into::into),
+        s3_connect_timeout_ms: None,
+        s3_read_timeout_ms: None,
+        s3_max_attempts: 3,
+        s3_initial_backoff_ms: 1000,
+        s3_credentials: S3Credentials::Static,
         key_id: std::env::var("TEST_KEY_ID").unwrap_or("beep_admin".to_string()),
         secret_key: std::env::var("TEST_SECRET_KEY").unwrap_or("beep_admin".to_string()),
         s3_bucket: std::env::var("S3_BUCKET").unwrap_or("test".to_string()),
         base_url: std::env::var("BASE_URL").unwrap_or("https://beep.com".to_string()),
+        signing_key: SigningKey::parse(
+            &std::env::var("SIGNING_KEY").unwrap_or("dGVzdF9zaWduaW5nX2tleV9iZWVw".to_string()),
+        )
+        .expect("Invalid signing key"),
+        signing_key_id: "v1".to_string(),
+        verification_keys: vec![],
+        callers: Callers::parse(&format!(
+            r#"[{{"name": "integration", "token": "{}", "prefixes": ["profile_picture", "message_attachment", "server_banner", "server_picture"], "actions": ["Put", "Get"]}}]"#,
+            CALLER_TOKEN
+        ))
+        .expect("Invalid callers"),
+        max_ttls: MaxTtls::default(),
+        policy_file: None,
     }
 }
 
@@ -44,9 +80,10 @@ async fn integration_full_flow(endpoint: &str, payload: &[u8], mime: &str) {
     let client = reqwest::Client::new();
     let response = client
         .post(format!("http://localhost:3004/{}", endpoint))
+        .bearer_auth(CALLER_TOKEN)
         .json(&serde_json::json!({
             "action": "Put",
-            "expires_in_ms": 1000
+            "expires_in_secs": 60
         }))
         .send()
         .await
@@ -71,9 +108,10 @@ async fn integration_full_flow(endpoint: &str, payload: &[u8], mime: &str) {
 
     let response = client
         .post(format!("http://localhost:3004/{}", endpoint))
+        .bearer_auth(CALLER_TOKEN)
         .json(&serde_json::json!({
             "action": "Get",
-            "expires_in_ms": 1000
+            "expires_in_secs": 60
         }))
         .send()
         .await
@@ -0,0 +1,293 @@
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use content_core::{
+    Backend, Memory, Operation,
+    auth::Callers,
+    config::{Config, MaxTtls, S3Addressing, S3Credentials, SigningKey, StorageBackend},
+    utils::RealTime,
+};
+use image::{ImageFormat, Rgb, RgbImage};
+use reqwest::{Client, StatusCode};
+use tokio::task::JoinHandle;
+
+const CALLER_TOKEN: &str = "memory_tests_caller_token";
+
+fn bootstrap_config(port: u16) -> Config {
+    Config {
+        port,
+        origins: vec!["beep_test.com".to_string()],
+        storage_backend: StorageBackend::Memory,
+        storage_path: "data".into(),
+        s3_endpoint: "http://0.0.0.0:3900/".to_string(),
+        s3_region: "garage".to_string(),
+        s3_addressing: S3Addressing::Path,
+        s3_ca_bundle: None,
+        s3_connect_timeout_ms: None,
+        s3_read_timeout_ms: None,
+        s3_max_attempts: 3,
+        s3_initial_backoff_ms: 1000,
+        s3_credentials: S3Credentials::Static,
+        key_id: "unused".to_string(),
+        secret_key: "unused".to_string(),
+        s3_bucket: "test".to_string(),
+        base_url: format!("http://localhost:{}", port),
+        signing_key: SigningKey::parse("dGVzdF9zaWduaW5nX2tleV9iZWVw")
+            .expect("Invalid signing key"),
+        signing_key_id: "v1".to_string(),
+        verification_keys: vec![],
+        callers: Callers::parse(&format!(
+            r#"[{{"name": "memory", "token": "{}", "prefixes": ["profile_picture", "message_attachment"], "actions": ["Put", "Get", "List", "CreateMultipartUpload"]}}]"#,
+            CALLER_TOKEN
+        ))
+        .expect("Invalid callers"),
+        max_ttls: MaxTtls::default(),
+        policy_file: None,
+    }
+}
+
+/// Starts the server on `memory`, and waits for it to answer
+async fn launch(port: u16, memory: Arc<Memory>) -> JoinHandle<()> {
+    let config = Arc::new(bootstrap_config(port));
+    let handle = tokio::spawn(async move {
+        content_core::app_with_storage(config, RealTime {}, Backend::Memory(memory))
+            .await
+            .expect("Server failed");
+    });
+
+    for _ in 0..50 {
+        if reqwest::get(format!("http://localhost:{}/health", port))
+            .await
+            .is_ok()
+        {
+            return handle;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Server didn't start");
+}
+
+fn png() -> Vec<u8> {
+    let mut png = Cursor::new(Vec::new());
+    RgbImage::from_pixel(16, 16, Rgb([200, 40, 40]))
+        .write_to(&mut png, ImageFormat::Png)
+        .expect("Encoding failed");
+    png.into_inner()
+}
+
+async fn health(port: u16) -> serde_json::Value {
+    let response = reqwest::get(format!("http://localhost:{}/health", port))
+        .await
+        .expect("Failed to make request");
+    assert!(response.status().is_success());
+    response.json().await.expect("Invalid health check")
+}
+
+async fn sign(client: &Client, port: u16, path: &str, action: &str) -> String {
+    let response = client
+        .post(format!("http://localhost:{}/{}", port, path))
+        .bearer_auth(CALLER_TOKEN)
+        .json(&serde_json::json!({
+            "action": action,
+            "expires_in_secs": 60
+        }))
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert!(response.status().is_success());
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Invalid signed url");
+    body["url"].as_str().expect("No url").to_string()
+}
+
+#[tokio::test]
+async fn test_memory_full_flow() {
+    let port = 3005;
+    let memory = Arc::new(Memory::new());
+    let handle = launch(port, memory.clone()).await;
+    let client = Client::new();
+    let payload = png();
+
+    assert_eq!(health(port).await["s3"], true);
+
+    let url = sign(&client, port, "profile_picture/me.png", "Put").await;
+    let response = client
+        .put(url)
+        .header("Content-Type", "image/png")
+        .body(payload.clone())
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert!(response.status().is_success());
+
+    let url = sign(&client, port, "profile_picture/me.png", "Get").await;
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert!(response.status().is_success());
+    assert_eq!(response.headers()["content-type"], "image/png");
+    let stored = response.bytes().await.expect("Failed to get response");
+    image::load_from_memory_with_format(&stored, ImageFormat::Png).expect("Invalid PNG");
+
+    let response = client
+        .get(format!(
+            "http://localhost:{}/public/profile_picture/me.png",
+            port
+        ))
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.bytes().await.expect("Failed to get response"),
+        stored
+    );
+
+    let response = client
+        .get(format!(
+            "http://localhost:{}/public/message_attachment/me.png",
+            port
+        ))
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .get(format!("http://localhost:{}/list/profile_picture", port))
+        .bearer_auth(CALLER_TOKEN)
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert!(response.status().is_success());
+    let listing = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Invalid listing");
+    assert_eq!(listing["objects"][0]["file_name"], "me.png");
+    assert_eq!(listing["objects"][0]["content_type"], "image/png");
+    assert_eq!(listing["objects"][0]["size"], stored.len());
+    assert_eq!(listing["next_continuation_token"], serde_json::Value::Null);
+
+    handle.abort();
+}
+
+#[tokio::test]
+async fn test_memory_injected_failures() {
+    let port = 3006;
+    let memory = Arc::new(Memory::new());
+    let handle = launch(port, memory.clone()).await;
+    let client = Client::new();
+
+    memory.fail(Operation::ShowBuckets);
+    assert_eq!(health(port).await["s3"], false);
+    memory.recover(Operation::ShowBuckets);
+    assert_eq!(health(port).await["s3"], true);
+
+    memory.fail(Operation::PutObject);
+    let url = sign(&client, port, "message_attachment/notes.txt", "Put").await;
+    let response = client
+        .put(url.clone())
+        .header("Content-Type", "text/plain")
+        .body("hello")
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    memory.recover(Operation::PutObject);
+    memory.set_latency(Duration::from_millis(200));
+    let response = client
+        .put(url)
+        .header("Content-Type", "text/plain")
+        .body("hello")
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert!(response.status().is_success());
+
+    let response = client
+        .get(sign(&client, port, "message_attachment/notes.txt", "Get").await)
+        .timeout(Duration::from_millis(50))
+        .send()
+        .await;
+    assert!(response.is_err_and(|e| e.is_timeout()));
+
+    handle.abort();
+}
+
+/// Uploads `data` to `path` in a single part, returning the completion response
+async fn upload_multipart(
+    client: &Client,
+    port: u16,
+    path: &str,
+    data: &'static str,
+) -> reqwest::Response {
+    let upload = client
+        .post(sign(client, port, path, "CreateMultipartUpload").await)
+        .json(&serde_json::json!({ "content_type": "text/plain", "parts": 1 }))
+        .send()
+        .await
+        .expect("Failed to make request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Invalid upload");
+    let part = client
+        .put(upload["parts"][0]["url"].as_str().expect("No part url"))
+        .header("Content-Length", data.len())
+        .body(data)
+        .send()
+        .await
+        .expect("Failed to make request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Invalid part");
+    client
+        .post(upload["complete_url"].as_str().expect("No complete url"))
+        .json(&serde_json::json!({ "parts": [part] }))
+        .send()
+        .await
+        .expect("Failed to make request")
+}
+
+#[tokio::test]
+async fn test_memory_multipart() {
+    let port = 3007;
+    let memory = Arc::new(Memory::new());
+    let handle = launch(port, memory.clone()).await;
+    let client = Client::new();
+    let path = "message_attachment/notes.txt";
+
+    let response = client
+        .put(sign(&client, port, path, "Put").await)
+        .header("Content-Type", "text/plain")
+        .body("hello")
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert!(response.status().is_success());
+
+    // A refused upload leaves the object at its key untouched
+    let response = upload_multipart(&client, port, path, "<html><script></script></html>").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client
+        .get(sign(&client, port, path, "Get").await)
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert_eq!(response.text().await.expect("Invalid body"), "hello");
+
+    let response = upload_multipart(&client, port, path, "hello world").await;
+    assert!(response.status().is_success());
+    let response = client
+        .get(sign(&client, port, path, "Get").await)
+        .send()
+        .await
+        .expect("Failed to make request");
+    assert_eq!(response.text().await.expect("Invalid body"), "hello world");
+
+    handle.abort();
+}